@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::Cursor;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use thiserror::Error;
+use tiny_http::{Header, Method, Request, Response, Server};
+use crate::config_reader::Config;
+use crate::billing::Biller;
+use crate::db::{DBEntry, DBError, Db};
+use crate::http::{self, encode};
+use crate::{csv_reader, ebill, pdf_gen, track, validate};
+
+static OPENAPI: &str = include_str!("openapi.json");
+pub static TOKEN_VAR: &str = "RUSTBILL_API_TOKEN";
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("Set {TOKEN_VAR} (in the environment or .env) to start the API")]
+    MissingToken,
+    #[error("Could not listen on {addr}: {message}")]
+    Bind { addr: String, message: String },
+    #[error("Missing or wrong bearer token")]
+    Unauthorized,
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    NotFound(String),
+}
+
+type Params = HashMap<String, String>;
+type ApiResult = Result<Response<Cursor<Vec<u8>>>, Box<dyn Error>>;
+
+#[derive(Debug, Deserialize)]
+struct NewEntry {
+    company: String,
+    date: NaiveDate,
+    minutes: i32,
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct NewInvoices {
+    /// All companies of the month if not given
+    company: Option<String>,
+    /// YYYY-MM, the current month if not given
+    month: Option<String>,
+    #[serde(default)]
+    strict: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusUpdate {
+    status: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Invoice {
+    billnr: String,
+    company: String,
+    /// Only for bills of a single project
+    #[serde(skip_serializing_if = "Option::is_none")]
+    project: Option<String>,
+    date: NaiveDate,
+    /// First and last day of the billed period
+    period_start: NaiveDate,
+    period_end: NaiveDate,
+    amount: f64,
+    amount_str: String,
+    status: String,
+    currency: String,
+    exchange_rate: f64,
+    pdf_url: String,
+    xml_url: String,
+}
+
+impl Invoice {
+    fn from_db(bill: DBEntry) -> Option<Self> {
+        let date = bill.billdate()?;
+        let (period_start, period_end) = bill.period()?;
+        let url = format!("/api/invoices/{}", encode(&bill.billnr));
+        Some(Invoice {
+            pdf_url: format!("{}/pdf", url),
+            xml_url: format!("{}/xml", url),
+            billnr: bill.billnr,
+            company: bill.company,
+            project: (!bill.project.is_empty()).then_some(bill.project),
+            date,
+            period_start,
+            period_end,
+            amount: bill.amount,
+            amount_str: bill.amount_str,
+            status: bill.status,
+            currency: bill.currency,
+            exchange_rate: bill.exchange_rate,
+        })
+    }
+}
+
+struct Api {
+    biller: Biller,
+    db: Db,
+    token: String,
+}
+
+fn decode(text: &str) -> String {
+    form_urlencoded::parse(format!("x={}", text).as_bytes()).map(|(_, value)| value.into_owned()).next().unwrap_or_default()
+}
+
+fn bad_request(message: impl Into<String>) -> Box<dyn Error> {
+    Box::new(ApiError::BadRequest(message.into()))
+}
+
+fn not_found(message: impl Into<String>) -> Box<dyn Error> {
+    Box::new(ApiError::NotFound(message.into()))
+}
+
+fn json_response(status: u16, body: &impl Serialize) -> ApiResult {
+    let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+    Ok(Response::from_data(serde_json::to_vec_pretty(body)?).with_status_code(status).with_header(header))
+}
+
+fn file_response(data: Vec<u8>, content_type: &str, filename: &str) -> ApiResult {
+    let content_type = Header::from_bytes("Content-Type", content_type).unwrap();
+    let disposition = Header::from_bytes("Content-Disposition", format!("attachment; filename=\"{}\"", filename)).unwrap();
+    Ok(Response::from_data(data).with_header(content_type).with_header(disposition))
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(request: &mut Request) -> Result<T, Box<dyn Error>> {
+    serde_json::from_reader(request.as_reader()).map_err(|e| bad_request(format!("Invalid request body: {}", e)))
+}
+
+/// Compares in constant time, so the token can not be guessed from response times
+fn token_matches(given: &str, token: &str) -> bool {
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+impl Api {
+    fn authorized(&self, request: &Request) -> bool {
+        request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv("Authorization"))
+            .and_then(|header| header.value.as_str().strip_prefix("Bearer "))
+            .is_some_and(|given| token_matches(given.trim(), &self.token))
+    }
+
+    fn known_company(&self, company: &str, billdate: &NaiveDate) -> Result<(), Box<dyn Error>> {
+        http::check_name("company", company)?;
+        let companies = self.biller.companies(billdate)?;
+        if !companies.iter().any(|known| known == company) && !self.biller.config().companies.contains_key(company) {
+            return Err(not_found(format!("Unknown company {:?}", company)))
+        }
+        Ok(())
+    }
+
+    fn bill(&self, billnr: &str) -> Result<DBEntry, Box<dyn Error>> {
+        self.db.get_bill(billnr)?.ok_or_else(|| not_found(format!("No invoice {:?}", billnr)))
+    }
+
+    fn customers(&self) -> ApiResult {
+        let mut customers = Vec::new();
+        for (name, company_config) in &self.biller.config().companies {
+            let mut customer = serde_json::to_value(company_config)?;
+            customer["name"] = json!(name);
+            customers.push(customer);
+        }
+        json_response(200, &customers)
+    }
+
+    fn entries(&self, params: &Params) -> ApiResult {
+        let company = params.get("company").ok_or_else(|| bad_request("Missing parameter \"company\""))?;
+        let billdate = http::billdate_of_month(params.get("month").map(String::as_str))?;
+        self.known_company(company, &billdate)?;
+        let period = self.biller.period(company, &billdate)?;
+
+        let records = self.biller.entries(company, &billdate)?;
+        let issues = validate::validate_records(&records, &period, self.biller.config().bill_config.calendar);
+        let minutes_total = csv_reader::extract_minutes_total(&records)?;
+
+        let entries: Vec<JsonValue> = records
+            .iter()
+            .map(|record| json!({ "date": record.date, "minutes": record.minutes, "description": record.description }))
+            .collect();
+        let issues: Vec<JsonValue> = issues
+            .iter()
+            .map(|issue| json!({
+                "severity": format!("{:?}", issue.severity).to_lowercase(),
+                "line": issue.line,
+                "message": issue.message,
+            }))
+            .collect();
+
+        json_response(200, &json!({
+            "company": company,
+            "month": billdate.format("%Y-%m").to_string(),
+            "minutes_total": minutes_total,
+            "entries": entries,
+            "issues": issues,
+        }))
+    }
+
+    fn add_entry(&self, request: &mut Request) -> ApiResult {
+        let entry: NewEntry = read_json(request)?;
+        http::check_name("company", &entry.company)?;
+        if entry.minutes <= 0 {
+            return Err(bad_request(format!("Minutes need to be positive, got {}", entry.minutes)))
+        }
+        track::log(self.biller.config(), &entry.company, entry.minutes, &entry.description, &entry.date)?;
+        json_response(201, &json!({
+            "company": entry.company,
+            "date": entry.date,
+            "minutes": entry.minutes,
+            "description": entry.description,
+        }))
+    }
+
+    fn invoices(&self, params: &Params) -> ApiResult {
+        let invoices: Vec<Invoice> = self.db.get_all_bills()?
+            .into_iter()
+            .filter(|bill| params.get("company").is_none_or(|company| *company == bill.company))
+            .filter(|bill| params.get("status").is_none_or(|status| *status == bill.status))
+            .filter_map(Invoice::from_db)
+            .collect();
+        json_response(200, &invoices)
+    }
+
+    fn generate(&self, request: &mut Request) -> ApiResult {
+        let body: NewInvoices = read_json(request)?;
+        let billdate = http::billdate_of_month(body.month.as_deref())?;
+
+        let companies = match &body.company {
+            Some(company) => {
+                self.known_company(company, &billdate)?;
+                vec![company.clone()]
+            },
+            None => self.biller.companies(&billdate)?,
+        };
+
+        let mut results = Vec::new();
+        for company in companies {
+            let result = self.biller.render(&company, &billdate, body.strict)
+                .and_then(|bills| bills.into_iter().map(|bill| self.biller.save(&bill, &billdate).map(|_| bill.billnr)).collect::<Result<Vec<_>, _>>());
+            match result {
+                Ok(billnrs) if billnrs.is_empty() => results.push(json!({ "company": company, "skipped": "no entries" })),
+                Ok(billnrs) => {
+                    for billnr in billnrs {
+                        results.push(serde_json::to_value(self.bill(&billnr).map(Invoice::from_db)?)?);
+                    }
+                },
+                // A single requested company fails the request, otherwise the others are still billed
+                Err(e) if body.company.is_some() => return Err(e),
+                Err(e) => results.push(json!({ "company": company, "error": e.to_string() })),
+            }
+        }
+        json_response(201, &results)
+    }
+
+    fn invoice(&self, billnr: &str) -> ApiResult {
+        json_response(200, &Invoice::from_db(self.bill(billnr)?))
+    }
+
+    fn invoice_file(&self, billnr: &str, kind: &str) -> ApiResult {
+        let bill = self.bill(billnr)?;
+        let (Some(billdate), Some(period)) = (bill.billdate(), bill.period()) else {
+            return Err(not_found("Invalid date in the database"))
+        };
+        let project = (!bill.project.is_empty()).then_some(bill.project.as_str());
+        let filename = pdf_gen::pdf_filename(billdate, &bill.company, project, &period);
+        let data = fs::read(self.biller.config().paths.bills_dir(&billdate).join(&filename))
+            .map_err(|_| not_found(format!("The pdf of invoice {:?} is missing", billnr)))?;
+
+        match kind {
+            "pdf" => file_response(data, "application/pdf", &filename),
+            _ => {
+                let xml = ebill::extract_xml_from_pdf(&data)?.ok_or_else(|| not_found(format!("Invoice {:?} has no embedded xml", billnr)))?;
+                file_response(xml.into_bytes(), "application/xml", &filename.replace(".pdf", ".xml"))
+            },
+        }
+    }
+
+    fn update_status(&self, billnr: &str, request: &mut Request) -> ApiResult {
+        let update: StatusUpdate = read_json(request)?;
+        self.bill(billnr)?;
+        match self.db.set_status(billnr, &update.status) {
+            Ok(_) => self.invoice(billnr),
+            Err(DBError::InvalidStatus(status)) => Err(bad_request(DBError::InvalidStatus(status).to_string())),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    fn handle(&self, request: &mut Request) -> ApiResult {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let params: Params = form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+        let segments: Vec<String> = path.trim_matches('/').split('/').map(decode).collect();
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        let method = request.method().clone();
+
+        if segments == ["api", "openapi.json"] {
+            let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+            return Ok(Response::from_data(OPENAPI).with_header(header))
+        }
+        if !self.authorized(request) {
+            return Err(Box::new(ApiError::Unauthorized))
+        }
+
+        match (method, segments.as_slice()) {
+            (Method::Get, ["api", "customers"]) => self.customers(),
+            (Method::Get, ["api", "entries"]) => self.entries(&params),
+            (Method::Post, ["api", "entries"]) => self.add_entry(request),
+            (Method::Get, ["api", "invoices"]) => self.invoices(&params),
+            (Method::Post, ["api", "invoices"]) => self.generate(request),
+            (Method::Get, ["api", "invoices", billnr]) => self.invoice(billnr),
+            (Method::Patch, ["api", "invoices", billnr]) => self.update_status(billnr, request),
+            (Method::Get, ["api", "invoices", billnr, kind @ ("pdf" | "xml")]) => self.invoice_file(billnr, kind),
+            _ => Err(not_found(format!("No route for {}", path))),
+        }
+    }
+}
+
+/// Serves the JSON API on `addr` until the process is stopped. Requests are handled one at a time
+pub fn serve(config: Config, addr: &str) -> Result<(), Box<dyn Error>> {
+    dotenv::dotenv().ok();
+    let token = std::env::var(TOKEN_VAR).ok().filter(|token| !token.is_empty()).ok_or(ApiError::MissingToken)?;
+    let db = Db::open(&config.paths.db)?;
+    let biller = Biller::new(config)?;
+
+    let server = Server::http(addr).map_err(|e| ApiError::Bind { addr: addr.to_string(), message: e.to_string() })?;
+    println!("Serving the API on http://{}/api, see /api/openapi.json", addr);
+
+    let api = Api { biller, db, token };
+    for mut request in server.incoming_requests() {
+        let response = api.handle(&mut request).unwrap_or_else(|e| {
+            eprintln!("{} {}: {}", request.method(), request.url(), e);
+            let status = match e.downcast_ref::<ApiError>() {
+                Some(ApiError::Unauthorized) => 401,
+                Some(ApiError::NotFound(_)) => 404,
+                Some(_) => 400,
+                None if e.is::<track::TrackError>() || e.is::<validate::ValidationError>() || e.is::<http::ParamError>() => 400,
+                None => 500,
+            };
+            let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+            Response::from_data(json!({ "error": e.to_string() }).to_string()).with_status_code(status).with_header(header)
+        });
+        if let Err(e) = request.respond(response) {
+            eprintln!("Could not send the response: {}", e);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,626 @@
+use std::cell::Cell;
+use std::error::Error;
+use chrono::{Days, NaiveDate};
+use typst::foundations::Value;
+use rusty_money::iso::Currency;
+use rusty_money::FormattableCurrency;
+use crate::calculate::{self, Amounts, Scope};
+use crate::exchange::{self, Rate};
+use crate::config_reader::{self, CompanyConfig, Config, PaymentTerms, ProjectBilling, ServicePeriod};
+use crate::csv_reader::Record;
+use crate::budget::{self, BudgetStatus};
+use crate::db::{Db, Usage};
+use crate::{date_utils, ebill, mail, pdf_gen, qrcode, time_source, validate};
+
+pub type BillResult<T> = Result<T, Box<dyn Error>>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BillingError {
+    #[error("{company}: {from} to {to} overlaps bill {billnr} ({billed_from} to {billed_to}). Cancel that bill first or bill another period")]
+    OverlappingBill { company: String, from: NaiveDate, to: NaiveDate, billnr: String, billed_from: NaiveDate, billed_to: NaiveDate },
+}
+
+#[derive(Debug)]
+pub struct Bill {
+    pub company: String,
+    /// The key of the project for bills of a single project
+    pub project: Option<String>,
+    /// First and last day of the billed period
+    pub period: (NaiveDate, NaiveDate),
+    pub duedate: NaiveDate,
+    pub billnr: String,
+    pub billnr_int: i32,
+    pub amounts: Amounts,
+    pub amount_str: String,
+    /// ISO code of the currency
+    pub currency: String,
+    /// Units of the currency per euro, 1 for euros
+    pub exchange_rate: f64,
+    /// The PDF with the e-bill XML attached
+    pub pdf: Vec<u8>,
+}
+
+/// A bill of a run before it is rendered
+struct Draft {
+    project: Option<String>,
+    records: Vec<Record>,
+    amounts: Amounts,
+}
+
+/// Everything the render stages need to know about one bill
+#[derive(Debug)]
+pub struct Invoice<'a> {
+    pub company: &'a str,
+    pub company_config: &'a CompanyConfig,
+    /// The key of the project for bills of a single project
+    pub project: Option<&'a str>,
+    pub billnr: &'a str,
+    pub billdate: NaiveDate,
+    pub duedate: NaiveDate,
+    pub payment_terms: PaymentTerms,
+    pub period: (NaiveDate, NaiveDate),
+    /// The Leistungszeitraum, within or equal to `period` unless set explicitly
+    pub service_period: (NaiveDate, NaiveDate),
+    pub records: &'a [Record],
+    pub amounts: &'a Amounts,
+    pub amount_str: &'a str,
+    pub currency: &'static Currency,
+    pub rate: Rate,
+    pub budgets: &'a [BudgetStatus],
+}
+
+/// Collects the time entries of a company
+pub trait Collect {
+    /// The companies that may have something to bill within `period`
+    fn companies(&self, config: &Config, period: &(NaiveDate, NaiveDate)) -> BillResult<Vec<String>>;
+    fn entries(&self, config: &Config, company: &str, period: &(NaiveDate, NaiveDate)) -> BillResult<Vec<Record>>;
+}
+
+/// Computes the amounts from the entries
+pub trait Calculate {
+    fn amounts(&self, config: &Config, company_config: &CompanyConfig, records: &[Record], scope: &Scope) -> BillResult<Amounts>;
+}
+
+/// Renders the bill to a PDF
+pub trait Render {
+    fn render(&self, config: &Config, invoice: &Invoice) -> BillResult<Vec<u8>>;
+}
+
+/// Builds the e-invoice and attaches it to the PDF
+pub trait EInvoice {
+    fn attach(&self, config: &Config, invoice: &Invoice, pdf: &[u8]) -> BillResult<Vec<u8>>;
+}
+
+/// Hands out bill numbers and keeps the bills
+pub trait Store {
+    /// The number of an already stored bill of the company (or of one of its projects) for that period, or the next free
+    /// one. `taken` are the numbers handed out to the other bills of the same run, which are not saved yet
+    fn billnr(&self, config: &Config, company: &str, project: Option<&str>, billdate: &NaiveDate, period: &(NaiveDate, NaiveDate), taken: &[i32]) -> BillResult<(String, i32)>;
+    /// Returns the file name the bill was saved as
+    fn save(&self, config: &Config, bill: &Bill, billdate: &NaiveDate) -> BillResult<String>;
+    /// What the stored bills of the company (or the hours of one of its projects) dated from `from` up to before `before` used,
+    /// for contingents and budgets
+    fn usage(&self, _config: &Config, _company: &str, _project: Option<&str>, _from: Option<&NaiveDate>, _before: &NaiveDate) -> BillResult<Usage> {
+        Ok(Usage::default())
+    }
+    /// Stored bills of the company (or of one of its projects) that are not cancelled and overlap `period` without covering
+    /// exactly it, with their bill number and period
+    fn overlapping(&self, _config: &Config, _company: &str, _project: Option<&str>, _period: &(NaiveDate, NaiveDate)) -> BillResult<Vec<(String, (NaiveDate, NaiveDate))>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Sends the saved bill to the company
+pub trait Deliver {
+    fn deliver(&self, config: &Config, bill: &Bill, filename: &str, billdate: &NaiveDate) -> BillResult<()>;
+}
+
+/// The configured time sources (csv, ics, json or sqlite)
+pub struct TimeSources;
+
+impl Collect for TimeSources {
+    fn companies(&self, config: &Config, period: &(NaiveDate, NaiveDate)) -> BillResult<Vec<String>> {
+        let mut companies = time_source::find_all_companies(config, period)?;
+        // Fixed items are billed without logged time
+        for (company, company_config) in &config.companies {
+            if (!company_config.recurring.is_empty() || !company_config.contingents.is_empty()) && !companies.contains(company) {
+                companies.push(company.clone());
+            }
+        }
+        Ok(companies)
+    }
+
+    fn entries(&self, config: &Config, company: &str, period: &(NaiveDate, NaiveDate)) -> BillResult<Vec<Record>> {
+        Ok(time_source::entries_in(config, company, period)?)
+    }
+}
+
+/// The hourly fee or rate card of the company applied to the hours worked, plus VAT
+pub struct HourlyFee;
+
+impl Calculate for HourlyFee {
+    fn amounts(&self, _config: &Config, company_config: &CompanyConfig, records: &[Record], scope: &Scope) -> BillResult<Amounts> {
+        Ok(calculate::calculate_amounts(records, company_config, scope)?)
+    }
+}
+
+/// The typst templates next to the config
+pub struct TypstPdf {
+    config_value: Value,
+}
+
+impl TypstPdf {
+    pub fn new(config: &Config) -> BillResult<Self> {
+        Ok(TypstPdf { config_value: pdf_gen::config_to_value(config)? })
+    }
+}
+
+impl Render for TypstPdf {
+    fn render(&self, config: &Config, invoice: &Invoice) -> BillResult<Vec<u8>> {
+        // The EPC (GiroCode) QR code only exists for euros
+        let qrcode = match invoice.currency.code() {
+            "EUR" => qrcode::create_qrcode(&config.bank_config, invoice.amount_str, &invoice.billdate, invoice.billnr, &config.bill_config.color)?,
+            _ => String::new(),
+        };
+        let (start, end) = invoice.period;
+
+        let locale = calculate::money_locale(invoice.currency, config.bill_config.number_format.as_deref())?;
+        let money = |amount: f64| calculate::format_money(amount, invoice.currency, locale);
+        let (vat_eur_str, exchange_rate, exchange_rate_date) = match invoice.currency.code() {
+            "EUR" => (String::new(), String::new(), String::new()),
+            _ => (
+                calculate::format_money(invoice.amounts.vat / invoice.rate.rate, calculate::find_currency("EUR")?, locale),
+                format!("{:.4}", invoice.rate.rate),
+                invoice.rate.date.format("%d.%m.%Y").to_string(),
+            ),
+        };
+
+        let project = invoice.project.and_then(|key| invoice.company_config.projects.get(key));
+        let project_field = |field: fn(&config_reader::ProjectConfig) -> &Option<String>| project.and_then(|project| field(project).clone()).unwrap_or_default();
+
+        let skonto = invoice.amounts.skonto;
+
+        let pdf_content = pdf_gen::Content {
+            company: invoice.company.to_string(),
+            billnr: invoice.billnr.to_string(),
+            date: invoice.billdate.format("%d.%m.%Y").to_string(),
+            due: invoice.duedate.format("%d.%m.%Y").to_string(),
+            payment_terms: invoice.payment_terms.describe(),
+            service_period: pdf_gen::format_period(&invoice.service_period),
+            qrcode,
+            config: self.config_value.clone(),
+            entries: pdf_gen::build_entries(invoice.records, invoice.amounts, &invoice.period, money),
+            project: invoice.project.map(|key| invoice.company_config.project_name(key).to_string()).unwrap_or_default(),
+            purchase_order: project_field(|project| &project.purchase_order),
+            cost_center: project_field(|project| &project.cost_center),
+            lines: pdf_gen::build_lines(invoice.amounts, invoice.company_config, &config.bill_config.bill_item, money),
+            sections: pdf_gen::build_sections(invoice.amounts, invoice.company_config, &config.bill_config.bill_item, money),
+            budgets: pdf_gen::build_budgets(invoice.budgets, money),
+            items: pdf_gen::build_items(invoice.amounts, money),
+            adjustments: pdf_gen::build_adjustments(invoice.amounts, money),
+            days_in_period: (end - start).num_days() + 1,
+            minutes_total: invoice.amounts.entries.iter().map(|entry| entry.minutes as i64).sum(),
+            hours_total: invoice.amounts.hours_total,
+            amount_net: invoice.amounts.net,
+            vat: invoice.amounts.vat,
+            amount_total: invoice.amounts.total,
+            currency: invoice.currency.code().to_string(),
+            hourly_fee_str: money(invoice.amounts.hourly_fee),
+            amount_net_str: money(invoice.amounts.net),
+            amount_hours_str: money(invoice.amounts.entries.iter().map(|entry| entry.amount).sum::<f64>() - invoice.amounts.cap_deduction),
+            vat_str: money(invoice.amounts.vat),
+            amount_total_str: money(invoice.amounts.total),
+            surcharge_str: if invoice.amounts.surcharge > 0.0 { money(invoice.amounts.surcharge) } else { String::new() },
+            weekend_surcharge: invoice.company_config.rate_card.as_ref().map(|rate_card| rate_card.weekend_surcharge).unwrap_or(0.0),
+            cap_deduction_str: if invoice.amounts.cap_deduction > 0.0 { money(-invoice.amounts.cap_deduction) } else { String::new() },
+            contingent_hours: invoice.amounts.contingent_minutes as f64 / 60.0,
+            contingent_str: if invoice.amounts.contingent_minutes > 0 { money(-invoice.amounts.contingent_deduction) } else { String::new() },
+            contingent_remaining: if invoice.company_config.contingents.is_empty() {
+                String::new()
+            } else {
+                format!("{}", invoice.amounts.contingent_remaining as f64 / 60.0)
+            },
+            skonto_percent: skonto.map(|skonto| skonto.percent.to_string()).unwrap_or_default(),
+            skonto_date: skonto.map(|skonto| (invoice.billdate + Days::new(skonto.days.into())).format("%d.%m.%Y").to_string()).unwrap_or_default(),
+            skonto_total_str: skonto.map(|skonto| money(skonto.total)).unwrap_or_default(),
+            vat_eur_str,
+            exchange_rate,
+            exchange_rate_date,
+        };
+
+        let pdf_data = pdf_gen::generate_pdf(pdf_content, &config.paths.root)?;
+        println!("Generated pdf");
+
+        // let signed_pdf_data = sign::sign_pdf(pdf_data)?;
+
+        Ok(pdf_data)
+    }
+}
+
+/// The UBL (XRechnung) xml, embedded into the PDF
+pub struct UblXml;
+
+impl EInvoice for UblXml {
+    fn attach(&self, config: &Config, invoice: &Invoice, pdf: &[u8]) -> BillResult<Vec<u8>> {
+        let xml = ebill::create_ebill_xml(invoice.billnr, invoice.amounts, invoice.billdate, invoice.duedate, &invoice.service_period, &config.bill_config, invoice.company_config, invoice.project, &invoice.payment_terms.describe(), &config.bank_config, invoice.currency.code(), invoice.rate.rate)?;
+        Ok(ebill::add_xml_to_pdf(pdf, xml)?)
+    }
+}
+
+/// PDFs in the bills directory, bill numbers in the database of `paths.db`
+pub struct Database {
+    db: Db,
+    ready: Cell<bool>,
+}
+
+impl Database {
+    /// The database is created on first use
+    pub fn new(config: &Config) -> Self {
+        Database { db: Db::new(&config.paths.db), ready: Cell::new(false) }
+    }
+
+    fn db(&self) -> BillResult<&Db> {
+        if !self.ready.get() {
+            self.db.create_db_if_needed()?;
+            self.ready.set(true);
+        }
+        Ok(&self.db)
+    }
+}
+
+impl Store for Database {
+    fn billnr(&self, _config: &Config, company: &str, project: Option<&str>, billdate: &NaiveDate, period: &(NaiveDate, NaiveDate), taken: &[i32]) -> BillResult<(String, i32)> {
+        let db = self.db()?;
+        Ok(match db.get_billnr_if_exists(company, project.unwrap_or_default(), period)? {
+            Some(billnr) => billnr,
+            None => db.get_new_billnr(billdate, &date_utils::month_dir_name(billdate), taken)?,
+        })
+    }
+
+    fn save(&self, config: &Config, bill: &Bill, billdate: &NaiveDate) -> BillResult<String> {
+        let db = self.db()?;
+        let saved_pdf_filename = pdf_gen::save_pdf(&bill.pdf, &config.paths.bills_dir(billdate), *billdate, &bill.company, bill.project.as_deref(), &bill.period)?;
+        db.add_to_db(&bill.company, bill.project.as_deref().unwrap_or_default(), billdate, &bill.period, &bill.duedate, &bill.billnr, &bill.amounts.total, &bill.amount_str, &bill.billnr_int, &bill.currency, &bill.exchange_rate, bill.amounts.skonto.as_ref())?;
+        let usage = Usage {
+            minutes: bill.amounts.entries.iter().map(|entry| entry.minutes).sum(),
+            net: bill.amounts.net,
+            contingent_minutes: bill.amounts.contingent_minutes,
+        };
+        db.set_usage(&bill.billnr, &usage)?;
+
+        let mut projects: Vec<&str> = bill.amounts.lines.iter().map(|line| line.project.as_str()).filter(|project| !project.is_empty()).collect();
+        projects.dedup();
+        let sections: Vec<(String, i32, f64)> = projects
+            .into_iter()
+            .map(|project| {
+                let (minutes, net) = bill.amounts.section(project);
+                (project.to_string(), minutes, net)
+            })
+            .collect();
+        db.set_sections(&bill.billnr, &sections)?;
+        Ok(saved_pdf_filename)
+    }
+
+    fn usage(&self, _config: &Config, company: &str, project: Option<&str>, from: Option<&NaiveDate>, before: &NaiveDate) -> BillResult<Usage> {
+        let db = self.db()?;
+        Ok(match project {
+            Some(project) => db.get_project_usage(company, project, from, before)?,
+            None => db.get_usage(company, from, before)?,
+        })
+    }
+
+    fn overlapping(&self, _config: &Config, company: &str, project: Option<&str>, period: &(NaiveDate, NaiveDate)) -> BillResult<Vec<(String, (NaiveDate, NaiveDate))>> {
+        let bills = self.db()?.get_overlapping_bills(company, project.unwrap_or_default(), period)?;
+        Ok(bills.into_iter().filter_map(|bill| Some((bill.billnr.clone(), bill.period()?))).collect())
+    }
+}
+
+/// Uploads a mail draft with the bill via IMAP
+pub struct MailDraft;
+
+impl Deliver for MailDraft {
+    fn deliver(&self, config: &Config, bill: &Bill, filename: &str, _billdate: &NaiveDate) -> BillResult<()> {
+        let company_config = config_reader::get_company_config(config, &bill.company)?;
+        Ok(mail::create_mail_draft(&config.mailconfig, company_config, &bill.period, bill.pdf.clone(), filename)?)
+    }
+}
+
+/// The billing pipeline: collect entries → compute amounts → render PDF → build e-invoice → persist → deliver.
+/// Every stage can be replaced with one of the `with_*` methods
+pub struct Biller {
+    config: Config,
+    collect: Box<dyn Collect>,
+    calculate: Box<dyn Calculate>,
+    render: Box<dyn Render>,
+    einvoice: Box<dyn EInvoice>,
+    store: Box<dyn Store>,
+    deliver: Option<Box<dyn Deliver>>,
+    /// Bills this period instead of the billing period of each company
+    period: Option<(NaiveDate, NaiveDate)>,
+    /// States this Leistungszeitraum instead of the one from the entries or the billing period
+    service_period: Option<(NaiveDate, NaiveDate)>,
+}
+
+impl Biller {
+    /// The pipeline the command line uses, without delivery
+    pub fn new(config: Config) -> BillResult<Self> {
+        Ok(Biller {
+            collect: Box::new(TimeSources),
+            calculate: Box::new(HourlyFee),
+            render: Box::new(TypstPdf::new(&config)?),
+            einvoice: Box::new(UblXml),
+            store: Box::new(Database::new(&config)),
+            deliver: None,
+            period: None,
+            service_period: None,
+            config,
+        })
+    }
+
+    /// Reads the config like the command line does, see `config_reader::read_config`
+    pub fn from_config_file(path: &str, overrides: &[String]) -> BillResult<Self> {
+        Biller::new(config_reader::read_config(path, overrides)?)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn with_collect(mut self, collect: impl Collect + 'static) -> Self {
+        self.collect = Box::new(collect);
+        self
+    }
+
+    pub fn with_calculate(mut self, calculate: impl Calculate + 'static) -> Self {
+        self.calculate = Box::new(calculate);
+        self
+    }
+
+    pub fn with_render(mut self, render: impl Render + 'static) -> Self {
+        self.render = Box::new(render);
+        self
+    }
+
+    pub fn with_einvoice(mut self, einvoice: impl EInvoice + 'static) -> Self {
+        self.einvoice = Box::new(einvoice);
+        self
+    }
+
+    pub fn with_store(mut self, store: impl Store + 'static) -> Self {
+        self.store = Box::new(store);
+        self
+    }
+
+    pub fn with_deliver(mut self, deliver: impl Deliver + 'static) -> Self {
+        self.deliver = Some(Box::new(deliver));
+        self
+    }
+
+    /// Bills `from` to `to` (both included), e.g. for a one-off project, instead of the periods of the companies.
+    /// Rendering refuses a period that overlaps a stored bill of the same company and project
+    pub fn with_period(mut self, from: NaiveDate, to: NaiveDate) -> Self {
+        self.period = Some((from, to));
+        self
+    }
+
+    /// States `from` to `to` as the Leistungszeitraum of the bills
+    pub fn with_service_period(mut self, from: NaiveDate, to: NaiveDate) -> Self {
+        self.service_period = Some((from, to));
+        self
+    }
+
+    /// The period billed on `billdate`: the one set with [Biller::with_period], otherwise the billing period of the company
+    /// containing `billdate`
+    pub fn period(&self, company: &str, billdate: &NaiveDate) -> BillResult<(NaiveDate, NaiveDate)> {
+        if let Some(period) = self.period {
+            return Ok(period)
+        }
+        let billing_period = self.config.companies.get(company).map(|company_config| company_config.billing_period).unwrap_or_default();
+        Ok(date_utils::period_bounds(billdate, billing_period)?)
+    }
+
+    /// The companies to bill on `billdate`, looking at all periods containing it
+    pub fn companies(&self, billdate: &NaiveDate) -> BillResult<Vec<String>> {
+        let span = match self.period {
+            Some(period) => period,
+            None => {
+                let mut span = date_utils::month_bounds(billdate)?;
+                for company_config in self.config.companies.values() {
+                    let (from, to) = date_utils::period_bounds(billdate, company_config.billing_period)?;
+                    span = (span.0.min(from), span.1.max(to));
+                }
+                span
+            },
+        };
+        self.collect.companies(&self.config, &span)
+    }
+
+    pub fn entries(&self, company: &str, billdate: &NaiveDate) -> BillResult<Vec<Record>> {
+        let period = self.period(company, billdate)?;
+        self.collect.entries(&self.config, company, &period)
+    }
+
+    /// The Leistungszeitraum of a bill with `records` in `period`
+    fn service_period(&self, records: &[Record], period: &(NaiveDate, NaiveDate)) -> (NaiveDate, NaiveDate) {
+        if let Some(service_period) = self.service_period {
+            return service_period
+        }
+        let dates = records.iter().filter(|record| record.minutes > 0).map(|record| record.date);
+        match (self.config.bill_config.service_period, dates.clone().min(), dates.max()) {
+            (ServicePeriod::Entries, Some(first), Some(last)) => (first, last),
+            _ => *period,
+        }
+    }
+
+    /// The amounts of the bills of a company in the period: one per project if the projects are billed separately,
+    /// the one without project first. Bills with nothing to bill are left out
+    fn drafts(&self, company: &str, company_config: &CompanyConfig, records: Vec<Record>, period: &(NaiveDate, NaiveDate)) -> BillResult<Vec<Draft>> {
+        let mut groups: Vec<(Option<String>, Vec<Record>)> = vec![(None, Vec::new())];
+        for record in records {
+            // Unknown projects stay on the main bill, where the calculation reports them
+            let project = match company_config.project_billing {
+                ProjectBilling::Separate => company_config.project_key(&record.project).map(str::to_string),
+                ProjectBilling::Sections => None,
+            };
+            match groups.iter_mut().find(|(key, _)| *key == project) {
+                Some((_, group)) => group.push(record),
+                None => groups.push((project, vec![record])),
+            }
+        }
+
+        // Contingents are drawn down across the bills of the run
+        let mut contingent_used = self.store.usage(&self.config, company, None, None, &period.0)?.contingent_minutes;
+        let mut drafts = Vec::new();
+        for (project, records) in groups {
+            let scope = Scope { period: *period, project: project.clone(), contingent_used };
+            let amounts = self.calculate.amounts(&self.config, company_config, &records, &scope)?;
+            contingent_used += amounts.contingent_minutes;
+            if records.iter().map(|record| record.minutes).sum::<i32>() > 0 || !amounts.items.is_empty() {
+                drafts.push(Draft { project, records, amounts });
+            }
+        }
+        Ok(drafts)
+    }
+
+    fn budget_statuses(&self, company: &str, company_config: &CompanyConfig, period: &(NaiveDate, NaiveDate), drafts: &[Draft]) -> BillResult<Vec<BudgetStatus>> {
+        let mut statuses = Vec::new();
+        for budget in company_config.budgets.iter().filter(|budget| budget::applies(budget, period)) {
+            let project = budget::project_key(budget, company_config)?;
+            let billed = self.store.usage(&self.config, company, project.as_deref(), budget.start.as_ref(), &period.0)?;
+            let current = drafts.iter().fold(Usage::default(), |usage, draft| {
+                let (minutes, net) = match &project {
+                    Some(project) => draft.amounts.section(project),
+                    None => (draft.amounts.entries.iter().map(|entry| entry.minutes).sum(), draft.amounts.net),
+                };
+                Usage { minutes: usage.minutes + minutes, net: usage.net + net, ..usage }
+            });
+            statuses.push(budget::status(budget, project, &billed, &current)?);
+        }
+        Ok(statuses)
+    }
+
+    /// The budgets of a company running in the period of `billdate`, including the hours of that period
+    pub fn budgets(&self, company: &str, billdate: &NaiveDate) -> BillResult<Vec<BudgetStatus>> {
+        let period = self.period(company, billdate)?;
+        let company_config = config_reader::get_company_config(&self.config, company)?;
+        let records = self.collect.entries(&self.config, company, &period)?;
+        let drafts = self.drafts(company, company_config, records, &period)?;
+        self.budget_statuses(company, company_config, &period, &drafts)
+    }
+
+    /// Collects and validates the entries of a company and renders its bills, without saving anything.
+    /// That is one bill, or one per project if the company bills its projects separately. Empty if there is nothing to bill
+    pub fn render(&self, company: &str, billdate: &NaiveDate, strict: bool) -> BillResult<Vec<Bill>> {
+        let period = self.period(company, billdate)?;
+
+        let records = self.collect.entries(&self.config, company, &period)?;
+
+        let issues = validate::validate_records(&records, &period, self.config.bill_config.calendar);
+        validate::report_issues(company, &issues, strict)?;
+
+        let company_config = config_reader::get_company_config(&self.config, company)?;
+        let drafts = self.drafts(company, company_config, records, &period)?;
+        if drafts.is_empty() {
+            return Ok(Vec::new())
+        }
+
+        let payment_terms = company_config.payment_terms.unwrap_or(self.config.bill_config.payment_terms);
+        let duedate = date_utils::calculate_due_date(*billdate, payment_terms, self.config.bill_config.calendar)?;
+
+        let budgets = self.budget_statuses(company, company_config, &period, &drafts)?;
+        budget::check(company, &budgets)?;
+
+        let currency = calculate::find_currency(&company_config.currency)?;
+        let rate = exchange::rate_for(&self.config.paths.rates, currency.code(), billdate)?;
+
+        let mut bills: Vec<Bill> = Vec::new();
+        // A period overlapping a stored bill would bill the same hours twice
+        for draft in &drafts {
+            if let Some((billnr, billed)) = self.store.overlapping(&self.config, company, draft.project.as_deref(), &period)?.into_iter().next() {
+                return Err(BillingError::OverlappingBill {
+                    company: company.to_string(),
+                    from: period.0,
+                    to: period.1,
+                    billnr,
+                    billed_from: billed.0,
+                    billed_to: billed.1,
+                }.into())
+            }
+        }
+
+        for Draft { project, records, amounts } in drafts {
+            let taken: Vec<i32> = bills.iter().map(|bill| bill.billnr_int).collect();
+            let (billnr, billnr_int) = self.store.billnr(&self.config, company, project.as_deref(), billdate, &period, &taken)?;
+            println!("The bill number is {:?}", billnr);
+
+            let amount_str = calculate::to_amount_string(&amounts.total, currency)?;
+
+            // Budgets of other projects don't concern this bill
+            let bill_budgets: Vec<BudgetStatus> = budgets
+                .iter()
+                .filter(|status| status.project.as_ref().is_none_or(|key| amounts.lines.iter().any(|line| &line.project == key)))
+                .cloned()
+                .collect();
+
+            let invoice = Invoice {
+                company,
+                company_config,
+                project: project.as_deref(),
+                billnr: &billnr,
+                billdate: *billdate,
+                duedate,
+                payment_terms,
+                period,
+                service_period: self.service_period(&records, &period),
+                records: &records,
+                amounts: &amounts,
+                amount_str: &amount_str,
+                currency,
+                rate,
+                budgets: &bill_budgets,
+            };
+
+            let pdf_data = self.render.render(&self.config, &invoice)?;
+            let pdf = self.einvoice.attach(&self.config, &invoice, &pdf_data)?;
+
+            bills.push(Bill {
+                company: company.to_string(),
+                project,
+                period,
+                duedate,
+                billnr,
+                billnr_int,
+                amounts,
+                amount_str,
+                currency: currency.code().to_string(),
+                exchange_rate: rate.rate,
+                pdf,
+            });
+        }
+        Ok(bills)
+    }
+
+    /// Persists the bill. Returns the file name it was saved as
+    pub fn save(&self, bill: &Bill, billdate: &NaiveDate) -> BillResult<String> {
+        self.store.save(&self.config, bill, billdate)
+    }
+
+    /// Delivers a saved bill, if a delivery stage is set
+    pub fn deliver(&self, bill: &Bill, filename: &str, billdate: &NaiveDate) -> BillResult<()> {
+        match &self.deliver {
+            Some(deliver) => deliver.deliver(&self.config, bill, filename, billdate),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs the whole pipeline for one company. Returns the bills and their file names, empty if there is nothing to bill
+    pub fn bill(&self, company: &str, billdate: &NaiveDate, strict: bool) -> BillResult<Vec<(Bill, String)>> {
+        let mut saved = Vec::new();
+        for bill in self.render(company, billdate, strict)? {
+            let filename = self.save(&bill, billdate)?;
+            self.deliver(&bill, &filename, billdate)?;
+            saved.push((bill, filename));
+        }
+        Ok(saved)
+    }
+}
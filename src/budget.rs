@@ -0,0 +1,144 @@
+use chrono::NaiveDate;
+use thiserror::Error;
+use crate::billing::{BillResult, Biller};
+use crate::config_reader::{Budget, CompanyConfig, OnExceed};
+use crate::db::Usage;
+
+#[derive(Debug, Error)]
+pub enum BudgetError {
+    #[error("Budget {0:?} needs either hours or an amount")]
+    Invalid(String),
+    #[error("Budget {name:?} is for project {project:?}, which is not configured for the company")]
+    UnknownProject { name: String, project: String },
+    #[error("{company}: the bill would exceed the budget {name:?} ({used:.2} of {limit:.2} {unit}). Not billing")]
+    Exceeded { company: String, name: String, used: f64, limit: f64, unit: &'static str },
+}
+
+type BudgetResult<T> = Result<T, BudgetError>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    Hours,
+    /// The net amount in the bill currency
+    Amount,
+}
+
+impl Unit {
+    pub fn name(self) -> &'static str {
+        match self {
+            Unit::Hours => "hours",
+            Unit::Amount => "net",
+        }
+    }
+}
+
+/// Where a budget stands with the bill of a period
+#[derive(Debug, Clone)]
+pub struct BudgetStatus {
+    pub name: String,
+    /// The key of the project the budget is limited to
+    pub project: Option<String>,
+    pub unit: Unit,
+    pub limit: f64,
+    /// Used by the bills before the period
+    pub billed: f64,
+    /// Used by the bill of the period
+    pub current: f64,
+    pub warn_at: f64,
+    pub on_exceed: OnExceed,
+}
+
+impl BudgetStatus {
+    pub fn used(&self) -> f64 {
+        self.billed + self.current
+    }
+
+    pub fn remaining(&self) -> f64 {
+        self.limit - self.used()
+    }
+
+    pub fn exceeded(&self) -> bool {
+        self.used() > self.limit + 1e-9
+    }
+
+    /// Used beyond the warning threshold
+    pub fn warning(&self) -> bool {
+        self.used() >= self.limit * self.warn_at / 100.0
+    }
+}
+
+/// Whether the budget runs during the billing period
+pub fn applies(budget: &Budget, period: &(NaiveDate, NaiveDate)) -> bool {
+    budget.start.is_none_or(|start| start <= period.1) && budget.end.is_none_or(|end| end >= period.0)
+}
+
+/// The key of the project the budget is limited to, if any
+pub fn project_key(budget: &Budget, company_config: &CompanyConfig) -> BudgetResult<Option<String>> {
+    budget.project
+        .as_deref()
+        .map(|project| {
+            company_config.project_key(project)
+                .map(str::to_string)
+                .ok_or_else(|| BudgetError::UnknownProject { name: budget.name.clone(), project: project.to_string() })
+        })
+        .transpose()
+}
+
+/// `billed` is the usage of the bills from the budget start up to the period, `current` the one of the bills of the period
+pub fn status(budget: &Budget, project: Option<String>, billed: &Usage, current: &Usage) -> BudgetResult<BudgetStatus> {
+    let (unit, limit, billed, current) = match (budget.hours, budget.amount) {
+        (Some(hours), None) => (Unit::Hours, hours, billed.minutes as f64 / 60.0, current.minutes as f64 / 60.0),
+        (None, Some(amount)) => (Unit::Amount, amount, billed.net, current.net),
+        _ => return Err(BudgetError::Invalid(budget.name.clone())),
+    };
+    Ok(BudgetStatus { name: budget.name.clone(), project, unit, limit, billed, current, warn_at: budget.warn_at, on_exceed: budget.on_exceed })
+}
+
+/// Prints a warning for budgets close to or beyond their limit. Fails for exceeded budgets that stop billing
+pub fn check(company: &str, statuses: &[BudgetStatus]) -> BudgetResult<()> {
+    for status in statuses {
+        if status.exceeded() && status.on_exceed == OnExceed::Stop {
+            return Err(BudgetError::Exceeded {
+                company: company.to_string(),
+                name: status.name.clone(),
+                used: status.used(),
+                limit: status.limit,
+                unit: status.unit.name(),
+            })
+        }
+        if status.exceeded() {
+            println!("{}: Warning: budget {:?} exceeded, {:.2} of {:.2} {} used", company, status.name, status.used(), status.limit, status.unit.name());
+        } else if status.warning() {
+            println!("{}: Warning: budget {:?} is {:.0} % used, {:.2} {} left", company, status.name, status.used() / status.limit * 100.0, status.remaining(), status.unit.name());
+        }
+    }
+    Ok(())
+}
+
+/// Prints the budgets of the companies (all with budgets if empty), including the period of `billdate` whether billed or not
+pub fn report(biller: &Biller, companies: &[String], billdate: &NaiveDate) -> BillResult<()> {
+    let companies: Vec<&String> = match companies {
+        [] => biller.config().companies.iter().filter(|(_, company_config)| !company_config.budgets.is_empty()).map(|(company, _)| company).collect(),
+        companies => companies.iter().collect(),
+    };
+    if companies.is_empty() {
+        println!("No budgets configured");
+        return Ok(())
+    }
+
+    // Reading the entries prints progress, so the table comes after
+    let mut rows: Vec<(&String, BudgetStatus)> = Vec::new();
+    for company in companies {
+        rows.extend(biller.budgets(company, billdate)?.into_iter().map(|status| (company, status)));
+    }
+
+    println!("\n{:<20} {:<24} {:>6} {:>10} {:>10} {:>10} {:>10} {:>6}", "Company", "Budget", "Unit", "Limit", "Billed", "Month", "Left", "Used");
+    for (company, status) in rows {
+        let marker = if status.exceeded() { "  exceeded" } else if status.warning() { "  warning" } else { "" };
+        println!(
+            "{:<20} {:<24} {:>6} {:>10.2} {:>10.2} {:>10.2} {:>10.2} {:>5.0}%{}",
+            company, status.name, status.unit.name(), status.limit, status.billed, status.current, status.remaining(), status.used() / status.limit * 100.0, marker,
+        );
+    }
+    Ok(())
+}
@@ -1,6 +1,9 @@
-use crate::config_reader;
+use std::collections::BTreeMap;
+use chrono::{Datelike, Months, NaiveDate, Weekday};
+use crate::config_reader::{self, Adjustment, CompanyConfig, RateCard, RecurringItem, Rounding};
+use crate::csv_reader::Record;
 use rust_decimal::Decimal;
-use rusty_money::{iso::{self}, Round, Money};
+use rusty_money::{iso::{self, Currency}, FormattableCurrency, Formatter, LocalFormat, Locale, Money, Params, Position, Round};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -9,11 +12,94 @@ pub enum AmountCalcs{
     CalculationError,
     #[error("Extraction error")]
     ExtractError(#[from] config_reader::ExtractError),
+    #[error("Line {line}: activity {activity:?} is not in the rate card")]
+    UnknownActivity { activity: String, line: u64 },
+    #[error("Line {line}: project {project:?} is not configured for the company")]
+    UnknownProject { project: String, line: u64 },
+    #[error("Discount or surcharge {0:?} needs either percent or an amount")]
+    InvalidAdjustment(String),
+    #[error("Unknown currency {0:?}")]
+    UnknownCurrency(String),
+    #[error("Unknown number format {0:?}, expected one of en-us, en-eu, en-in or en-by")]
+    UnknownNumberFormat(String),
 }
 
 type CalculationResult<T> = Result<T, AmountCalcs>;
 type CurrencyResult<T> = Result<T, AmountCalcs>;
 
+/// An entry as billed: rounded to the billing increment, at the fee of its activity
+#[derive(Debug, Clone)]
+pub struct BilledEntry {
+    pub minutes: i32,
+    pub hourly_fee: f64,
+    /// Including the weekend surcharge
+    pub amount: f64,
+}
+
+/// The hours of one activity in a project, a position on the bill
+#[derive(Debug, Clone)]
+pub struct ActivityLine {
+    /// The key of the project in the config. Empty for entries without project
+    pub project: String,
+    /// Empty for entries without activity
+    pub activity: String,
+    pub minutes: i32,
+    pub hours: f64,
+    pub hourly_fee: f64,
+    /// The hours at the hourly fee
+    pub base: f64,
+    /// Discounts and surcharges of the activity
+    pub adjustments: Vec<AppliedAdjustment>,
+    /// After its discounts and surcharges, without the weekend surcharge
+    pub amount: f64,
+}
+
+/// A discount or surcharge as applied to a line or the whole bill
+#[derive(Debug, Clone)]
+pub struct AppliedAdjustment {
+    pub reason: String,
+    /// A surcharge, otherwise a discount
+    pub charge: bool,
+    pub percent: Option<f64>,
+    /// What the percentage is taken of
+    pub base: f64,
+    /// Positive for discounts as well
+    pub amount: f64,
+}
+
+impl AppliedAdjustment {
+    /// Negative for discounts
+    pub fn signed(&self) -> f64 {
+        if self.charge { self.amount } else { -self.amount }
+    }
+}
+
+/// Early payment discount
+#[derive(Debug, Clone, Copy)]
+pub struct SkontoTerms {
+    pub percent: f64,
+    pub days: u32,
+    /// The total less the discount
+    pub total: f64,
+}
+
+/// A fixed amount on the bill: a recurring item or a purchased contingent
+#[derive(Debug, Clone)]
+pub struct FixedItem {
+    pub description: String,
+    pub amount: f64,
+}
+
+/// The bill the amounts are calculated for
+#[derive(Debug, Clone)]
+pub struct Scope {
+    pub period: (NaiveDate, NaiveDate),
+    /// Set for the bill of a single project. The fixed items go on the bill without project
+    pub project: Option<String>,
+    /// Minutes of the prepaid contingents used by earlier bills
+    pub contingent_used: i32,
+}
+
 #[derive(Debug)]
 pub struct Amounts {
     pub net: f64,
@@ -21,17 +107,43 @@ pub struct Amounts {
     pub total: f64,
     pub hourly_fee: f64,
     pub hours_total: f64,
+    /// One per record, in the same order
+    pub entries: Vec<BilledEntry>,
+    pub lines: Vec<ActivityLine>,
+    /// Added for work on weekends
+    pub surcharge: f64,
+    /// Deducted by the day cap
+    pub cap_deduction: f64,
+    pub items: Vec<FixedItem>,
+    /// Minutes drawn from prepaid contingents by this bill, and their value
+    pub contingent_minutes: i32,
+    pub contingent_deduction: f64,
+    /// Minutes left in the contingents after this bill
+    pub contingent_remaining: i32,
+    /// Discounts and surcharges of the whole bill
+    pub adjustments: Vec<AppliedAdjustment>,
+    pub skonto: Option<SkontoTerms>,
 }
 
-fn calculate_amount_net(minutes_total: &i32, hourly_fee: &f64) -> CalculationResult<f64> {
-    let minutes_total_float: f64 = *minutes_total as f64;
-    let amount_net: f64 = minutes_total_float / 60.0 * hourly_fee;
-    Ok(amount_net)
+impl Amounts {
+    /// The billed minutes and net amount of the lines of a project, without surcharges and deductions
+    pub fn section(&self, project: &str) -> (i32, f64) {
+        self.lines
+            .iter()
+            .filter(|line| line.project == project)
+            .fold((0, 0.0), |(minutes, net), line| (minutes + line.minutes, net + line.amount))
+    }
 }
 
+fn round_cents(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
+
+/// The VAT rate of all bills
+pub static VAT_RATE: f64 = 0.19;
+
 fn calculate_vat(amount_net: &f64) -> CalculationResult<f64> {
-    let vat_rate: f64 = 0.19;
-    let amount_vat: f64 = amount_net * vat_rate;
+    let amount_vat: f64 = amount_net * VAT_RATE;
     Ok(amount_vat)
 }
 
@@ -40,24 +152,276 @@ fn calculate_amount_total(amount_net: &f64, amount_vat: &f64) -> CalculationResu
     Ok(amount_total)
 }
 
-pub fn calculate_amounts(minutes_total: &i32, hourly_fee: &f64) -> CalculationResult<Amounts> {
-    let amount_net = calculate_amount_net(minutes_total, hourly_fee)?;
-    let amount_vat = calculate_vat(&amount_net)?;
+fn round_to_increment(minutes: i32, increment: Option<i32>, rounding: Rounding) -> i32 {
+    let increment = match increment {
+        Some(increment) if increment > 1 => increment,
+        _ => return minutes,
+    };
+    let units = match rounding {
+        Rounding::Up => (minutes + increment - 1).div_euclid(increment),
+        Rounding::Nearest => (minutes + increment / 2).div_euclid(increment),
+        Rounding::Down => minutes.div_euclid(increment),
+    };
+    units * increment
+}
+
+/// The activity as named in the rate card and its fee
+fn activity_fee<'a>(record: &'a Record, company_config: &'a CompanyConfig, rate_card: &'a RateCard) -> CalculationResult<(&'a str, f64)> {
+    let activity = record.activity.trim();
+    if activity.is_empty() {
+        return Ok(("", company_config.hourly_fee))
+    }
+    rate_card.activities
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(activity))
+        .map(|(name, fee)| (name.as_str(), *fee))
+        .ok_or_else(|| AmountCalcs::UnknownActivity { activity: activity.to_string(), line: record.line })
+}
+
+fn apply_adjustment(adjustment: &Adjustment, charge: bool, base: f64) -> CalculationResult<AppliedAdjustment> {
+    let amount = match (adjustment.percent, adjustment.amount) {
+        (Some(percent), None) => round_cents(base * percent / 100.0),
+        (None, Some(amount)) => amount,
+        _ => return Err(AmountCalcs::InvalidAdjustment(adjustment.reason.clone())),
+    };
+    Ok(AppliedAdjustment { reason: adjustment.reason.clone(), charge, percent: adjustment.percent, base, amount })
+}
+
+/// The discounts and surcharges for an activity, or with `None` those of the whole bill
+fn adjustments(company_config: &CompanyConfig, activity: Option<&str>, base: f64) -> CalculationResult<Vec<AppliedAdjustment>> {
+    let applies = |adjustment: &&Adjustment| match (&adjustment.activity, activity) {
+        (None, None) => true,
+        (Some(adjustment_activity), Some(activity)) => adjustment_activity.eq_ignore_ascii_case(activity),
+        _ => false,
+    };
+    let discounts = company_config.discounts.iter().filter(applies).map(|discount| apply_adjustment(discount, false, base));
+    let surcharges = company_config.surcharges.iter().filter(applies).map(|surcharge| apply_adjustment(surcharge, true, base));
+    discounts.chain(surcharges).collect()
+}
+
+/// The key of the project in the config, empty for entries without project
+fn project_key<'a>(record: &Record, company_config: &'a CompanyConfig) -> CalculationResult<&'a str> {
+    if record.project.trim().is_empty() {
+        return Ok("")
+    }
+    company_config.project_key(&record.project)
+        .ok_or_else(|| AmountCalcs::UnknownProject { project: record.project.trim().to_string(), line: record.line })
+}
+
+/// The dates within `period` on which a recurring item is due
+fn occurrences(item: &RecurringItem, period: &(NaiveDate, NaiveDate)) -> Vec<NaiveDate> {
+    let last = item.end.map_or(period.1, |end| end.min(period.1));
+    let Some(step) = item.interval.months() else {
+        return (item.start >= period.0 && item.start <= last).then_some(item.start).into_iter().collect()
+    };
+    (0..)
+        .map_while(|n| item.start.checked_add_months(Months::new(n * step)).filter(|date| *date <= last))
+        .filter(|date| *date >= period.0)
+        .collect()
+}
+
+/// The recurring items due in `period` and the contingents bought in it
+fn fixed_items(company_config: &CompanyConfig, period: &(NaiveDate, NaiveDate)) -> Vec<FixedItem> {
+    let recurring = company_config.recurring
+        .iter()
+        .flat_map(|item| occurrences(item, period).into_iter().map(|_| FixedItem { description: item.description.clone(), amount: item.amount }));
+    let contingents = company_config.contingents
+        .iter()
+        .filter(|contingent| contingent.date >= period.0 && contingent.date <= period.1)
+        .map(|contingent| FixedItem { description: format!("Stundenkontingent ({} Stunden)", contingent.hours), amount: contingent.price });
+    recurring.chain(contingents).collect()
+}
+
+/// Applies the rate card of the company (activity fees, billing increment, weekend surcharge and day cap) to the records,
+/// with a line per project and activity. Adds the fixed items due in the period and draws the hours from the prepaid contingents
+pub fn calculate_amounts(records: &[Record], company_config: &CompanyConfig, scope: &Scope) -> CalculationResult<Amounts> {
+    let period = &scope.period;
+    let default_rate_card = RateCard::default();
+    let rate_card = company_config.rate_card.as_ref().unwrap_or(&default_rate_card);
+
+    let mut entries: Vec<BilledEntry> = Vec::new();
+    let mut lines: Vec<ActivityLine> = Vec::new();
+    let mut day_totals: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+    let mut surcharge = 0.0;
+
+    for record in records {
+        let project = project_key(record, company_config)?;
+        let (activity, hourly_fee) = activity_fee(record, company_config, rate_card)?;
+        let minutes = round_to_increment(record.minutes, rate_card.increment, rate_card.rounding);
+        let base = minutes as f64 / 60.0 * hourly_fee;
+        let extra = match record.date.weekday() {
+            Weekday::Sat | Weekday::Sun => base * rate_card.weekend_surcharge / 100.0,
+            _ => 0.0,
+        };
+
+        entries.push(BilledEntry { minutes, hourly_fee, amount: base + extra });
+        *day_totals.entry(record.date).or_default() += base + extra;
+        surcharge += extra;
+
+        match lines.iter_mut().find(|line| line.project == project && line.activity == activity) {
+            Some(line) => line.minutes += minutes,
+            None => lines.push(ActivityLine {
+                project: project.to_string(),
+                activity: activity.to_string(),
+                minutes,
+                hours: 0.0,
+                hourly_fee,
+                base: 0.0,
+                adjustments: Vec::new(),
+                amount: 0.0,
+            }),
+        }
+    }
+
+    // Sections by project, entries without project first
+    lines.sort_by(|a, b| a.project.cmp(&b.project));
+
+    for line in &mut lines {
+        line.hours = line.minutes as f64 / 60.0;
+        line.base = round_cents(line.hours * line.hourly_fee);
+        line.adjustments = adjustments(company_config, Some(&line.activity), line.base)?;
+        line.amount = line.base + line.adjustments.iter().map(AppliedAdjustment::signed).sum::<f64>();
+    }
+    let surcharge = round_cents(surcharge);
+    let cap_deduction = match rate_card.day_cap {
+        Some(cap) => round_cents(day_totals.values().map(|total| (total - cap).max(0.0)).sum()),
+        None => 0.0,
+    };
+
+    let items = match scope.project {
+        None => fixed_items(company_config, period),
+        Some(_) => Vec::new(),
+    };
+
+    // Entries are drawn in date order from the earliest contingent bought on or before their day, at the fee they
+    // are billed at after the day cap. Minutes used by earlier bills are taken from the earliest contingents
+    let mut contingents: Vec<(NaiveDate, i32)> = company_config.contingents
+        .iter()
+        .filter(|contingent| contingent.date <= period.1)
+        .map(|contingent| (contingent.date, (contingent.hours * 60.0).round() as i32))
+        .collect();
+    contingents.sort_by_key(|(date, _)| *date);
+    let mut used = scope.contingent_used;
+    for (_, minutes) in &mut contingents {
+        let taken = used.clamp(0, *minutes);
+        *minutes -= taken;
+        used -= taken;
+    }
+    let cap_factor = |date: &NaiveDate| match (rate_card.day_cap, day_totals.get(date)) {
+        (Some(cap), Some(total)) if *total > cap => cap / total,
+        _ => 1.0,
+    };
+    let mut order: Vec<usize> = (0..entries.len()).collect();
+    order.sort_by_key(|index| records[*index].date);
+    let mut contingent_minutes = 0;
+    let mut contingent_deduction = 0.0;
+    for index in order {
+        let (entry, date) = (&entries[index], records[index].date);
+        let mut open = entry.minutes.max(0);
+        for (_, minutes) in contingents.iter_mut().filter(|(bought, _)| *bought <= date) {
+            let drawn = open.min(*minutes);
+            *minutes -= drawn;
+            open -= drawn;
+            contingent_minutes += drawn;
+            contingent_deduction += drawn as f64 / 60.0 * entry.hourly_fee * cap_factor(&date);
+        }
+    }
+    let contingent_remaining = contingents.iter().map(|(_, minutes)| minutes).sum();
+    // The contingent never covers more than the hours are billed at
+    let hours_amount = lines.iter().map(|line| line.amount).sum::<f64>() + surcharge - cap_deduction;
+    let contingent_deduction = round_cents(contingent_deduction).min(round_cents(hours_amount).max(0.0));
+
+    let amount_before_adjustments = lines.iter().map(|line| line.amount).sum::<f64>()
+        + items.iter().map(|item| item.amount).sum::<f64>()
+        + surcharge
+        - cap_deduction
+        - contingent_deduction;
+    let document_adjustments = adjustments(company_config, None, round_cents(amount_before_adjustments))?;
+    let amount_net = round_cents(amount_before_adjustments + document_adjustments.iter().map(AppliedAdjustment::signed).sum::<f64>());
+    // The VAT is rounded once, so the total is exactly net plus VAT (BR-CO-15)
+    let amount_vat = round_cents(calculate_vat(&amount_net)?);
     let amount_total = calculate_amount_total(&amount_net, &amount_vat)?;
-    let hours_total = *minutes_total as f64 / 60.0;
+    let skonto = company_config.skonto.map(|skonto| SkontoTerms {
+        percent: skonto.percent,
+        days: skonto.days,
+        total: round_cents(amount_total * (1.0 - skonto.percent / 100.0)),
+    });
+    let hours_total = entries.iter().map(|entry| entry.minutes).sum::<i32>() as f64 / 60.0;
 
-    let amounts =  Amounts {net: amount_net, vat: amount_vat, total: amount_total, hourly_fee: hourly_fee.clone(), hours_total};
+    let amounts = Amounts {
+        net: amount_net,
+        vat: amount_vat,
+        total: amount_total,
+        hourly_fee: company_config.hourly_fee,
+        hours_total,
+        entries,
+        lines,
+        surcharge,
+        cap_deduction,
+        items,
+        contingent_minutes,
+        contingent_deduction,
+        contingent_remaining,
+        adjustments: document_adjustments,
+        skonto,
+    };
 
     Ok(amounts)
 }
 
-pub fn to_euro_string(currencyfloat: &f64) -> CurrencyResult<String> {
+pub fn find_currency(code: &str) -> CurrencyResult<&'static Currency> {
+    iso::find(&code.to_uppercase()).ok_or_else(|| AmountCalcs::UnknownCurrency(code.to_string()))
+}
+
+/// The rounded amount without symbol or separators, e.g. `1234.5`
+pub fn to_amount_string(currencyfloat: &f64, currency: &Currency) -> CurrencyResult<String> {
 
     let decimal_amount = Decimal::from_f64_retain(*currencyfloat).ok_or(AmountCalcs::CalculationError)?;
-    let eur_amount = Money::from_decimal(decimal_amount, iso::EUR);
-    let eur_amount_rounded = eur_amount.round(2, Round::HalfEven);
-    let eur_amount_rounded_value = *eur_amount_rounded.amount();
-    let raw_string = eur_amount_rounded_value.to_string();
+    let amount = Money::from_decimal(decimal_amount, currency);
+    let amount_rounded = amount.round(currency.exponent(), Round::HalfEven);
+    let amount_rounded_value = *amount_rounded.amount();
+    let raw_string = amount_rounded_value.to_string();
 
     Ok(raw_string)
+}
+
+/// The locale given in `bill_config.number_format`, or the one of the currency
+pub fn money_locale(currency: &Currency, number_format: Option<&str>) -> CurrencyResult<Locale> {
+    match number_format.map(str::to_lowercase).as_deref() {
+        None => Ok(currency.locale()),
+        Some("en-us") => Ok(Locale::EnUs),
+        Some("en-eu") => Ok(Locale::EnEu),
+        Some("en-in") => Ok(Locale::EnIn),
+        Some("en-by") => Ok(Locale::EnBy),
+        Some(other) => Err(AmountCalcs::UnknownNumberFormat(other.to_string())),
+    }
+}
+
+/// Formats an amount for the invoice, e.g. `1.234,50 €`, `$1,234.50` or `CHF 1,234.50`
+pub fn format_money(amount: f64, currency: &Currency, locale: Locale) -> String {
+    let Some(decimal_amount) = Decimal::from_f64_retain(amount) else {
+        return format!("{:.2} {}", amount, currency.code())
+    };
+    let money = Money::from_decimal(decimal_amount, currency);
+    let format = LocalFormat::from_locale(locale);
+
+    // In the eurozone the symbol follows the amount. Symbols longer than one character read better as the code
+    let positions = if locale == Locale::EnEu || !currency.symbol_first() {
+        vec![Position::Sign, Position::Amount, Position::Space, Position::Symbol]
+    } else if currency.symbol().chars().count() == 1 {
+        vec![Position::Sign, Position::Symbol, Position::Amount]
+    } else {
+        vec![Position::Sign, Position::Code, Position::Space, Position::Amount]
+    };
+
+    let params = Params {
+        digit_separator: format.digit_separator,
+        exponent_separator: format.exponent_separator,
+        separator_pattern: format.digit_separator_pattern(),
+        positions,
+        rounding: Some(currency.exponent()),
+        symbol: Some(currency.symbol()),
+        code: Some(currency.code()),
+    };
+    Formatter::money(&money, params)
 }
\ No newline at end of file
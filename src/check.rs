@@ -0,0 +1,97 @@
+use chrono::NaiveDate;
+use thiserror::Error;
+use crate::billing::{Bill, BillResult, Biller, Store};
+use crate::config_reader::{self, Config};
+
+#[derive(Debug, Error)]
+pub enum CheckError {
+    #[error("Config check failed with {0} problem(s)")]
+    Failed(usize),
+}
+
+type CheckResult<T> = Result<T, CheckError>;
+
+fn report(ok: bool, what: &str, problems: &mut usize) {
+    if ok {
+        println!("  OK    {}", what);
+    } else {
+        println!("  FAIL  {}", what);
+        *problems += 1;
+    }
+}
+
+// Renders with a placeholder bill number and without touching the database
+struct CheckStore;
+
+impl Store for CheckStore {
+    fn billnr(&self, _config: &Config, _company: &str, _project: Option<&str>, _billdate: &NaiveDate, _period: &(NaiveDate, NaiveDate), _taken: &[i32]) -> BillResult<(String, i32)> {
+        Ok((String::from("CHECK"), 0))
+    }
+
+    fn save(&self, _config: &Config, _bill: &Bill, _billdate: &NaiveDate) -> BillResult<String> {
+        Ok(String::new())
+    }
+}
+
+fn compile_templates(biller: &Biller, company: &str, billdate: &NaiveDate) -> BillResult<()> {
+    config_reader::get_company_config(biller.config(), company)?;
+    biller.render(company, billdate, false)?;
+    Ok(())
+}
+
+pub fn check_config(config_name: &str, overrides: &[String], billdate: &NaiveDate) -> CheckResult<()> {
+    let mut problems = 0;
+
+    println!("Checking config schema");
+    let config = match config_reader::read_config(config_name, overrides) {
+        Ok(config) => {
+            report(true, &format!("{} matches the config schema", config_name), &mut problems);
+            config
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            report(false, &format!("{} matches the config schema", config_name), &mut problems);
+            return Err(CheckError::Failed(problems));
+        }
+    };
+
+    println!("Checking companies with timesheets in {:?}", config.paths.data_dir(billdate));
+    let biller = match Biller::new(config) {
+        Ok(biller) => biller.with_store(CheckStore),
+        Err(e) => {
+            eprintln!("{}", e);
+            report(false, "config can be converted for the templates", &mut problems);
+            return Err(CheckError::Failed(problems));
+        }
+    };
+    let config = biller.config();
+    let companies = match biller.companies(billdate) {
+        Ok(companies) => companies,
+        Err(e) => {
+            eprintln!("Could not list the companies: {}", e);
+            report(false, "data directory is readable", &mut problems);
+            Vec::new()
+        }
+    };
+    for company in &companies {
+        report(config.companies.contains_key(company), &format!("{} has a config entry", company), &mut problems);
+    }
+
+    println!("Checking that the templates compile");
+    if companies.is_empty() {
+        println!("  No timesheets found, skipping template compilation");
+    }
+    for company in companies.iter().filter(|company| config.companies.contains_key(*company)) {
+        let res = compile_templates(&biller, company, billdate);
+        if let Err(e) = &res {
+            eprintln!("{}", e);
+        }
+        report(res.is_ok(), &format!("templates compile for {}", company), &mut problems);
+    }
+
+    if problems > 0 {
+        return Err(CheckError::Failed(problems));
+    }
+    println!("Config is fine");
+    Ok(())
+}
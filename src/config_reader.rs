@@ -1,27 +1,38 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
 use std::fs::{self};
-use serde_yaml::{self, Value};
+use std::path::{Path, PathBuf};
+use chrono::NaiveDate;
+use dotenv::dotenv;
+use serde_yaml::{self, Mapping, Value};
+use crate::date_utils;
 use serde_yaml::Error as YamlError;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum ExtractError {
-    #[error("YAML parsing error")]
+    #[error("YAML parsing error: {0}")]
     YamlParseError(#[from] YamlError),
-    #[error("Missing data")]
-    MissingData,
+    #[error("Invalid config override {0:?}, expected key.path=value")]
+    InvalidOverride(String),
+    #[error("No config entry for company {0:?}")]
+    MissingCompany(String),
+    #[error("csv.delimiter {0:?} is not an ASCII character, the csv reader splits on single bytes")]
+    InvalidDelimiter(char),
     #[error("Error reading yaml")]
     Error(#[from] std::io::Error)
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct BankConfig {
+    pub bank_name: String,
     pub bic: String,
     pub iban: String,
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct CompanyAddress {
     pub name: String,
     pub city: String,
@@ -30,7 +41,300 @@ pub struct CompanyAddress {
 
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonFormat {
+    /// Detected from the fields of the first entry
+    #[default]
+    Auto,
+    Toggl,
+    Clockify,
+    /// `[{"date": "2024-12-03", "minutes": 90, "description": "..."}]`
+    Generic,
+}
+
+/// Where the time entries of a company come from. Paths may contain `{month}`, which is replaced by `YYYY-MM`
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SourceConfig {
+    /// `<data>/YYYY-MM/<company>.csv`
+    Csv,
+    Ics {
+        path: PathBuf,
+        /// Only events with this category, for calendars shared by several companies
+        #[serde(default)]
+        category: Option<String>,
+    },
+    Json {
+        path: PathBuf,
+        #[serde(default)]
+        format: JsonFormat,
+        /// Only entries of this client, for exports covering several companies
+        #[serde(default)]
+        client: Option<String>,
+    },
+    Sqlite {
+        path: PathBuf,
+        #[serde(default = "default_timesheet_table")]
+        table: String,
+    },
+}
+
+fn default_timesheet_table() -> String {
+    String::from("timesheet")
+}
+
+impl SourceConfig {
+    fn resolve(&mut self, root: &Path) {
+        match self {
+            SourceConfig::Csv => {},
+            SourceConfig::Ics { path, .. } | SourceConfig::Json { path, .. } | SourceConfig::Sqlite { path, .. } => {
+                *path = root.join(&path);
+            },
+        }
+    }
+}
+
+/// How entries are rounded to the billing increment
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rounding {
+    #[default]
+    Up,
+    Nearest,
+    Down,
+}
+
+/// Contract terms beyond a single hourly fee
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RateCard {
+    /// Hourly fee per activity (the `Activity` column). Entries without an activity are billed at `hourly_fee`
+    #[serde(default)]
+    pub activities: BTreeMap<String, f64>,
+    /// Each entry is billed in multiples of this many minutes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub increment: Option<i32>,
+    #[serde(default)]
+    pub rounding: Rounding,
+    /// Percent added to work on Saturdays and Sundays
+    #[serde(default)]
+    pub weekend_surcharge: f64,
+    /// The most that is billed (net) for a single day
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub day_cap: Option<f64>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interval {
+    #[default]
+    Monthly,
+    Quarterly,
+    Yearly,
+    /// A single fixed-price item, e.g. a milestone
+    Once,
+}
+
+impl Interval {
+    /// None for `once`
+    pub fn months(self) -> Option<u32> {
+        match self {
+            Interval::Monthly => Some(1),
+            Interval::Quarterly => Some(3),
+            Interval::Yearly => Some(12),
+            Interval::Once => None,
+        }
+    }
+}
+
+/// How often a company is billed. A bill covers the period containing the bill date
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BillingPeriod {
+    #[default]
+    Monthly,
+    Quarterly,
+    /// Monday to Sunday
+    Weekly,
+    /// Two weeks from Monday, counted from Monday 2024-01-01
+    Biweekly,
+}
+
+/// A fixed amount billed every `interval` from `start` (until `end`), e.g. a retainer or hosting fee
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RecurringItem {
+    pub description: String,
+    pub amount: f64,
+    #[serde(default)]
+    pub interval: Interval,
+    pub start: NaiveDate,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<NaiveDate>,
+}
+
+/// Prepaid hours, billed at `price` in the period of `date`. Hours worked from then on are drawn from it until it is used up
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Contingent {
+    pub hours: f64,
+    pub price: f64,
+    pub date: NaiveDate,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectBilling {
+    /// One bill with a section per project
+    #[default]
+    Sections,
+    /// One bill per project, plus one for entries without project and the fixed items
+    Separate,
+}
+
+/// A project, purchase order or cost center of a company (the `Project` column)
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ProjectConfig {
+    /// Shown on the bill instead of the key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purchase_order: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_center: Option<String>,
+}
+
+/// A discount or surcharge, either `percent` or a fixed `amount`. With an `activity` it applies to each line of that
+/// activity, otherwise to the whole bill
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Adjustment {
+    pub reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub percent: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub activity: Option<String>,
+}
+
+/// When a bill is due, e.g. `business_days: 10`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "PaymentTermsFields", into = "PaymentTermsFields")]
+pub enum PaymentTerms {
+    /// Calendar days after the bill date
+    Net(u32),
+    /// Days after the bill date that are neither weekends nor holidays of `bill_config.calendar`
+    BusinessDays(u32),
+    /// Days after the end of the month of the bill date
+    EndOfMonth(u32),
+}
+
+// serde_yaml only reads enums from tagged values, the config has a map with one of the keys
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct PaymentTermsFields {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    net: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    business_days: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    end_of_month: Option<u32>,
+}
+
+impl TryFrom<PaymentTermsFields> for PaymentTerms {
+    type Error = String;
+
+    fn try_from(fields: PaymentTermsFields) -> Result<Self, Self::Error> {
+        match (fields.net, fields.business_days, fields.end_of_month) {
+            (Some(days), None, None) => Ok(PaymentTerms::Net(days)),
+            (None, Some(days), None) => Ok(PaymentTerms::BusinessDays(days)),
+            (None, None, Some(days)) => Ok(PaymentTerms::EndOfMonth(days)),
+            _ => Err(String::from("payment_terms needs exactly one of net, business_days or end_of_month")),
+        }
+    }
+}
+
+impl From<PaymentTerms> for PaymentTermsFields {
+    fn from(terms: PaymentTerms) -> Self {
+        match terms {
+            PaymentTerms::Net(days) => PaymentTermsFields { net: Some(days), ..Default::default() },
+            PaymentTerms::BusinessDays(days) => PaymentTermsFields { business_days: Some(days), ..Default::default() },
+            PaymentTerms::EndOfMonth(days) => PaymentTermsFields { end_of_month: Some(days), ..Default::default() },
+        }
+    }
+}
+
+/// 10 business days
+impl Default for PaymentTerms {
+    fn default() -> Self {
+        PaymentTerms::BusinessDays(10)
+    }
+}
+
+impl PaymentTerms {
+    /// As stated on the bill, e.g. "innerhalb von 10 Werktagen"
+    pub fn describe(&self) -> String {
+        let days = |count: u32, unit: &str| format!("{} {}{}", count, unit, if count == 1 { "" } else { "en" });
+        match *self {
+            PaymentTerms::Net(count) => format!("innerhalb von {}", days(count, "Tag")),
+            PaymentTerms::BusinessDays(count) => format!("innerhalb von {}", days(count, "Werktag")),
+            PaymentTerms::EndOfMonth(0) => String::from("bis zum Monatsende"),
+            PaymentTerms::EndOfMonth(count) => format!("innerhalb von {} nach Monatsende", days(count, "Tag")),
+        }
+    }
+}
+
+/// Where the service period (Leistungszeitraum) of a bill comes from
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServicePeriod {
+    /// The first to the last day with entries, the billing period for bills with fixed items only
+    #[default]
+    Entries,
+    BillingPeriod,
+}
+
+/// Early payment discount: `percent` off the total when paid within `days` of the bill date
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct Skonto {
+    pub percent: f64,
+    pub days: u32,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnExceed {
+    #[default]
+    Warn,
+    /// Refuse to bill beyond the budget
+    Stop,
+}
+
+/// A limit on the billed hours or net amount, from `start` to `end`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Budget {
+    pub name: String,
+    /// Only count the hours and amounts of this project
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hours: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<NaiveDate>,
+    /// Warn once this share (in percent) is used
+    #[serde(default = "default_warn_at")]
+    pub warn_at: f64,
+    #[serde(default)]
+    pub on_exceed: OnExceed,
+}
+
+fn default_warn_at() -> f64 {
+    80.0
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct CompanyConfig {
     pub email: String,
     pub subject: String,
@@ -38,10 +342,58 @@ pub struct CompanyConfig {
     pub greeting_from: String,
     pub hourly_fee: f64,
     pub address: CompanyAddress,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceConfig>,
+    /// ISO code of the currency the company is billed in
+    #[serde(default = "default_currency")]
+    pub currency: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_card: Option<RateCard>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recurring: Vec<RecurringItem>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contingents: Vec<Contingent>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub budgets: Vec<Budget>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub projects: BTreeMap<String, ProjectConfig>,
+    #[serde(default)]
+    pub project_billing: ProjectBilling,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub discounts: Vec<Adjustment>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub surcharges: Vec<Adjustment>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skonto: Option<Skonto>,
+    /// Overrides `bill_config.payment_terms`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payment_terms: Option<PaymentTerms>,
+    #[serde(default)]
+    pub billing_period: BillingPeriod,
+    /// Debtor account in the DATEV export, instead of `datev.debtor_account`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debtor_account: Option<u32>,
 }
 
-#[derive(Debug, Deserialize)]
+impl CompanyConfig {
+    /// The configured key of a project given in a timesheet, which may differ in case
+    pub fn project_key(&self, project: &str) -> Option<&str> {
+        self.projects.keys().find(|key| key.eq_ignore_ascii_case(project.trim())).map(String::as_str)
+    }
+
+    /// The name of a project as shown on the bill
+    pub fn project_name<'a>(&'a self, key: &'a str) -> &'a str {
+        self.projects.get(key).and_then(|project| project.name.as_deref()).unwrap_or(key)
+    }
+}
+
+fn default_currency() -> String {
+    String::from("EUR")
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct BillConfig {
+  pub header: String,
   pub company: String,
   pub name: String,
   pub street: String,
@@ -50,29 +402,247 @@ pub struct BillConfig {
   pub postcode: String,
   pub email: String,
   pub telephone: String,
+  pub telephone_concise: String,
+  pub company_web: String,
   pub bill_item: String,
   pub bill_item_description: String,
   pub vat_id: String,
   pub tax_id: String,
   pub color: String,
+  pub text: String,
+  /// `en-us`, `en-eu`, `en-in` or `en-by`. Defaults to the format of the currency
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub number_format: Option<String>,
+  /// The holidays skipped by business day payment terms and flagged in timesheets
+  pub calendar: date_utils::Calendar,
+  /// Unless the company has its own. Not in the defaults layer, where it would be merged with the configured terms
+  #[serde(default)]
+  pub payment_terms: PaymentTerms,
+  /// The Leistungszeitraum stated on the bills
+  pub service_period: ServicePeriod,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct MailConfig {
     pub email: String,
+    pub email_from: String,
     pub imap_server: String,
     pub imap_port: u16,
     pub email_text: String,
 }
 
-#[derive(Debug, Deserialize)]
+/// A stage of the dunning process, sent `after_days` after the due date or the previous letter
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DunningLevel {
+    pub name: String,
+    pub after_days: i64,
+    #[serde(default)]
+    pub fee: f64,
+    /// Charge default interest from the due date on
+    #[serde(default)]
+    pub interest: bool,
+    /// Opening paragraph of the letter
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DunningConfig {
+    #[serde(default = "default_dunning_levels")]
+    pub levels: Vec<DunningLevel>,
+    /// Fixed default interest in percent per year, instead of the statutory rate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interest_rate: Option<f64>,
+    /// Percentage points above the Basiszinssatz: 9 between businesses, 5 for consumers (§ 288 BGB)
+    #[serde(default = "default_interest_points")]
+    pub interest_points: f64,
+    /// Verzugspauschale for late bills in euros (§ 288 Abs. 5 BGB), 0 for consumers
+    #[serde(default = "default_flat_fee")]
+    pub flat_fee: f64,
+    /// Days the customer is given to pay after a letter
+    #[serde(default = "default_dunning_days")]
+    pub days: i64,
+}
+
+impl Default for DunningConfig {
+    fn default() -> Self {
+        DunningConfig {
+            levels: default_dunning_levels(),
+            interest_rate: None,
+            interest_points: default_interest_points(),
+            flat_fee: default_flat_fee(),
+            days: default_dunning_days(),
+        }
+    }
+}
+
+fn default_dunning_levels() -> Vec<DunningLevel> {
+    vec![
+        DunningLevel {
+            name: String::from("Zahlungserinnerung"),
+            after_days: 7,
+            fee: 0.0,
+            interest: false,
+            text: String::from("sicherlich ist es Ihrer Aufmerksamkeit entgangen, dass die folgende Rechnung noch nicht beglichen ist."),
+        },
+        DunningLevel {
+            name: String::from("1. Mahnung"),
+            after_days: 14,
+            fee: 5.0,
+            interest: true,
+            text: String::from("trotz unserer Zahlungserinnerung ist die folgende Rechnung weiterhin offen."),
+        },
+        DunningLevel {
+            name: String::from("2. Mahnung"),
+            after_days: 14,
+            fee: 10.0,
+            interest: true,
+            text: String::from("leider konnten wir auch nach unserer ersten Mahnung keinen Zahlungseingang für die folgende Rechnung feststellen."),
+        },
+    ]
+}
+
+fn default_dunning_days() -> i64 {
+    10
+}
+
+fn default_interest_points() -> f64 {
+    9.0
+}
+
+fn default_flat_fee() -> f64 {
+    40.0
+}
+
+/// The Buchungsstapel export for the tax advisor
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DatevConfig {
+    /// Beraternummer
+    pub consultant: u32,
+    /// Mandantennummer
+    pub client: u32,
+    /// Month the fiscal year starts in
+    #[serde(default = "default_fiscal_year_start")]
+    pub fiscal_year_start: u32,
+    /// Sachkontenlänge
+    #[serde(default = "default_account_length")]
+    pub account_length: u32,
+    /// Revenue account per VAT rate in percent, by default the SKR03 ones
+    #[serde(default = "default_revenue_accounts")]
+    pub revenue_accounts: BTreeMap<u32, u32>,
+    /// Collective debtor account for companies without their own
+    #[serde(default = "default_debtor_account")]
+    pub debtor_account: u32,
+}
+
+fn default_fiscal_year_start() -> u32 {
+    1
+}
+
+fn default_account_length() -> u32 {
+    4
+}
+
+fn default_revenue_accounts() -> BTreeMap<u32, u32> {
+    BTreeMap::from([(19, 8400), (7, 8300)])
+}
+
+fn default_debtor_account() -> u32 {
+    10000
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CsvConfig {
+    /// chrono format strings or one of the presets `german`, `iso` and `us`
+    pub date_formats: Vec<String>,
+    /// Detected from the header line if not set
+    pub delimiter: Option<char>,
+}
+
+impl CsvConfig {
+    /// The configured delimiter as the byte the csv reader and writer take
+    pub fn delimiter_byte(&self) -> Result<Option<u8>, ExtractError> {
+        self.delimiter.map(|delimiter| u8::try_from(delimiter).ok().filter(u8::is_ascii).ok_or(ExtractError::InvalidDelimiter(delimiter))).transpose()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PathsConfig {
+    pub data: PathBuf,
+    pub bills: PathBuf,
+    pub db: PathBuf,
+    /// ECB csv with the exchange rates, only needed for companies not billed in euros
+    pub rates: PathBuf,
+    /// csv of the Basiszinssatz and the day it applies from, for the statutory default interest
+    pub base_rates: PathBuf,
+    /// Directory of the project config. Relative paths and the typst templates are resolved against it
+    #[serde(skip)]
+    pub root: PathBuf,
+}
+
+impl PathsConfig {
+    fn resolve(&mut self, root: &Path) {
+        self.root = root.to_path_buf();
+        self.data = root.join(&self.data);
+        self.bills = root.join(&self.bills);
+        self.db = root.join(&self.db);
+        self.rates = root.join(&self.rates);
+        self.base_rates = root.join(&self.base_rates);
+    }
+
+    pub fn data_dir(&self, billdate: &NaiveDate) -> PathBuf {
+        self.data.join(date_utils::month_dir_name(billdate))
+    }
+
+    pub fn bills_dir(&self, billdate: &NaiveDate) -> PathBuf {
+        self.bills.join(date_utils::month_dir_name(billdate))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     pub bank_config: BankConfig,
     pub bill_config: BillConfig,
     pub mailconfig: MailConfig,
+    pub companies: BTreeMap<String, CompanyConfig>,
+    pub paths: PathsConfig,
+    pub csv: CsvConfig,
+    /// Not in the defaults layer, where the levels would be merged with the configured ones
+    #[serde(default)]
+    pub dunning: DunningConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datev: Option<DatevConfig>,
+}
+
+static DEFAULTS: &str = "
+mailconfig:
+  imap_port: 993
+bill_config:
+  country: DE
+  color: \"#99d0ba\"
+  calendar: BE
+  service_period: entries
+paths:
+  data: data
+  bills: bills
+  db: db.sql
+  rates: rates.csv
+  base_rates: base_rates.csv
+csv:
+  date_formats: [german, iso, us]
+";
+
+static ENV_PREFIX: &str = "RUSTBILL_";
+
+/// The shared config in `$XDG_CONFIG_HOME/rustbill/config.yaml`, falling back to `~/.config`
+fn global_config_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("rustbill").join("config.yaml"))
 }
 
-fn read_config_yaml(path: &str) -> Result<Value, ExtractError> {
+fn read_config_yaml(path: &Path) -> Result<Value, ExtractError> {
     let yaml_content = fs::read_to_string(path)?;
 
     let config: Value = serde_yaml::from_str(&yaml_content)?;
@@ -80,24 +650,138 @@ fn read_config_yaml(path: &str) -> Result<Value, ExtractError> {
     Ok(config)
 }
 
-pub fn read_config(path: &str) -> Result<Config, ExtractError> {
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
 
-    println!("Reading config from {:?}", path);
-    
-    let yaml_content = fs::read_to_string(path)?;
+// Overrides are plain strings, so we keep the type of the value they replace (e.g. a postcode stays a string)
+fn parse_override(existing: Option<&Value>, raw: &str) -> Value {
+    match existing {
+        Some(Value::String(_)) => Value::String(raw.to_string()),
+        _ => serde_yaml::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string())),
+    }
+}
+
+fn set_override(config: &mut Value, key_path: &[&str], raw: &str) {
+    let existing = key_path.iter().try_fold(&*config, |value, key| value.get(*key));
+
+    let mut overlay = parse_override(existing, raw);
+    for key in key_path.iter().rev() {
+        let mut map = Mapping::new();
+        map.insert(Value::from(*key), overlay);
+        overlay = Value::Mapping(map);
+    }
+    merge(config, overlay);
+}
+
+// Env var names are usually upper case, so each segment is matched against the existing keys ignoring case:
+// `COMPANIES__SAMPLECOMPANY__HOURLY_FEE` is `companies.SampleCompany.hourly_fee`. Keys not in the config are lowercased
+fn env_key_path(config: &Value, key: &str) -> Vec<String> {
+    let mut current = Some(config);
+    key.split("__")
+        .map(|segment| {
+            let existing = current
+                .and_then(Value::as_mapping)
+                .and_then(|map| map.keys().filter_map(Value::as_str).find(|existing| existing.eq_ignore_ascii_case(segment)));
+            let key = existing.map_or_else(|| segment.to_lowercase(), str::to_string);
+            current = current.and_then(|value| value.get(key.as_str()));
+            key
+        })
+        .collect()
+}
+
+/// `RUSTBILL_MAILCONFIG__IMAP_SERVER=...` overrides `mailconfig.imap_server`
+fn apply_env_overrides(config: &mut Value) {
+    dotenv().ok();
 
-    let config: Config = serde_yaml::from_str(&yaml_content)?;
+    let mut overrides: Vec<(String, String)> = env::vars()
+        .filter_map(|(key, value)| {
+            let key = key.strip_prefix(ENV_PREFIX)?;
+            if !key.contains("__") {
+                return None
+            }
+            Some((key.to_string(), value))
+        })
+        .collect();
+    overrides.sort();
+
+    for (key, value) in overrides {
+        let key_path = env_key_path(config, &key);
+        println!("Overriding {} from the environment", key_path.join("."));
+        set_override(config, &key_path.iter().map(String::as_str).collect::<Vec<_>>(), &value);
+    }
+}
+
+/// CLI overrides in the form `section.key=value`
+fn apply_cli_overrides(config: &mut Value, overrides: &[String]) -> Result<(), ExtractError> {
+    for item in overrides {
+        let Some((key, value)) = item.split_once('=') else {return Err(ExtractError::InvalidOverride(item.to_string()))};
+        let key_path: Vec<&str> = key.trim().split('.').collect();
+        set_override(config, &key_path, value);
+    }
+    Ok(())
+}
+
+/// Merges the config layers: defaults, global config, project config, `RUSTBILL_*` env vars, CLI overrides
+fn read_config_layers(path: &str, overrides: &[String]) -> Result<Value, ExtractError> {
+    let mut config: Value = serde_yaml::from_str(DEFAULTS)?;
+    let mut found_global = false;
+
+    if let Some(global_path) = global_config_path().filter(|path| path.exists()) {
+        println!("Reading global config from {:?}", global_path);
+        merge(&mut config, read_config_yaml(&global_path)?);
+        found_global = true;
+    }
+
+    // The project config is only optional if there is a global one
+    let project_path = Path::new(path);
+    if project_path.exists() || !found_global {
+        println!("Reading config from {:?}", path);
+        merge(&mut config, read_config_yaml(project_path)?);
+    }
+
+    apply_env_overrides(&mut config);
+    apply_cli_overrides(&mut config, overrides)?;
 
     Ok(config)
 }
 
-pub fn get_company_config(path: &str, company_str: &str) -> Result<CompanyConfig, ExtractError> {
-    let config = read_config_yaml(path)?;
+/// The directory containing the project config
+pub fn config_root(path: &str) -> PathBuf {
+    match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+pub fn read_config(path: &str, overrides: &[String]) -> Result<Config, ExtractError> {
+    let config_value = read_config_layers(path, overrides)?;
 
-    let Some(companies) = config.get("companies") else {return Err(ExtractError::MissingData)};
-    let Some(company) = companies.get(company_str) else {return Err(ExtractError::MissingData)};
+    let mut config: Config = serde_yaml::from_value(config_value)?;
 
-    let company_config: CompanyConfig = serde_yaml::from_value(company.clone())?;
+    let root = std::path::absolute(config_root(path))?;
+    config.paths.resolve(&root);
+    for company in config.companies.values_mut() {
+        if let Some(source) = &mut company.source {
+            source.resolve(&root);
+        }
+    }
 
-    Ok(company_config)
-}
\ No newline at end of file
+    Ok(config)
+}
+
+pub fn get_company_config<'a>(config: &'a Config, company_str: &str) -> Result<&'a CompanyConfig, ExtractError> {
+    config.companies.get(company_str).ok_or_else(|| ExtractError::MissingCompany(company_str.to_string()))
+}
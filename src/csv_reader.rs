@@ -1,62 +1,207 @@
 use std::{
-    path::Path,
+    path::{Path, PathBuf},
     error::Error,
     fs,
-    fs::File,
 };
-use csv::ReaderBuilder;
-use serde::{Deserialize};
-use chrono::NaiveDate;
-use chrono::Datelike;
+use csv::{ReaderBuilder, StringRecord};
+use chrono::{NaiveDate, NaiveTime};
+use thiserror::Error;
+use crate::config_reader::{CsvConfig, ExtractError};
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Debug, Error)]
+pub enum CsvError {
+    #[error("Could not read {file:?}")]
+    Io { file: PathBuf, source: std::io::Error },
+    #[error("{file:?}, line {line}: {source}")]
+    Parse { file: PathBuf, line: u64, source: csv::Error },
+    #[error("{file:?}: missing column {column:?}")]
+    MissingColumn { file: PathBuf, column: String },
+    #[error("{file:?}, line {line}: invalid {column} {value:?}")]
+    InvalidValue { file: PathBuf, line: u64, column: String, value: String },
+    #[error(transparent)]
+    Config(#[from] ExtractError),
+}
+
+type CsvResult<T> = Result<T, CsvError>;
+
+#[derive(Debug, Clone)]
 pub struct Record {
-    #[allow(unused)]
-    pub date: String,
+    pub date: NaiveDate,
     pub minutes: i32,
-    #[allow(unused)]
     pub description: String,
-    pub year: Option<i32>,
-    pub month: Option<u32>,
-    pub day: Option<u32>,
+    /// Looked up in the rate card of the company. Empty if not given
+    pub activity: String,
+    /// A project of the company. Empty if not given
+    pub project: String,
+    /// Line in the source file, for error messages
+    pub line: u64,
+}
+
+#[derive(Debug)]
+enum Duration {
+    Minutes(usize),
+    Hours(usize),
+    StartEnd(usize, usize),
+}
+
+#[derive(Debug)]
+struct Columns {
+    date: usize,
+    duration: Duration,
+    description: Option<usize>,
+    activity: Option<usize>,
+    project: Option<usize>,
+}
+
+/// Expands the named presets (iso, german, us) into chrono format strings.
+/// Two-digit years come first, as `%Y` would happily parse `24` as the year 24
+fn expand_date_formats(formats: &[String]) -> Vec<String> {
+    formats
+        .iter()
+        .flat_map(|format| match format.to_lowercase().as_str() {
+            "iso" => vec![String::from("%Y-%m-%d")],
+            "german" => vec![String::from("%d.%m.%y"), String::from("%d.%m.%Y")],
+            "us" => vec![String::from("%m/%d/%y"), String::from("%m/%d/%Y")],
+            _ => vec![format.clone()],
+        })
+        .collect()
 }
 
+/// The format new entries are written in: the first configured one, presets with four-digit years
+pub fn write_date_format(csv_config: &CsvConfig) -> String {
+    match csv_config.date_formats.first().map(|format| format.to_lowercase()).as_deref() {
+        None | Some("german") => String::from("%d.%m.%Y"),
+        Some("iso") => String::from("%Y-%m-%d"),
+        Some("us") => String::from("%m/%d/%Y"),
+        Some(_) => csv_config.date_formats[0].clone(),
+    }
+}
+
+fn parse_date(value: &str, formats: &[String]) -> Option<NaiveDate> {
+    formats.iter().find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+}
+
+/// Accepts both `1.5` and `1,5`
+fn parse_decimal(value: &str) -> Option<f64> {
+    value.replace(',', ".").parse().ok()
+}
+
+fn parse_time(value: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(value, "%H:%M:%S"))
+        .ok()
+}
 
-pub fn get_ymd(datestr: &str) -> Result<(i32, u32, u32), Box<dyn Error>> {
-    let date = NaiveDate::parse_from_str(datestr, "%d.%m.%Y")?;
-    let (year, month, day) = (date.year(), date.month(), date.day());
-    Ok((year, month, day))
+pub fn detect_delimiter(header: &str) -> u8 {
+    [b';', b',', b'\t']
+        .into_iter()
+        .max_by_key(|delimiter| header.bytes().filter(|b| b == delimiter).count())
+        .unwrap_or(b';')
 }
 
-pub fn read_csv<P: AsRef<Path>>(path: P) -> Result<Vec<Record>, Box<dyn Error>> {
-    let file = File::open(path)?;
+fn find_columns(headers: &StringRecord, file: &Path) -> CsvResult<Columns> {
+    let find = |name: &str| headers.iter().position(|header| header.trim().eq_ignore_ascii_case(name));
+    let missing = |column: &str| CsvError::MissingColumn { file: file.to_path_buf(), column: column.to_string() };
+
+    let date = find("Date").ok_or_else(|| missing("Date"))?;
+
+    let duration = if let Some(idx) = find("Minutes") {
+        Duration::Minutes(idx)
+    } else if let Some(idx) = find("Hours") {
+        Duration::Hours(idx)
+    } else {
+        match (find("Start"), find("End")) {
+            (Some(start), Some(end)) => Duration::StartEnd(start, end),
+            _ => return Err(missing("Minutes, Hours or Start/End")),
+        }
+    };
+
+    Ok(Columns { date, duration, description: find("Description"), activity: find("Activity"), project: find("Project") })
+}
+
+fn parse_record(row: &StringRecord, columns: &Columns, formats: &[String], file: &Path, line: u64) -> CsvResult<Record> {
+    let field = |idx: usize| row.get(idx).unwrap_or("").trim();
+    let invalid = |column: &str, value: &str| CsvError::InvalidValue {
+        file: file.to_path_buf(),
+        line,
+        column: column.to_string(),
+        value: value.to_string(),
+    };
+
+    let date_str = field(columns.date);
+    let date = parse_date(date_str, formats).ok_or_else(|| invalid("date", date_str))?;
+
+    let minutes = match columns.duration {
+        Duration::Minutes(idx) => {
+            let value = field(idx);
+            let minutes = parse_decimal(value).ok_or_else(|| invalid("minutes", value))?;
+            minutes.round() as i32
+        },
+        Duration::Hours(idx) => {
+            let value = field(idx);
+            let hours = parse_decimal(value).ok_or_else(|| invalid("hours", value))?;
+            (hours * 60.0).round() as i32
+        },
+        Duration::StartEnd(start_idx, end_idx) => {
+            let (start_str, end_str) = (field(start_idx), field(end_idx));
+            let start = parse_time(start_str).ok_or_else(|| invalid("start time", start_str))?;
+            let end = parse_time(end_str).ok_or_else(|| invalid("end time", end_str))?;
+            let mut minutes = (end - start).num_minutes();
+            // Work past midnight
+            if minutes < 0 {
+                minutes += 24 * 60;
+            }
+            minutes as i32
+        },
+    };
+
+    let description = columns.description.map(field).unwrap_or("").to_string();
+    let activity = columns.activity.map(field).unwrap_or("").to_string();
+    let project = columns.project.map(field).unwrap_or("").to_string();
+
+    Ok(Record { date, minutes, description, activity, project, line })
+}
+
+pub fn read_csv<P: AsRef<Path>>(path: P, csv_config: &CsvConfig) -> CsvResult<Vec<Record>> {
+    let file = path.as_ref();
+    let content = fs::read_to_string(file).map_err(|source| CsvError::Io { file: file.to_path_buf(), source })?;
+    let content = content.trim_start_matches('\u{feff}');
 
     println!("found file {:?}", file);
 
-    let mut records: Vec<Record> = Vec::new();
-    let mut counts: i32 = 0;
+    let delimiter = match csv_config.delimiter_byte()? {
+        Some(delimiter) => delimiter,
+        None => detect_delimiter(content.lines().next().unwrap_or("")),
+    };
+    let formats = expand_date_formats(&csv_config.date_formats);
 
     let mut rdr = ReaderBuilder::new()
         .has_headers(true)
-        .delimiter(b';')
-        .from_reader(file);
-
-    for result in rdr.deserialize() {
-        let mut record: Record = result?;
-        let datestr = &record.date;
-        let ymd = get_ymd(datestr)?;
-        record.year = Some(ymd.0);
-        record.month = Some(ymd.1);
-        record.day = Some(ymd.2);
-        counts += 1;
-        records.push(record);
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(content.as_bytes());
+
+    let headers = rdr.headers().map_err(|source| CsvError::Parse { file: file.to_path_buf(), line: 1, source })?.clone();
+    let columns = find_columns(&headers, file)?;
+
+    let mut records: Vec<Record> = Vec::new();
+
+    for result in rdr.records() {
+        let row = result.map_err(|source| {
+            let line = source.position().map(|pos| pos.line()).unwrap_or(0);
+            CsvError::Parse { file: file.to_path_buf(), line, source }
+        })?;
+        let line = row.position().map(|pos| pos.line()).unwrap_or(0);
+        if row.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        records.push(parse_record(&row, &columns, &formats, file, line)?);
     }
-    println!("Found {} table entries", counts);
+    println!("Found {} table entries", records.len());
     Ok(records)
-    }
+}
 
-pub fn extract_minutes_total(records: &Vec<Record>) -> Result<i32, Box<dyn Error>> {
+pub fn extract_minutes_total(records: &[Record]) -> Result<i32, Box<dyn Error>> {
     let mut minutes_total = 0;
 
     for record in records {
@@ -81,6 +226,6 @@ pub fn find_all_companies(dir_path: &Path) -> Result<Vec<String>, Box<dyn Error>
             }
         })
         .collect();
-    
+
     Ok(csv_files)
-}
\ No newline at end of file
+}
@@ -1,25 +1,169 @@
+use std::fmt;
+use std::str::FromStr;
 use bdays::HolidayCalendar;
+use bdays::calendars::de::GermanState;
+use bdays::calendars::us::USSettlement;
 use chrono::{Datelike, Duration, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use crate::config_reader::{BillingPeriod, PaymentTerms};
 
 #[derive(Debug, Error)]
 pub enum DateError {
-    #[error("No total amount could be computed")]
+    #[error("Date could not be computed")]
     CalculationError,
+    #[error("Unknown holiday calendar {0:?}, expected a German state (e.g. BE), ANY, US or NONE")]
+    UnknownCalendar(String),
 }
 
-fn get_first_of_next_month(year: i32, month: u32) -> Result<NaiveDate, DateError> {
-    let first_of_next_month; 
+static GERMAN_STATES: [(&str, GermanState); 17] = [
+    ("BW", GermanState::BW), ("BY", GermanState::BY), ("BE", GermanState::BE), ("BB", GermanState::BB),
+    ("HB", GermanState::HB), ("HH", GermanState::HH), ("HE", GermanState::HE), ("MV", GermanState::MV),
+    ("NI", GermanState::NI), ("NW", GermanState::NW), ("RP", GermanState::RP), ("SL", GermanState::SL),
+    ("SN", GermanState::SN), ("ST", GermanState::ST), ("SH", GermanState::SH), ("TH", GermanState::TH),
+    // Holidays of any German state
+    ("ANY", GermanState::ANY),
+];
+
+/// The holidays skipped when counting business days, `bill_config.calendar`
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Calendar {
+    German(GermanState),
+    UnitedStates,
+    /// Only weekends are skipped
+    WeekendsOnly,
+}
 
-    if month == 12 {
-        first_of_next_month = NaiveDate::from_ymd_opt(year+1, 1, 1);
+/// Berlin
+impl Default for Calendar {
+    fn default() -> Self {
+        Calendar::German(GermanState::BE)
     }
-    else {
-        first_of_next_month = NaiveDate::from_ymd_opt(year, month+1, 1);
+}
+
+impl HolidayCalendar<NaiveDate> for Calendar {
+    fn is_holiday(&self, date: NaiveDate) -> bool {
+        match self {
+            Calendar::German(state) => state.is_holiday(date),
+            Calendar::UnitedStates => USSettlement.is_holiday(date),
+            Calendar::WeekendsOnly => false,
+        }
+    }
+}
+
+impl FromStr for Calendar {
+    type Err = DateError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        let code = code.trim().to_uppercase();
+        match code.as_str() {
+            "US" => Ok(Calendar::UnitedStates),
+            "NONE" => Ok(Calendar::WeekendsOnly),
+            _ => GERMAN_STATES
+                .iter()
+                .find(|(state_code, _)| *state_code == code)
+                .map(|(_, state)| Calendar::German(*state))
+                .ok_or(DateError::UnknownCalendar(code)),
+        }
     }
+}
+
+impl TryFrom<String> for Calendar {
+    type Error = DateError;
+
+    fn try_from(code: String) -> Result<Self, Self::Error> {
+        code.parse()
+    }
+}
+
+impl fmt::Display for Calendar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            // The variants are named like the state codes
+            Calendar::German(state) => write!(f, "{:?}", state),
+            Calendar::UnitedStates => write!(f, "US"),
+            Calendar::WeekendsOnly => write!(f, "NONE"),
+        }
+    }
+}
+
+impl From<Calendar> for String {
+    fn from(calendar: Calendar) -> Self {
+        calendar.to_string()
+    }
+}
+
+fn get_first_of_next_month(year: i32, month: u32) -> Result<NaiveDate, DateError> {
+    let first_of_next_month = if month == 12 {
+        NaiveDate::from_ymd_opt(year+1, 1, 1)
+    }
+    else {
+        NaiveDate::from_ymd_opt(year, month+1, 1)
+    };
     first_of_next_month.ok_or(DateError::CalculationError)
 }
 
+/// First and last day of the month containing `date`
+pub fn month_bounds(date: &NaiveDate) -> Result<(NaiveDate, NaiveDate), DateError> {
+    let first = date.with_day(1).ok_or(DateError::CalculationError)?;
+    let last = get_first_of_next_month(date.year(), date.month())? - Duration::days(1);
+    Ok((first, last))
+}
+
+/// First and last day of the billing period containing `date`
+pub fn period_bounds(date: &NaiveDate, billing_period: BillingPeriod) -> Result<(NaiveDate, NaiveDate), DateError> {
+    let weeks = |count: i64| -> Result<(NaiveDate, NaiveDate), DateError> {
+        let monday = *date - Duration::days(date.weekday().num_days_from_monday().into());
+        // Biweekly periods start on every other Monday from 2024-01-01
+        let epoch = NaiveDate::from_ymd_opt(2024, 1, 1).ok_or(DateError::CalculationError)?;
+        let offset = (monday - epoch).num_weeks().rem_euclid(count);
+        let first = monday - Duration::weeks(offset);
+        Ok((first, first + Duration::days(7 * count - 1)))
+    };
+
+    match billing_period {
+        BillingPeriod::Monthly => month_bounds(date),
+        BillingPeriod::Quarterly => {
+            let first_month = (date.month() - 1) / 3 * 3 + 1;
+            let first = NaiveDate::from_ymd_opt(date.year(), first_month, 1).ok_or(DateError::CalculationError)?;
+            let last = month_bounds(&(first + Duration::days(62)))?.1;
+            Ok((first, last))
+        },
+        BillingPeriod::Weekly => weeks(1),
+        BillingPeriod::Biweekly => weeks(2),
+    }
+}
+
+/// The first day of each month `period` touches
+pub fn months_in(period: &(NaiveDate, NaiveDate)) -> Result<Vec<NaiveDate>, DateError> {
+    let mut months = Vec::new();
+    let mut first = month_bounds(&period.0)?.0;
+    while first <= period.1 {
+        months.push(first);
+        first = get_first_of_next_month(first.year(), first.month())?;
+    }
+    Ok(months)
+}
+
+/// Whether `period` is exactly one calendar month
+pub fn is_month(period: &(NaiveDate, NaiveDate)) -> bool {
+    month_bounds(&period.0).is_ok_and(|month| month == *period)
+}
+
+pub fn is_holiday(date: NaiveDate, calendar: Calendar) -> bool {
+    calendar.is_holiday(date)
+}
+
+/// The `YYYY-MM` directory name holding the data and bills of a month
+pub fn month_dir_name(date: &NaiveDate) -> String {
+    format!(
+        "{year}-{month:02}",
+        year = date.year(),
+        month = date.month(),
+    )
+}
+
 pub fn parse_date_or_default(datestr: &str) -> Result<NaiveDate, Box<dyn std::error::Error>> {
     match NaiveDate::parse_from_str(datestr, "%Y-%m-%d") {
         Ok(date) => Ok(date),
@@ -37,12 +181,22 @@ pub fn parse_date_or_default(datestr: &str) -> Result<NaiveDate, Box<dyn std::er
     }
 }
 
-pub fn calculate_due_date(date: NaiveDate) -> Result<NaiveDate, Box<dyn std::error::Error>> {
-    println!("Advancing {:?} 10 working days", date);
+pub fn parse_date_or_today(datestr: &str) -> Result<NaiveDate, Box<dyn std::error::Error>> {
+    if datestr.is_empty() {
+        return Ok(Local::now().date_naive())
+    }
+    Ok(NaiveDate::parse_from_str(datestr, "%Y-%m-%d")?)
+}
+
+pub fn calculate_due_date(date: NaiveDate, terms: PaymentTerms, calendar: Calendar) -> Result<NaiveDate, DateError> {
+    println!("Due {} from {:?}", terms.describe(), date);
+
+    let due_date = match terms {
+        PaymentTerms::Net(days) => date + Duration::days(days.into()),
+        PaymentTerms::BusinessDays(days) => calendar.advance_bdays(date, days.try_into().map_err(|_| DateError::CalculationError)?),
+        PaymentTerms::EndOfMonth(days) => month_bounds(&date)?.1 + Duration::days(days.into()),
+    };
 
-    let cal = bdays::calendars::de::GermanState::BE;
-    let due_date  = cal.advance_bdays(date,  9);
-    
     println!("Due date is {:?}", due_date);
     Ok(due_date)
 }
\ No newline at end of file
@@ -0,0 +1,239 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::{Datelike, Local, NaiveDate};
+use thiserror::Error;
+use crate::config_reader::{Config, DatevConfig};
+use crate::db::{DBEntry, DBError, Db};
+use crate::{date_utils, pdf_gen};
+
+#[derive(Debug, Error)]
+pub enum DatevError {
+    #[error("The DATEV export needs a datev section with at least consultant and client in the config")]
+    MissingConfig,
+    #[error("Invalid month {0}")]
+    InvalidMonth(u32),
+    #[error("No revenue account for {rate} % VAT (bill {billnr}), add it to datev.revenue_accounts")]
+    MissingRevenueAccount { rate: u32, billnr: String },
+    #[error("Could not write {file:?}")]
+    Io { file: PathBuf, source: std::io::Error },
+    #[error(transparent)]
+    Date(#[from] date_utils::DateError),
+    #[error(transparent)]
+    DB(#[from] DBError),
+}
+
+type DatevResult<T> = Result<T, DatevError>;
+
+// Names of the 125 columns of a Buchungsstapel in format version 13
+fn column_names() -> Vec<String> {
+    let mut names: Vec<String> = [
+        "Umsatz (ohne Soll/Haben-Kz)", "Soll/Haben-Kennzeichen", "WKZ Umsatz", "Kurs", "Basis-Umsatz", "WKZ Basis-Umsatz",
+        "Konto", "Gegenkonto (ohne BU-Schlüssel)", "BU-Schlüssel", "Belegdatum", "Belegfeld 1", "Belegfeld 2", "Skonto",
+        "Buchungstext", "Postensperre", "Diverse Adressnummer", "Geschäftspartnerbank", "Sachverhalt", "Zinssperre", "Beleglink",
+    ].map(String::from).to_vec();
+    for number in 1..=8 {
+        names.push(format!("Beleginfo - Art {}", number));
+        names.push(format!("Beleginfo - Inhalt {}", number));
+    }
+    names.extend([
+        "KOST1 - Kostenstelle", "KOST2 - Kostenstelle", "Kost-Menge", "EU-Land u. UStID (Bestimmung)", "EU-Steuersatz (Bestimmung)",
+        "Abw. Versteuerungsart", "Sachverhalt L+L", "Funktionsergänzung L+L", "BU 49 Hauptfunktionstyp", "BU 49 Hauptfunktionsnummer",
+        "BU 49 Funktionsergänzung",
+    ].map(String::from));
+    for number in 1..=20 {
+        names.push(format!("Zusatzinformation - Art {}", number));
+        names.push(format!("Zusatzinformation - Inhalt {}", number));
+    }
+    names.extend([
+        "Stück", "Gewicht", "Zahlweise", "Forderungsart", "Veranlagungsjahr", "Zugeordnete Fälligkeit", "Skontotyp", "Auftragsnummer",
+        "Buchungstyp", "USt-Schlüssel (Anzahlungen)", "EU-Mitgliedstaat (Anzahlungen)", "Sachverhalt L+L (Anzahlungen)",
+        "EU-Steuersatz (Anzahlungen)", "Erlöskonto (Anzahlungen)", "Herkunft-Kz", "Buchungs GUID", "KOST-Datum", "SEPA-Mandatsreferenz",
+        "Skontosperre", "Gesellschaftername", "Beteiligtennummer", "Identifikationsnummer", "Zeichnernummer", "Postensperre bis",
+        "Bezeichnung SoBil-Sachverhalt", "Kennzeichen SoBil-Buchung", "Festschreibung", "Leistungsdatum", "Datum Zuord. Steuerperiode",
+        "Fälligkeit", "Generalumkehr (GU)", "Steuersatz", "Land", "Abrechnungsreferenz", "BVV-Position", "EU-Mitgliedstaat u. UStID (Ursprung)",
+        "EU-Steuersatz (Ursprung)", "Abw. Skontokonto",
+    ].map(String::from));
+    names
+}
+
+// Column indices of the fields rustbill fills
+const UMSATZ: usize = 0;
+const SOLL_HABEN: usize = 1;
+const WKZ_UMSATZ: usize = 2;
+const KURS: usize = 3;
+const BASIS_UMSATZ: usize = 4;
+const WKZ_BASIS_UMSATZ: usize = 5;
+const KONTO: usize = 6;
+const GEGENKONTO: usize = 7;
+const BELEGDATUM: usize = 9;
+const BELEGFELD_1: usize = 10;
+const BUCHUNGSTEXT: usize = 13;
+const BELEGLINK: usize = 19;
+const FAELLIGKEIT: usize = 116;
+
+fn text(value: &str, max_len: usize) -> String {
+    format!("\"{}\"", value.chars().take(max_len).collect::<String>().replace('"', "\"\""))
+}
+
+fn amount(value: f64, decimals: usize) -> String {
+    format!("{:.*}", decimals, value).replace('.', ",")
+}
+
+/// DATEV reads the files as Windows-1252, characters it lacks become `?`
+fn to_windows_1252(content: &str) -> Vec<u8> {
+    content
+        .chars()
+        .map(|char| match char as u32 {
+            code @ (0..=0x7f | 0xa0..=0xff) => code as u8,
+            _ => match char {
+                '€' => 0x80, '‚' => 0x82, '„' => 0x84, '…' => 0x85, '‘' => 0x91, '’' => 0x92,
+                '“' => 0x93, '”' => 0x94, '–' => 0x96, '—' => 0x97,
+                _ => b'?',
+            },
+        })
+        .collect()
+}
+
+/// The first day of the fiscal year `date` is in
+fn fiscal_year_start(datev: &DatevConfig, date: &NaiveDate) -> DatevResult<NaiveDate> {
+    let year = if date.month() < datev.fiscal_year_start { date.year() - 1 } else { date.year() };
+    NaiveDate::from_ymd_opt(year, datev.fiscal_year_start, 1).ok_or(DatevError::InvalidMonth(datev.fiscal_year_start))
+}
+
+fn header(datev: &DatevConfig, period: &(NaiveDate, NaiveDate), description: &str) -> DatevResult<String> {
+    let fields = [
+        text("EXTF", 4),
+        String::from("700"),
+        String::from("21"),
+        text("Buchungsstapel", 14),
+        String::from("13"),
+        Local::now().format("%Y%m%d%H%M%S%3f").to_string(),
+        String::new(),
+        text("RE", 2),
+        text("", 25),
+        text("", 25),
+        datev.consultant.to_string(),
+        datev.client.to_string(),
+        fiscal_year_start(datev, &period.0)?.format("%Y%m%d").to_string(),
+        datev.account_length.to_string(),
+        period.0.format("%Y%m%d").to_string(),
+        period.1.format("%Y%m%d").to_string(),
+        text(description, 30),
+        text("", 2),
+        String::from("1"),
+        String::from("0"),
+        String::from("0"),
+        text("EUR", 3),
+        String::new(),
+        text("", 0),
+        String::new(),
+        String::new(),
+        text("", 2),
+        String::new(),
+        String::new(),
+        text("", 0),
+        text("", 0),
+    ];
+    Ok(fields.join(";"))
+}
+
+/// The booking of a bill: the gross amount from the debtor to the revenue account of its VAT rate
+fn booking(config: &Config, datev: &DatevConfig, bill: &DBEntry, columns: usize) -> DatevResult<String> {
+    let rate = bill.vat_percent();
+    let revenue_account = datev.revenue_accounts
+        .get(&rate)
+        .ok_or_else(|| DatevError::MissingRevenueAccount { rate, billnr: bill.billnr.clone() })?;
+    let company_config = config.companies.get(&bill.company);
+    let debtor_account = company_config.and_then(|company| company.debtor_account).unwrap_or(datev.debtor_account);
+    let name = company_config.map(|company| company.address.name.as_str()).unwrap_or(&bill.company);
+
+    let mut fields = vec![String::new(); columns];
+    fields[UMSATZ] = amount(bill.amount, 2);
+    fields[SOLL_HABEN] = text("S", 1);
+    fields[WKZ_UMSATZ] = text(&bill.currency, 3);
+    if bill.currency != "EUR" {
+        fields[KURS] = amount(bill.exchange_rate, 6);
+        fields[BASIS_UMSATZ] = amount(bill.amount / bill.exchange_rate, 2);
+        fields[WKZ_BASIS_UMSATZ] = text("EUR", 3);
+    }
+    fields[KONTO] = debtor_account.to_string();
+    fields[GEGENKONTO] = revenue_account.to_string();
+    if let Some(billdate) = bill.billdate() {
+        fields[BELEGDATUM] = billdate.format("%d%m").to_string();
+        let project = Some(bill.project.as_str()).filter(|project| !project.is_empty());
+        let period = bill.period().unwrap_or((billdate, billdate));
+        let link = Path::new(&date_utils::month_dir_name(&billdate)).join(pdf_gen::pdf_filename(billdate, &bill.company, project, &period));
+        fields[BELEGLINK] = text(&link.to_string_lossy(), 210);
+    }
+    fields[BELEGFELD_1] = text(&bill.billnr, 36);
+    fields[BUCHUNGSTEXT] = text(&format!("{} {}", name, bill.billnr), 60);
+    if let Some(due) = bill.due_date() {
+        fields[FAELLIGKEIT] = due.format("%d%m%Y").to_string();
+    }
+    Ok(fields.join(";"))
+}
+
+/// The months of `year` each Buchungsstapel covers. A stapel may not cross fiscal years,
+/// so a whole year is split where the fiscal year starts
+fn stapel_months(datev: &DatevConfig, month: Option<u32>) -> DatevResult<Vec<(u32, u32)>> {
+    match (month, datev.fiscal_year_start) {
+        (Some(month), _) if (1..=12).contains(&month) => Ok(vec![(month, month)]),
+        (Some(month), _) => Err(DatevError::InvalidMonth(month)),
+        (None, 1) => Ok(vec![(1, 12)]),
+        (None, start) if (2..=12).contains(&start) => Ok(vec![(1, start - 1), (start, 12)]),
+        (None, start) => Err(DatevError::InvalidMonth(start)),
+    }
+}
+
+/// Writes the bills of `month` (or the whole `year`) as an EXTF Buchungsstapel for DATEV, to `output` or
+/// `EXTF_Buchungsstapel_YYYY_MM.csv` in the bills directory. Cancelled bills are left out.
+/// If the fiscal year does not start in January, a whole year is written as one stapel per fiscal year,
+/// with the months appended to the file name (`YYYY_01-06` and `YYYY_07-12`)
+pub fn export(config: &Config, year: i32, month: Option<u32>, output: Option<&Path>) -> DatevResult<()> {
+    let datev = config.datev.as_ref().ok_or(DatevError::MissingConfig)?;
+    let parts = stapel_months(datev, month)?;
+    let db = Db::open(&config.paths.db)?;
+
+    for &(first_month, last_month) in &parts {
+        let (description, name) = match (first_month, last_month) {
+            (first, last) if first == last => (format!("Rechnungen {:02}/{}", first, year), format!("{}_{:02}", year, first)),
+            (1, 12) => (format!("Rechnungen {}", year), year.to_string()),
+            (first, last) => (format!("Rechnungen {:02}-{:02}/{}", first, last, year), format!("{}_{:02}-{:02}", year, first, last)),
+        };
+        let file = match output {
+            Some(output) if parts.len() > 1 => {
+                let stem = output.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+                let extension = output.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+                output.with_file_name(format!("{}_{:02}-{:02}{}", stem, first_month, last_month, extension))
+            },
+            Some(output) => output.to_path_buf(),
+            None => config.paths.bills.join(format!("EXTF_Buchungsstapel_{}.csv", name)),
+        };
+        write_stapel(config, datev, &db, year, (first_month, last_month), &description, &file)?;
+    }
+    Ok(())
+}
+
+fn write_stapel(config: &Config, datev: &DatevConfig, db: &Db, year: i32, (first_month, last_month): (u32, u32), description: &str, file: &Path) -> DatevResult<()> {
+    let first_day = NaiveDate::from_ymd_opt(year, first_month, 1).ok_or(DatevError::InvalidMonth(first_month))?;
+    let last_day = date_utils::month_bounds(&NaiveDate::from_ymd_opt(year, last_month, 1).ok_or(DatevError::InvalidMonth(last_month))?)?.1;
+
+    let bills: Vec<DBEntry> = db.get_bills_in_months(year, first_month, last_month)?
+        .into_iter()
+        .filter(|bill| bill.status != "cancelled")
+        .collect();
+
+    let columns = column_names();
+    let mut lines = vec![header(datev, &(first_day, last_day), description)?, columns.join(";")];
+    for bill in &bills {
+        lines.push(booking(config, datev, bill, columns.len())?);
+    }
+    let content = lines.join("\r\n") + "\r\n";
+
+    if let Some(dir) = file.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|source| DatevError::Io { file: file.to_path_buf(), source })?;
+    }
+    fs::write(file, to_windows_1252(&content)).map_err(|source| DatevError::Io { file: file.to_path_buf(), source })?;
+    println!("Exported {} bills to {:?}", bills.len(), file);
+    Ok(())
+}
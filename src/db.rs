@@ -1,227 +1,689 @@
-use std::path::Path;
-use rusqlite::{Connection, Result, params};
-use chrono::{NaiveDate, Datelike};
+use std::path::{Path, PathBuf};
+use rusqlite::{Connection, Params, Result, params};
+use chrono::{Days, NaiveDate, Datelike};
 use thiserror::Error;
+use crate::calculate::{self, SkontoTerms};
+use crate::date_utils;
+use crate::interest::Invoiced;
 
 #[derive(Debug, Error)]
 pub enum DBError{
     #[error("DB connection error")]
     DBConnectionError(#[from] rusqlite::Error),
+    #[error("Unknown bill status {0:?}, expected one of {BILL_STATUSES:?}")]
+    InvalidStatus(String),
 }
 
 #[derive(Debug)]
-struct DBEntry {
+pub struct DBEntry {
     // date: NaiveDate,
-    id: i32,
-    year: i32,
-    month: u32,
-    day: u32,
-    company: String,
-    billnr: String,
-    amount: f64,
-    amount_str: String,
-    billnr_int: i32,
+    pub id: i32,
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    pub company: String,
+    pub billnr: String,
+    pub amount: f64,
+    pub amount_str: String,
+    pub billnr_int: i32,
+    pub status: String,
+    pub currency: String,
+    pub exchange_rate: f64,
+    /// Minutes drawn from prepaid contingents
+    pub contingent_minutes: i32,
+    /// Billed minutes and net amount, for budgets
+    pub minutes: i32,
+    pub net: f64,
+    /// The project key for bills of a single project, empty otherwise
+    pub project: String,
+    /// The billed period as YYYY-MM-DD, empty for bills stored before periods were recorded
+    pub period_start: String,
+    pub period_end: String,
+    /// YYYY-MM-DD, empty for bills stored before due dates were recorded
+    pub due_date: String,
+    /// What settles the bill if paid within `skonto_days` of its date, 0 without Skonto
+    pub skonto_total: f64,
+    pub skonto_days: u32,
+    /// The Verzugspauschale and the day it was first charged in a dunning letter or Zinsrechnung, 0 and empty until then
+    pub flat_fee: f64,
+    pub flat_fee_date: String,
+    /// The default interest invoiced with Zinsrechnungen and the last day it was invoiced for, 0 and empty until then
+    pub interest: f64,
+    pub interest_until: String,
 }
 
-type DBResult<T> = Result<T, DBError>;
+impl DBEntry {
+    pub fn billdate(&self) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(self.year, self.month, self.day)
+    }
+
+    pub fn due_date(&self) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(&self.due_date, "%Y-%m-%d").ok()
+    }
+
+    /// The net amount. Bills stored before it was recorded are assumed to be taxed at the current rate
+    pub fn net_amount(&self) -> f64 {
+        if self.net == 0.0 && self.amount != 0.0 {
+            return self.amount / (1.0 + calculate::VAT_RATE)
+        }
+        self.net
+    }
+
+    /// The VAT rate in whole percent
+    pub fn vat_percent(&self) -> u32 {
+        let net = self.net_amount();
+        if net == 0.0 {
+            return (calculate::VAT_RATE * 100.0).round() as u32
+        }
+        ((self.amount / net - 1.0) * 100.0).round().max(0.0) as u32
+    }
 
-static DB_PATH_STR: &str = "db.sql";
+    /// The interest and Verzugspauschale invoiced on the bill before, which payments cover first
+    pub fn invoiced(&self) -> Invoiced {
+        Invoiced { amount: self.interest + self.flat_fee, until: NaiveDate::parse_from_str(&self.interest_until, "%Y-%m-%d").ok() }
+    }
+
+    /// The Skonto total and the last day it may be paid on
+    pub fn skonto(&self) -> Option<(f64, NaiveDate)> {
+        if self.skonto_total <= 0.0 {
+            return None
+        }
+        Some((self.skonto_total, self.billdate()?.checked_add_days(Days::new(self.skonto_days as u64))?))
+    }
+
+    /// The billed period. Bills stored before periods were recorded covered the month of their date
+    pub fn period(&self) -> Option<(NaiveDate, NaiveDate)> {
+        match (NaiveDate::parse_from_str(&self.period_start, "%Y-%m-%d"), NaiveDate::parse_from_str(&self.period_end, "%Y-%m-%d")) {
+            (Ok(start), Ok(end)) => Some((start, end)),
+            _ => date_utils::month_bounds(&self.billdate()?).ok(),
+        }
+    }
+}
 
-fn get_connection() -> DBResult<Connection> {
-    let db_path = Path::new(DB_PATH_STR);
-    let conn = Connection::open(&db_path)?;
+/// A credit from a bank statement, or the part of it that pays one bill
+#[derive(Debug, Clone)]
+pub struct Payment {
+    pub id: i32,
+    /// The bank's reference of the transaction, or one built from its fields. Re-imported transactions are skipped
+    pub transaction_id: String,
+    /// YYYY-MM-DD
+    pub date: String,
+    pub amount: f64,
+    pub currency: String,
+    pub payer: String,
+    /// The remittance information
+    pub reference: String,
+    /// Empty while the payment is not assigned to a bill
+    pub billnr: String,
+}
 
-    Ok(conn)
+impl Payment {
+    pub fn date(&self) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(&self.date, "%Y-%m-%d").ok()
+    }
 }
 
-pub fn create_db_if_needed() -> DBResult<()> {
-    let db_path = Path::new(DB_PATH_STR);
-    if db_path.exists() {
-        println!("The database exists.")
+/// A dunning letter sent for a bill
+#[derive(Debug, Clone)]
+pub struct Dunning {
+    pub id: i32,
+    pub billnr: String,
+    /// 1 for the first level
+    pub level: i32,
+    /// YYYY-MM-DD
+    pub date: String,
+    pub fee: f64,
+    pub interest: f64,
+}
+
+impl Dunning {
+    pub fn date(&self) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(&self.date, "%Y-%m-%d").ok()
+    }
+}
+
+/// What bills used up, for contingents and budgets
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Usage {
+    pub minutes: i32,
+    pub net: f64,
+    pub contingent_minutes: i32,
+}
+
+type DBResult<T> = Result<T, DBError>;
+
+pub static BILL_STATUSES: [&str; 4] = ["open", "sent", "paid", "cancelled"];
+
+// Columns added after the table was first created. Databases missing them are migrated on startup
+static ADDED_COLUMNS: [(&str, &str); 16] = [
+    ("status", "TEXT NOT NULL DEFAULT 'open'"),
+    ("currency", "TEXT NOT NULL DEFAULT 'EUR'"),
+    // Units of the currency per euro when the bill was created
+    ("exchange_rate", "REAL NOT NULL DEFAULT 1.0"),
+    ("contingent_minutes", "INTEGER NOT NULL DEFAULT 0"),
+    ("minutes", "INTEGER NOT NULL DEFAULT 0"),
+    ("net", "REAL NOT NULL DEFAULT 0"),
+    ("project", "TEXT NOT NULL DEFAULT ''"),
+    ("period_start", "TEXT NOT NULL DEFAULT ''"),
+    ("period_end", "TEXT NOT NULL DEFAULT ''"),
+    ("due_date", "TEXT NOT NULL DEFAULT ''"),
+    ("skonto_total", "REAL NOT NULL DEFAULT 0"),
+    ("skonto_days", "INTEGER NOT NULL DEFAULT 0"),
+    ("flat_fee", "REAL NOT NULL DEFAULT 0"),
+    ("flat_fee_date", "TEXT NOT NULL DEFAULT ''"),
+    ("interest", "REAL NOT NULL DEFAULT 0"),
+    ("interest_until", "TEXT NOT NULL DEFAULT ''"),
+];
+
+/// The bill database, opened for each query
+#[derive(Debug, Clone)]
+pub struct Db {
+    path: PathBuf,
+}
+
+fn get_first_billnr(vec: &[DBEntry]) -> Option<(String, i32)> {
+    vec.first().map(|s| (s.billnr.clone(), s.billnr_int))
+}
+
+fn get_all_billnr_ints(bills: &[DBEntry]) -> Vec<i32> {
+    let extracted_billnrs: Vec<i32> = if !bills.is_empty() {
+        bills.iter().map(|item| item.billnr_int).collect()
     } else {
-        println!("Creating new database");
-        let conn = get_connection()?;
+        Vec::new()
+    };
+    extracted_billnrs
+}
+
+fn date_key(date: &NaiveDate) -> i32 {
+    date.year() * 10000 + date.month() as i32 * 100 + date.day() as i32
+}
+
+impl Db {
+    /// The database at `path`, created by [Db::create_db_if_needed]
+    pub fn new(path: &Path) -> Self {
+        Db { path: path.to_path_buf() }
+    }
+
+    /// The database at `path`, created and migrated if needed
+    pub fn open(path: &Path) -> DBResult<Self> {
+        let db = Db::new(path);
+        db.create_db_if_needed()?;
+        Ok(db)
+    }
+
+    fn get_connection(&self) -> DBResult<Connection> {
+        let db_path = &self.path;
+        let conn = Connection::open(db_path)?;
+
+        Ok(conn)
+    }
+
+    pub fn create_db_if_needed(&self) -> DBResult<()> {
+        let db_path = &self.path;
+        if db_path.exists() {
+            println!("The database exists.")
+        } else {
+            println!("Creating new database at {:?}", db_path);
+            let conn = self.get_connection()?;
+
+            conn.execute(
+                "CREATE TABLE bill (
+                    id          INTEGER PRIMARY KEY,
+                    year        INTEGER,
+                    month       INTEGER,
+                    day         INTEGER,
+                    company     TEXT NOT NULL,
+                    billnr      TEXT NOT NULL,
+                    amount      FLOAT,
+                    amount_str  TEXT NOT NULL,
+                    billnr_int  INTEGER
+                )",
+                (),
+            )?;
+        }
+
+        self.migrate()
+    }
+
+    fn migrate(&self) -> DBResult<()> {
+        let conn = self.get_connection()?;
+
+        let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('bill')")?;
+        let columns: Vec<String> = stmt.query_map([], |row| row.get(0))?.collect::<Result<_>>()?;
+
+        for (column, definition) in ADDED_COLUMNS {
+            if !columns.iter().any(|existing| existing == column) {
+                println!("Adding column {:?} to the database", column);
+                conn.execute(&format!("ALTER TABLE bill ADD COLUMN {} {}", column, definition), ())?;
+            }
+        }
+
+        // One row per transaction and bill it pays, with an empty bill number while unassigned
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS payment (
+                id              INTEGER PRIMARY KEY,
+                transaction_id  TEXT NOT NULL,
+                date            TEXT NOT NULL,
+                amount          REAL NOT NULL,
+                currency        TEXT NOT NULL,
+                payer           TEXT NOT NULL,
+                reference       TEXT NOT NULL,
+                billnr          TEXT NOT NULL DEFAULT ''
+            )",
+            (),
+        )?;
 
         conn.execute(
-            "CREATE TABLE bill (
+            "CREATE TABLE IF NOT EXISTS dunning (
                 id          INTEGER PRIMARY KEY,
-                year        INTEGER,
-                month       INTEGER,
-                day         INTEGER,
-                company     TEXT NOT NULL,
                 billnr      TEXT NOT NULL,
-                amount      FLOAT,
-                amount_str  TEXT NOT NULL,
-                billnr_int  INTEGER
+                level       INTEGER NOT NULL,
+                date        TEXT NOT NULL,
+                fee         REAL NOT NULL,
+                interest    REAL NOT NULL
+            )",
+            (),
+        )?;
+
+        // Billed minutes and net amount per project of a bill, for project budgets
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS bill_section (
+                billnr      TEXT NOT NULL,
+                project     TEXT NOT NULL,
+                minutes     INTEGER NOT NULL,
+                net         REAL NOT NULL
             )",
             (),
         )?;
+
+        Ok(())
     }
 
-    Ok(())
-}
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_to_db(&self, company: &str, project: &str, billdate: &NaiveDate, period: &(NaiveDate, NaiveDate), due_date: &NaiveDate, billnr: &str, amount: &f64, amount_str: &str, billnr_int: &i32, currency: &str, exchange_rate: &f64, skonto: Option<&SkontoTerms>) -> DBResult<()> {
+
+        let new_entry = DBEntry {
+            id: 0,
+            year: billdate.year(),
+            month: billdate.month(),
+            day: billdate.day(),
+            company: company.to_string(),
+            billnr: billnr.to_string(),
+            amount: amount.to_owned(),
+            amount_str: amount_str.to_string(),
+            billnr_int: *billnr_int,
+            status: String::from("open"),
+            currency: currency.to_string(),
+            exchange_rate: *exchange_rate,
+            contingent_minutes: 0,
+            minutes: 0,
+            net: 0.0,
+            project: project.to_string(),
+            period_start: period.0.to_string(),
+            period_end: period.1.to_string(),
+            due_date: due_date.to_string(),
+            skonto_total: skonto.map_or(0.0, |skonto| skonto.total),
+            skonto_days: skonto.map_or(0, |skonto| skonto.days),
+            flat_fee: 0.0,
+            flat_fee_date: String::new(),
+            interest: 0.0,
+            interest_until: String::new(),
+        };
 
-pub fn add_to_db(company: &str, billdate: &NaiveDate, billnr: &str, amount: &f64, amount_str: &str, billnr_int: &i32) -> DBResult<()> {
-
-    let new_entry = DBEntry {
-        id: 0,
-        year: billdate.year(),
-        month: billdate.month(),
-        day: billdate.day(),
-        company: company.to_string(),
-        billnr: billnr.to_string(),
-        amount: amount.to_owned(),
-        amount_str: amount_str.to_string(),
-        billnr_int: billnr_int.clone(),
-    };
+        let conn = self.get_connection()?;
 
-    let conn = get_connection()?;
+        // A regenerated bill keeps its status and the Verzugspauschale and interest charged on it
+        let (status, flat_fee, flat_fee_date, interest, interest_until) = self.query_for_bill(company, project, period)?
+            .into_iter()
+            .next()
+            .map(|bill| (bill.status, bill.flat_fee, bill.flat_fee_date, bill.interest, bill.interest_until))
+            .unwrap_or((new_entry.status, new_entry.flat_fee, new_entry.flat_fee_date, new_entry.interest, new_entry.interest_until));
 
-    let existing_entries = get_id_if_exists(company, billdate)?;
+        let existing_entries = self.get_id_if_exists(company, project, period)?;
 
-    if !existing_entries.is_empty() {
-        for id in existing_entries {
-            delete_entry_by_id(&id)?;
+        if !existing_entries.is_empty() {
+            for id in existing_entries {
+                self.delete_entry_by_id(&id)?;
+            }
         }
+
+        conn.execute(
+            "INSERT INTO bill (year, month, day, company, billnr, amount, amount_str, billnr_int, status, currency, exchange_rate, project, period_start, period_end, due_date, skonto_total, skonto_days, flat_fee, flat_fee_date, interest, interest_until) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21)",
+            params![
+                new_entry.year, new_entry.month, new_entry.day, new_entry.company, new_entry.billnr, new_entry.amount, new_entry.amount_str, new_entry.billnr_int, status,
+                new_entry.currency, new_entry.exchange_rate, new_entry.project, new_entry.period_start, new_entry.period_end, new_entry.due_date, new_entry.skonto_total, new_entry.skonto_days,
+                flat_fee, flat_fee_date, interest, interest_until,
+            ],
+        )?;
+
+        Ok(())
     }
 
-    conn.execute(
-        "INSERT INTO bill (year, month, day, company, billnr, amount, amount_str, billnr_int) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-        (&new_entry.year, &new_entry.month, &new_entry.day, &new_entry.company, &new_entry.billnr, &new_entry.amount, &new_entry.amount_str, &new_entry.billnr_int),
-    )?;
+    fn delete_entry_by_id(&self, id: &i32) -> DBResult<()> {
+        let conn = self.get_connection()?;
 
-    Ok(())
-}
+        println!("Delete existing DB entry with ID: {:?}", id);
+        let delete_str = "DELETE FROM bill WHERE id = ?1".to_string();
+
+        conn.execute(&delete_str, params![id])?;
 
-fn delete_entry_by_id(id: &i32) -> DBResult<()> {
-    let conn = get_connection()?;
+        Ok(())
+    }
 
-    println!("Delete existing DB entry with ID: {:?}", id);
-    let delete_str = "DELETE FROM bill WHERE id = ?1".to_string();
+    fn query_db(&self, query_str: &str) -> DBResult<Vec<DBEntry>> {
+        self.query_db_with_params(query_str, [])
+    }
 
-    conn.execute(&delete_str, params![id])?;
+    fn query_db_with_params<P: Params>(&self, query_str: &str, params: P) -> DBResult<Vec<DBEntry>> {
+
+        let conn = self.get_connection()?;
+
+        let mut stmt = conn.prepare(
+        query_str)?;
+
+        let bill_iter = stmt.query_map(params, |row| {
+            let res_bill = DBEntry {
+                id: row.get(0)?,
+                year: row.get(1)?,
+                month: row.get(2)?,
+                day: row.get(3)?,
+                company: row.get(4)?,
+                billnr: row.get(5)?,
+                amount: row.get(6)?,
+                amount_str: row.get(7)?,
+                billnr_int: row.get(8)?,
+                status: row.get(9)?,
+                currency: row.get(10)?,
+                exchange_rate: row.get(11)?,
+                contingent_minutes: row.get(12)?,
+                minutes: row.get(13)?,
+                net: row.get(14)?,
+                project: row.get(15)?,
+                period_start: row.get(16)?,
+                period_end: row.get(17)?,
+                due_date: row.get(18)?,
+                skonto_total: row.get(19)?,
+                skonto_days: row.get(20)?,
+                flat_fee: row.get(21)?,
+                flat_fee_date: row.get(22)?,
+                interest: row.get(23)?,
+                interest_until: row.get(24)?,
+            };
+        Ok(res_bill)
+        })?;
+
+        let mut bills: Vec<DBEntry> = Vec::new();
+
+        for bill in bill_iter {
+            bills.push(bill.unwrap());
+        }
 
-    Ok(())
-}
+        Ok(bills)
+    }
 
-fn query_db(query_str: &str) -> DBResult<Vec<DBEntry>> {
+    // Bills stored without a period are monthly bills of the month of their date
+    fn query_for_bill(&self, company: &str, project: &str, period: &(NaiveDate, NaiveDate)) -> DBResult<Vec<DBEntry>> {
+        let query_str = "SELECT * FROM bill WHERE company = ?1 AND project = ?2
+            AND ((period_start = ?3 AND period_end = ?4) OR (?5 AND period_start = '' AND year = ?6 AND month = ?7))";
 
-    let conn = get_connection()?;
+        let bills = self.query_db_with_params(query_str, params![
+            company, project, period.0.to_string(), period.1.to_string(), date_utils::is_month(period), period.0.year(), period.0.month(),
+        ])?;
 
-    let mut stmt = conn.prepare(
-    query_str)?;
+        Ok(bills)
+    }
 
-    let bill_iter = stmt.query_map([], |row| {
-        let res_bill = DBEntry {
-            id: row.get(0)?,
-            year: row.get(1)?,
-            month: row.get(2)?,
-            day: row.get(3)?,
-            company: row.get(4)?,
-            billnr: row.get(5)?,
-            amount: row.get(6)?,
-            amount_str: row.get(7)?,
-            billnr_int: row.get(8)?,
-        };
-    Ok(res_bill)
-    })?;
+    fn query_for_month(&self, billdate: &NaiveDate) -> DBResult<Vec<DBEntry>> {
+        let query_str = format!("SELECT * FROM bill WHERE month == '{month}'", month = billdate.month());
 
-    let mut bills: Vec<DBEntry> = Vec::new();
+        let bills = self.query_db(&query_str)?;
 
-    for bill in bill_iter {
-        bills.push(bill.unwrap());
+        Ok(bills)
     }
 
-    Ok(bills)
-}
+    fn get_id_if_exists(&self, company: &str, project: &str, period: &(NaiveDate, NaiveDate)) -> DBResult<Vec<i32>> {
 
-fn query_for_company_in_month(company: &str, billdate: &NaiveDate) -> DBResult<Vec<DBEntry>> {
-    let query_str = format!("SELECT * FROM bill WHERE company == '{company}' AND month == '{month}'", company=company, month = billdate.month());
+        let bills = self.query_for_bill(company, project, period)?;
 
-    let bills = query_db(&query_str)?;
+        let extracted_ids: Vec<i32> = if !bills.is_empty() {
+            bills.iter().map(|item| item.id).collect()
+        } else {
+            Vec::new()
+        };
 
-    Ok(bills)
-}
+        println!("Found existing db entries with IDs: {:#?}", extracted_ids);
 
-fn query_for_month(billdate: &NaiveDate) -> DBResult<Vec<DBEntry>> {
-    let query_str = format!("SELECT * FROM bill WHERE month == '{month}'", month = billdate.month());
+        Ok(extracted_ids)
+    }
 
-    let bills = query_db(&query_str)?;
 
-    Ok(bills)
-}
 
-fn get_id_if_exists(company: &str, billdate: &NaiveDate) -> DBResult<Vec<i32>> {
+    /// `project` is empty for the bill of the company, otherwise the key of a project billed separately
+    pub fn get_billnr_if_exists(&self, company: &str, project: &str, period: &(NaiveDate, NaiveDate)) -> DBResult<Option<(String, i32)>> {
 
-    let bills = query_for_company_in_month(company, billdate)?;
+        let bills = self.query_for_bill(company, project, period)?;
+        let res = get_first_billnr(&bills);
 
-    let extracted_ids: Vec<i32> = if !bills.is_empty() {
-        bills.iter().map(|item| item.id).collect()
-    } else {
-        Vec::new()
-    };
+        match &res {
+            Some(res) => println!("Found existing billnr: {:?}", res),
+            None => println!("No existing billnr found")
+         }
 
-    println!("Found existing db entries with IDs: {:#?}", extracted_ids);
+        Ok(res)
+    }
 
-    Ok(extracted_ids)
-}
+    /// All bills of the company in the month of `billdate`, with those of projects billed separately
+    pub fn get_bills_in_month(&self, company: &str, billdate: &NaiveDate) -> DBResult<Vec<DBEntry>> {
+        self.query_db_with_params(
+            "SELECT * FROM bill WHERE company = ?1 AND month = ?2 AND year = ?3 ORDER BY billnr_int",
+            params![company, billdate.month(), billdate.year()],
+        )
+    }
 
-fn get_first_billnr(vec: &Vec<DBEntry>) -> Option<(String, i32)> {
-    vec.get(0).map(|s| (s.billnr.clone(), s.billnr_int))
-}
+    /// The bills of the company (or of one of its projects) that are not cancelled and cover part of `period` but not exactly it
+    pub fn get_overlapping_bills(&self, company: &str, project: &str, period: &(NaiveDate, NaiveDate)) -> DBResult<Vec<DBEntry>> {
+        let bills = self.query_db_with_params(
+            "SELECT * FROM bill WHERE company = ?1 AND project = ?2 AND status != 'cancelled' ORDER BY year, month, day",
+            params![company, project],
+        )?;
+        Ok(bills
+            .into_iter()
+            .filter(|bill| bill.period().is_some_and(|billed| billed != *period && billed.0 <= period.1 && billed.1 >= period.0))
+            .collect())
+    }
 
-fn get_all_billnr_ints(bills: &Vec<DBEntry>) -> Vec<i32> {
-    let extracted_billnrs: Vec<i32> = if !bills.is_empty() {
-        bills.iter().map(|item| item.billnr_int).collect()
-    } else {
-        Vec::new()
-    };
-    extracted_billnrs
-}
+    /// The bills dated from `first_month` to `last_month` of `year`, the oldest first
+    pub fn get_bills_in_months(&self, year: i32, first_month: u32, last_month: u32) -> DBResult<Vec<DBEntry>> {
+        self.query_db_with_params(
+            "SELECT * FROM bill WHERE year = ?1 AND month BETWEEN ?2 AND ?3 ORDER BY month, day, billnr_int",
+            params![year, first_month, last_month],
+        )
+    }
 
-pub fn get_billnr_if_exists(company: &str, billdate: &NaiveDate) -> DBResult<Option<(String, i32)>> {
+    /// `taken` are numbers handed out but not stored yet
+    pub fn get_new_billnr(&self, billdate: &NaiveDate, billnr_base: &str, taken: &[i32]) -> DBResult<(String, i32)> {
+        let bills = self.query_for_month(billdate)?;
+        let mut billnrs = get_all_billnr_ints(&bills);
+        billnrs.extend_from_slice(taken);
 
-    let bills = query_for_company_in_month(company, billdate)?;
-    let res = get_first_billnr(&bills);
+        let highest_int = match billnrs.iter().max() {
+            Some(&max_value) => max_value + 1,
+            None => {1},
+        };
 
-    match &res {
-        Some(res) => println!("Found existing billnr: {:?}", res),
-        None => println!("No existing billnr found")
-     }
+        let billnr = format!("{billnr_base}{billnr_int:02}",
+            billnr_base = billnr_base,
+            billnr_int = &highest_int,
+        );
+        println!("New billnumber: {:?}", billnr);
+        Ok((billnr, highest_int))
+    }
 
-    Ok(res)
-}
+    /// All bills, the newest first
+    pub fn get_all_bills(&self) -> DBResult<Vec<DBEntry>> {
+        self.query_db("SELECT * FROM bill ORDER BY year DESC, month DESC, billnr_int DESC")
+    }
 
-pub fn get_new_billnr(billdate: &NaiveDate, billnr_base: &str) -> DBResult<(String, i32)> {
-    let bills = query_for_month(billdate)?;
-    let billnrs = get_all_billnr_ints(&bills);
+    pub fn get_bill(&self, billnr: &str) -> DBResult<Option<DBEntry>> {
+        Ok(self.query_db_with_params("SELECT * FROM bill WHERE billnr = ?1", params![billnr])?.into_iter().next())
+    }
 
-    let highest_int = match billnrs.iter().max() {
-        Some(&max_value) => {
-            let new_billnr_int = max_value + 1;
-            new_billnr_int
+    /// Sets the status of a bill. Returns false if there is no such bill
+    pub fn set_status(&self, billnr: &str, status: &str) -> DBResult<bool> {
+        if !BILL_STATUSES.contains(&status) {
+            return Err(DBError::InvalidStatus(status.to_string()))
         }
-        None => {1},
-    };
+        let conn = self.get_connection()?;
+        let updated = conn.execute("UPDATE bill SET status = ?1 WHERE billnr = ?2", params![status, billnr])?;
+        Ok(updated > 0)
+    }
 
-    let billnr = format!("{billnr_base}{billnr_int:02}",
-        billnr_base = billnr_base,
-        billnr_int = &highest_int,
-    );
-    println!("New billnumber: {:?}", billnr);
-    Ok((billnr, highest_int))
-}
+    /// Records the Verzugspauschale charged on a bill, so later letters do not charge it again
+    pub fn set_flat_fee(&self, billnr: &str, flat_fee: f64, date: &NaiveDate) -> DBResult<()> {
+        let conn = self.get_connection()?;
+        conn.execute("UPDATE bill SET flat_fee = ?1, flat_fee_date = ?2 WHERE billnr = ?3", params![flat_fee, date.to_string(), billnr])?;
+        Ok(())
+    }
 
-#[allow(dead_code)]
-pub fn print_all_db_entries() -> DBResult<()> {
+    /// Records the interest invoiced on a bill up to `until`, so later statements start after it
+    pub fn set_interest(&self, billnr: &str, interest: f64, until: &NaiveDate) -> DBResult<()> {
+        let conn = self.get_connection()?;
+        conn.execute("UPDATE bill SET interest = ?1, interest_until = ?2 WHERE billnr = ?3", params![interest, until.to_string(), billnr])?;
+        Ok(())
+    }
 
-    let query_str = "SELECT * FROM bill".to_string();
+    pub fn set_usage(&self, billnr: &str, usage: &Usage) -> DBResult<()> {
+        let conn = self.get_connection()?;
+        conn.execute(
+            "UPDATE bill SET minutes = ?1, net = ?2, contingent_minutes = ?3 WHERE billnr = ?4",
+            params![usage.minutes, usage.net, usage.contingent_minutes, billnr],
+        )?;
+        Ok(())
+    }
+
+    /// Replaces the per-project minutes and net amounts of a bill
+    pub fn set_sections(&self, billnr: &str, sections: &[(String, i32, f64)]) -> DBResult<()> {
+        let conn = self.get_connection()?;
+        conn.execute("DELETE FROM bill_section WHERE billnr = ?1", params![billnr])?;
+        for (project, minutes, net) in sections {
+            conn.execute(
+                "INSERT INTO bill_section (billnr, project, minutes, net) VALUES (?1, ?2, ?3, ?4)",
+                params![billnr, project, minutes, net],
+            )?;
+        }
+        Ok(())
+    }
+
+
+    /// The sum of what the bills of the company dated from `from` (if given) up to before `before` used. Cancelled bills don't count
+    pub fn get_usage(&self, company: &str, from: Option<&NaiveDate>, before: &NaiveDate) -> DBResult<Usage> {
+        let conn = self.get_connection()?;
+        let usage = conn.query_row(
+            "SELECT COALESCE(SUM(minutes), 0), COALESCE(SUM(net), 0), COALESCE(SUM(contingent_minutes), 0) FROM bill
+             WHERE company = ?1 AND status != 'cancelled' AND year * 10000 + month * 100 + day BETWEEN ?2 AND ?3 - 1",
+            params![company, from.map_or(0, date_key), date_key(before)],
+            |row| Ok(Usage { minutes: row.get(0)?, net: row.get(1)?, contingent_minutes: row.get(2)? }),
+        )?;
+        Ok(usage)
+    }
+
+    /// Like [Db::get_usage], limited to the hours of a project, whether billed separately or in sections
+    pub fn get_project_usage(&self, company: &str, project: &str, from: Option<&NaiveDate>, before: &NaiveDate) -> DBResult<Usage> {
+        let conn = self.get_connection()?;
+        let usage = conn.query_row(
+            "SELECT COALESCE(SUM(bill_section.minutes), 0), COALESCE(SUM(bill_section.net), 0) FROM bill_section
+             JOIN bill ON bill.billnr = bill_section.billnr
+             WHERE bill.company = ?1 AND bill_section.project = ?2 AND bill.status != 'cancelled'
+             AND bill.year * 10000 + bill.month * 100 + bill.day BETWEEN ?3 AND ?4 - 1",
+            params![company, project, from.map_or(0, date_key), date_key(before)],
+            |row| Ok(Usage { minutes: row.get(0)?, net: row.get(1)?, contingent_minutes: 0 }),
+        )?;
+        Ok(usage)
+    }
+
+    /// Bills that are neither paid nor cancelled, the oldest first
+    pub fn get_unpaid_bills(&self) -> DBResult<Vec<DBEntry>> {
+        self.query_db("SELECT * FROM bill WHERE status IN ('open', 'sent') ORDER BY year, month, day, billnr_int")
+    }
+
+    fn query_payments<P: Params>(&self, query_str: &str, params: P) -> DBResult<Vec<Payment>> {
+        let conn = self.get_connection()?;
+        let mut stmt = conn.prepare(query_str)?;
+        let payments = stmt.query_map(params, |row| {
+            Ok(Payment {
+                id: row.get(0)?,
+                transaction_id: row.get(1)?,
+                date: row.get(2)?,
+                amount: row.get(3)?,
+                currency: row.get(4)?,
+                payer: row.get(5)?,
+                reference: row.get(6)?,
+                billnr: row.get(7)?,
+            })
+        })?;
+        Ok(payments.collect::<Result<_>>()?)
+    }
+
+    pub fn has_payment(&self, transaction_id: &str) -> DBResult<bool> {
+        Ok(!self.query_payments("SELECT * FROM payment WHERE transaction_id = ?1", params![transaction_id])?.is_empty())
+    }
 
-    let bills = query_db(&query_str)?;
+    pub fn add_payment(&self, payment: &Payment) -> DBResult<()> {
+        let conn = self.get_connection()?;
+        conn.execute(
+            "INSERT INTO payment (transaction_id, date, amount, currency, payer, reference, billnr) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![payment.transaction_id, payment.date, payment.amount, payment.currency, payment.payer, payment.reference, payment.billnr],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_payment(&self, id: i32) -> DBResult<Option<Payment>> {
+        Ok(self.query_payments("SELECT * FROM payment WHERE id = ?1", params![id])?.into_iter().next())
+    }
 
-    for bill in bills {
-        println!("{:?}\n", bill);
+    /// Payments not assigned to a bill, the oldest first
+    pub fn get_unassigned_payments(&self) -> DBResult<Vec<Payment>> {
+        self.query_payments("SELECT * FROM payment WHERE billnr = '' ORDER BY date, id", [])
     }
 
-    Ok(())
-}
\ No newline at end of file
+    /// The payments assigned to a bill, the oldest first
+    pub fn get_payments(&self, billnr: &str) -> DBResult<Vec<Payment>> {
+        self.query_payments("SELECT * FROM payment WHERE billnr = ?1 ORDER BY date, id", params![billnr])
+    }
+
+    /// The payments assigned to a bill received from `from` to `to`, the oldest first
+    pub fn get_payments_between(&self, from: &NaiveDate, to: &NaiveDate) -> DBResult<Vec<Payment>> {
+        self.query_payments("SELECT * FROM payment WHERE billnr != '' AND date BETWEEN ?1 AND ?2 ORDER BY date, id", params![from.to_string(), to.to_string()])
+    }
+
+    /// The sum of the payments assigned to a bill
+    pub fn get_paid_amount(&self, billnr: &str) -> DBResult<f64> {
+        let conn = self.get_connection()?;
+        Ok(conn.query_row("SELECT COALESCE(SUM(amount), 0) FROM payment WHERE billnr = ?1", params![billnr], |row| row.get(0))?)
+    }
+
+    pub fn assign_payment(&self, id: i32, billnr: &str) -> DBResult<()> {
+        let conn = self.get_connection()?;
+        conn.execute("UPDATE payment SET billnr = ?1 WHERE id = ?2", params![billnr, id])?;
+        Ok(())
+    }
+
+    /// The dunning letters of a bill, the first first
+    pub fn get_dunnings(&self, billnr: &str) -> DBResult<Vec<Dunning>> {
+        let conn = self.get_connection()?;
+        let mut stmt = conn.prepare("SELECT id, billnr, level, date, fee, interest FROM dunning WHERE billnr = ?1 ORDER BY level")?;
+        let dunnings = stmt.query_map(params![billnr], |row| {
+            Ok(Dunning { id: row.get(0)?, billnr: row.get(1)?, level: row.get(2)?, date: row.get(3)?, fee: row.get(4)?, interest: row.get(5)? })
+        })?;
+        Ok(dunnings.collect::<Result<_>>()?)
+    }
+
+    pub fn add_dunning(&self, dunning: &Dunning) -> DBResult<()> {
+        let conn = self.get_connection()?;
+        conn.execute(
+            "INSERT INTO dunning (billnr, level, date, fee, interest) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![dunning.billnr, dunning.level, dunning.date, dunning.fee, dunning.interest],
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+use chrono::{Days, NaiveDate};
+use thiserror::Error;
+use crate::config_reader::{Config, DunningLevel};
+use crate::db::{DBEntry, DBError, Db, Dunning};
+use crate::pdf_gen::{self, DunningContent, Item, PdfError};
+use crate::interest::{self, InterestError, InterestRate};
+use crate::{calculate, date_utils, mail, qrcode};
+
+#[derive(Debug, Error)]
+pub enum DunningError {
+    #[error("Bill {0} has no valid date")]
+    InvalidBill(String),
+    #[error("Company {0:?} of an open bill is not configured")]
+    UnknownCompany(String),
+    #[error("No bill {0:?}")]
+    UnknownBill(String),
+    #[error(transparent)]
+    DB(#[from] DBError),
+    #[error(transparent)]
+    Date(#[from] date_utils::DateError),
+    #[error(transparent)]
+    Interest(#[from] InterestError),
+    #[error(transparent)]
+    Currency(#[from] calculate::AmountCalcs),
+    #[error(transparent)]
+    QrCode(#[from] qrcode::QRGenErrors),
+    #[error(transparent)]
+    Pdf(#[from] PdfError),
+    #[error(transparent)]
+    Mail(#[from] mail::MailError),
+}
+
+type DunningResult<T> = Result<T, DunningError>;
+
+// Amounts closer than half a cent are settled
+static TOLERANCE: f64 = 0.005;
+
+/// An unpaid bill with what is still open of it
+#[derive(Debug)]
+pub struct OpenItem {
+    pub bill: DBEntry,
+    pub billdate: NaiveDate,
+    pub due: NaiveDate,
+    pub paid: f64,
+    pub open: f64,
+    /// Negative while the bill is not due yet
+    pub days_overdue: i64,
+    /// The letters sent so far, the first first
+    pub dunnings: Vec<Dunning>,
+}
+
+impl OpenItem {
+    /// The level the next letter is at and its config, if it is due on `date`.
+    /// The first level counts from the due date, the others from the previous letter
+    pub fn next_level<'a>(&self, config: &'a Config, date: &NaiveDate) -> Option<(usize, &'a DunningLevel)> {
+        let index = self.dunnings.len();
+        let level = config.dunning.levels.get(index)?;
+        let since = self.dunnings.last().and_then(Dunning::date).unwrap_or(self.due);
+        let reached = since.checked_add_days(Days::new(level.after_days.max(0) as u64))?;
+        (*date >= reached).then_some((index + 1, level))
+    }
+
+    /// Name of the last letter sent, empty if there was none
+    pub fn last_level<'a>(&self, config: &'a Config) -> &'a str {
+        self.dunnings
+            .last()
+            .and_then(|dunning| config.dunning.levels.get(dunning.level as usize - 1))
+            .map(|level| level.name.as_str())
+            .unwrap_or_default()
+    }
+}
+
+// Bills stored before due dates were recorded are due by the current payment terms
+fn due_date(bill: &DBEntry, billdate: NaiveDate, config: &Config) -> DunningResult<NaiveDate> {
+    if let Some(due) = bill.due_date() {
+        return Ok(due)
+    }
+    let terms = config.companies.get(&bill.company).and_then(|company| company.payment_terms).unwrap_or(config.bill_config.payment_terms);
+    Ok(date_utils::calculate_due_date(billdate, terms, config.bill_config.calendar)?)
+}
+
+/// The bills billed up to `date` that are neither paid nor cancelled, the oldest first
+pub fn open_items(db: &Db, config: &Config, date: &NaiveDate) -> DunningResult<Vec<OpenItem>> {
+    let mut items = Vec::new();
+    for bill in db.get_unpaid_bills()? {
+        let item = open_item(db, config, bill, date)?;
+        if item.billdate <= *date && item.open >= TOLERANCE {
+            items.push(item);
+        }
+    }
+    Ok(items)
+}
+
+fn open_item(db: &Db, config: &Config, bill: DBEntry, date: &NaiveDate) -> DunningResult<OpenItem> {
+    let billdate = bill.billdate().ok_or_else(|| DunningError::InvalidBill(bill.billnr.clone()))?;
+    let paid = db.get_paid_amount(&bill.billnr)?;
+    let due = due_date(&bill, billdate, config)?;
+    let dunnings = db.get_dunnings(&bill.billnr)?;
+    Ok(OpenItem { billdate, due, paid, open: bill.amount - paid, days_overdue: (*date - due).num_days(), dunnings, bill })
+}
+
+/// Prints the open items as of `date` with the level of their last letter and the next one that is due
+pub fn report(config: &Config, date: &NaiveDate) -> DunningResult<()> {
+    let items = open_items(&Db::open(&config.paths.db)?, config, date)?;
+    if items.is_empty() {
+        println!("No open bills");
+        return Ok(())
+    }
+
+    println!("\n{:<12} {:<20} {:<10} {:<10} {:>10} {:>10} {:>10} {:<4} {:>7}  {:<20}", "Bill", "Company", "Date", "Due", "Amount", "Paid", "Open", "", "Overdue", "Dunning");
+    for item in &items {
+        let next = match item.next_level(config, date) {
+            Some((_, level)) => format!("  next: {}", level.name),
+            None => String::new(),
+        };
+        println!(
+            "{:<12} {:<20} {:<10} {:<10} {:>10.2} {:>10.2} {:>10.2} {:<4} {:>7}  {:<20}{}",
+            item.bill.billnr, item.bill.company, item.billdate, item.due, item.bill.amount, item.paid, item.open, item.bill.currency,
+            item.days_overdue.max(0), item.last_level(config), next,
+        );
+    }
+
+    let mut currencies: Vec<&str> = items.iter().map(|item| item.bill.currency.as_str()).collect();
+    currencies.sort();
+    currencies.dedup();
+    for currency in currencies {
+        let of_currency = items.iter().filter(|item| item.bill.currency == currency);
+        let (open, overdue) = of_currency.fold((0.0, 0.0), |(open, overdue), item| {
+            (open + item.open, if item.days_overdue > 0 { overdue + item.open } else { overdue })
+        });
+        println!("Open {:.2} {}, of which {:.2} {} overdue", open, currency, overdue, currency);
+    }
+    Ok(())
+}
+
+/// A letter about a bill before it is rendered
+struct Letter {
+    subject: String,
+    text: String,
+    /// The positions and their amounts in the bill currency
+    items: Vec<(String, f64)>,
+}
+
+impl Letter {
+    fn total(&self) -> f64 {
+        self.items.iter().map(|(_, amount)| amount).sum()
+    }
+}
+
+/// The Verzugspauschale still to charge. It is in euros, so only bills in euros get it, and only once per bill
+fn flat_fee(config: &Config, bill: &DBEntry) -> f64 {
+    if bill.currency == "EUR" && bill.flat_fee == 0.0 { config.dunning.flat_fee } else { 0.0 }
+}
+
+fn money(config: &Config, bill: &DBEntry) -> DunningResult<impl Fn(f64) -> String> {
+    let currency = calculate::find_currency(&bill.currency)?;
+    let locale = calculate::money_locale(currency, config.bill_config.number_format.as_deref())?;
+    Ok(move |amount: f64| calculate::format_money(amount, currency, locale))
+}
+
+/// The letter of `level`, the interest it charges and the Verzugspauschale if it is charged for the first time
+fn letter(db: &Db, item: &OpenItem, level: &DunningLevel, config: &Config, date: &NaiveDate, rate: Option<&InterestRate>) -> DunningResult<(Letter, f64, f64)> {
+    let mut items = vec![(format!("Rechnung {} vom {}", item.bill.billnr, item.billdate.format("%d.%m.%Y")), item.bill.amount)];
+    if item.paid > 0.0 {
+        items.push((String::from("Bereits gezahlt"), -item.paid));
+    }
+    // Fees of earlier letters stay owed, their interest is superseded by the current statement
+    for dunning in item.dunnings.iter().filter(|dunning| dunning.fee > 0.0) {
+        let name = config.dunning.levels.get(dunning.level as usize - 1).map(|level| level.name.as_str()).unwrap_or("Mahnung");
+        items.push((format!("Mahngebühr {} vom {}", name, dunning.date().map(|date| date.format("%d.%m.%Y").to_string()).unwrap_or_default()), dunning.fee));
+    }
+    if item.bill.flat_fee > 0.0 {
+        let charged = NaiveDate::parse_from_str(&item.bill.flat_fee_date, "%Y-%m-%d").map(|date| date.format("%d.%m.%Y").to_string()).unwrap_or_default();
+        items.push((format!("Verzugspauschale vom {}", charged), item.bill.flat_fee));
+    }
+    if level.fee > 0.0 {
+        items.push((format!("Mahngebühr {}", level.name), level.fee));
+    }
+    let (interest, flat_fee) = match rate {
+        Some(rate) if level.interest => {
+            let statement = interest::statement(item.bill.amount, &item.due, &db.get_payments(&item.bill.billnr)?, date, rate, flat_fee(config, &item.bill), &item.bill.invoiced())?;
+            items.extend(statement.items(money(config, &item.bill)?));
+            (statement.interest, statement.flat_fee)
+        },
+        _ => (0.0, 0.0),
+    };
+    let letter = Letter { subject: level.name.clone(), text: level.text.clone(), items };
+    Ok((letter, interest, flat_fee))
+}
+
+/// Renders `letter` about the bill of `item`. Returns the PDF and the day the customer has to pay by
+fn render(config: &Config, item: &OpenItem, date: &NaiveDate, letter: &Letter) -> DunningResult<(Vec<u8>, NaiveDate)> {
+    let money = money(config, &item.bill)?;
+    let total = letter.total();
+    let pay_by = date.checked_add_days(Days::new(config.dunning.days.max(0) as u64)).ok_or(date_utils::DateError::CalculationError)?;
+    let qrcode = match item.bill.currency.as_str() {
+        "EUR" => {
+            let amount = calculate::to_amount_string(&total, calculate::find_currency("EUR")?)?;
+            qrcode::create_qrcode(&config.bank_config, &amount, &item.billdate, &item.bill.billnr, &config.bill_config.color)?
+        },
+        _ => String::new(),
+    };
+
+    let content = DunningContent {
+        company: item.bill.company.clone(),
+        level: letter.subject.clone(),
+        text: letter.text.clone(),
+        billnr: item.bill.billnr.clone(),
+        bill_date: item.billdate.format("%d.%m.%Y").to_string(),
+        bill_due: item.due.format("%d.%m.%Y").to_string(),
+        date: date.format("%d.%m.%Y").to_string(),
+        due: pay_by.format("%d.%m.%Y").to_string(),
+        qrcode,
+        config: pdf_gen::config_to_value(config)?,
+        items: letter.items.iter().map(|(description, amount)| Item { description: description.clone(), amount_str: money(*amount) }).collect(),
+        total_str: money(total),
+    };
+    Ok((pdf_gen::generate_dunning_pdf(content, &config.paths.root)?, pay_by))
+}
+
+fn mail_body(subject: &str, item: &OpenItem, total: &str, pay_by: &NaiveDate) -> String {
+    format!(
+        "anbei unsere {} zur Rechnung {} vom {}. Bitte überweisen Sie den Betrag von {} bis zum {}.",
+        subject, item.bill.billnr, item.billdate.format("%d.%m.%Y"), total, pay_by.format("%d.%m.%Y"),
+    )
+}
+
+/// Renders, saves and records the letters due on `date` for all companies (or `company`), optionally with mail drafts
+pub fn dun(config: &Config, date: &NaiveDate, company: Option<&str>, maildraft: bool) -> DunningResult<()> {
+    let db = Db::open(&config.paths.db)?;
+    let items = open_items(&db, config, date)?;
+    let rate = if config.dunning.levels.iter().any(|level| level.interest) { Some(InterestRate::from_config(config)?) } else { None };
+    let mut sent = 0;
+    for item in items.iter().filter(|item| company.is_none_or(|company| item.bill.company == company)) {
+        let Some((number, level)) = item.next_level(config, date) else {
+            continue
+        };
+        let company_config = config.companies.get(&item.bill.company).ok_or_else(|| DunningError::UnknownCompany(item.bill.company.clone()))?;
+        let (letter, interest, flat_fee) = letter(&db, item, level, config, date, rate.as_ref())?;
+        let (pdf, pay_by) = render(config, item, date, &letter)?;
+        let filename = pdf_gen::dunning_filename(*date, &item.bill.company, &item.bill.billnr, number);
+        pdf_gen::write_pdf(&pdf, &config.paths.bills_dir(date), &filename)?;
+
+        db.add_dunning(&Dunning {
+            id: 0,
+            billnr: item.bill.billnr.clone(),
+            level: number as i32,
+            date: date.to_string(),
+            fee: level.fee,
+            interest,
+        })?;
+        if flat_fee > 0.0 {
+            db.set_flat_fee(&item.bill.billnr, flat_fee, date)?;
+        }
+        let total = money(config, &item.bill)?(letter.total());
+        println!("{} {}: {} over {}", item.bill.company, item.bill.billnr, level.name, total);
+
+        if maildraft {
+            let subject = format!("{}: Rechnung {}", level.name, item.bill.billnr);
+            mail::create_draft(&config.mailconfig, company_config, &subject, &mail_body(&level.name, item, &total, &pay_by), pdf, &filename)?;
+        }
+        sent += 1;
+    }
+
+    if sent == 0 {
+        println!("No dunning letters due");
+    }
+    Ok(())
+}
+
+/// Prints the default interest on a bill up to `date` (or its payment) and saves it as a Zinsrechnung,
+/// optionally with a mail draft
+pub fn interest_invoice(config: &Config, billnr: &str, date: &NaiveDate, maildraft: bool) -> DunningResult<()> {
+    let db = Db::open(&config.paths.db)?;
+    let bill = db.get_bill(billnr)?.ok_or_else(|| DunningError::UnknownBill(billnr.to_string()))?;
+    let item = open_item(&db, config, bill, date)?;
+    let rate = InterestRate::from_config(config)?;
+    let invoiced = item.bill.invoiced();
+    let statement = interest::statement(item.bill.amount, &item.due, &db.get_payments(billnr)?, date, &rate, flat_fee(config, &item.bill), &invoiced)?;
+    if let Some(until) = invoiced.until.filter(|until| until >= date) {
+        println!("The interest on bill {} is invoiced up to {} already", billnr, until);
+        return Ok(())
+    }
+    if statement.periods.is_empty() {
+        println!("Bill {} was not overdue on {}", billnr, date);
+        return Ok(())
+    }
+
+    let money = money(config, &item.bill)?;
+    let letter = Letter {
+        subject: String::from("Zinsrechnung"),
+        text: format!(
+            "die folgende Rechnung war am {} fällig und wurde nicht rechtzeitig beglichen. Für die Zeit bis zum {} berechnen wir Ihnen Verzugszinsen (§ 288 BGB) auf den jeweils offenen Betrag.",
+            item.due.format("%d.%m.%Y"), statement.until.format("%d.%m.%Y"),
+        ),
+        items: statement.items(&money),
+    };
+    for (description, amount) in &letter.items {
+        println!("{:<80} {:>12}", description, money(*amount));
+    }
+    let total = money(letter.total());
+    println!("{:<80} {:>12}", "Total", total);
+
+    let company_config = config.companies.get(&item.bill.company).ok_or_else(|| DunningError::UnknownCompany(item.bill.company.clone()))?;
+    let (pdf, pay_by) = render(config, &item, date, &letter)?;
+    let filename = pdf_gen::interest_filename(*date, &item.bill.company, billnr);
+    pdf_gen::write_pdf(&pdf, &config.paths.bills_dir(date), &filename)?;
+    if statement.flat_fee > 0.0 {
+        db.set_flat_fee(billnr, statement.flat_fee, date)?;
+    }
+    db.set_interest(billnr, item.bill.interest + statement.interest, &statement.until)?;
+
+    if maildraft {
+        let subject = format!("Zinsrechnung: Rechnung {}", billnr);
+        mail::create_draft(&config.mailconfig, company_config, &subject, &mail_body("Zinsrechnung", &item, &total, &pay_by), pdf, &filename)?;
+    }
+    Ok(())
+}
@@ -1,12 +1,13 @@
 use minijinja::{Environment, context};
 use chrono::{NaiveDate};
 use std::io::Cursor;
-use crate::config_reader::{BankConfig, BillConfig, CompanyConfig};
+use crate::config_reader::{BankConfig, BillConfig, CompanyConfig, ProjectBilling};
 use lopdf::{Document, Object, Dictionary, Stream};
-use crate::calculate::Amounts;
+use crate::calculate::{Amounts, AppliedAdjustment};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum XMLError {
     #[error("io Error")]
     IoError(#[from] std::io::Error),
@@ -18,8 +19,23 @@ pub enum XMLError {
 
 type XMLResult<T> = Result<T, XMLError>;
 
+// Amounts with two decimals, as EN16931 allows no more for them
+fn money(amount: f64) -> String {
+    format!("{:.2}", amount)
+}
+
+fn adjustment_context(adjustment: &AppliedAdjustment) -> minijinja::Value {
+    context! {
+        is_charge => if adjustment.charge { "true" } else { "false" },
+        reason => adjustment.reason,
+        percent => adjustment.percent.map(|percent| format!("{:.2}", percent)),
+        base => money(adjustment.base),
+        amount => money(adjustment.amount),
+    }
+}
+
 
-pub fn add_xml_to_pdf(input_bytes: &Vec<u8>, xml_content: String) -> XMLResult<Vec<u8>> {
+pub fn add_xml_to_pdf(input_bytes: &[u8], xml_content: String) -> XMLResult<Vec<u8>> {
     let mut doc = Document::load_mem(input_bytes)?;
     
     let xml_stream = Stream::new(
@@ -68,22 +84,87 @@ pub fn add_xml_to_pdf(input_bytes: &Vec<u8>, xml_content: String) -> XMLResult<V
 
 }
 
-pub fn create_ebill_xml(billnr:&str, amounts: &Amounts, bill_date: NaiveDate, due_date: NaiveDate, bill_config: &BillConfig, company_config: &CompanyConfig, bank_config: &BankConfig) -> XMLResult<String> {
+/// Returns the xml embedded by `add_xml_to_pdf`, if any
+pub fn extract_xml_from_pdf(input_bytes: &[u8]) -> XMLResult<Option<String>> {
+    let doc = Document::load_mem(input_bytes)?;
+
+    for object in doc.objects.values() {
+        if let Object::Stream(stream) = object {
+            if matches!(stream.dict.get(b"Type").and_then(Object::as_name), Ok(name) if name == b"EmbeddedFile") {
+                let content = stream.decompressed_content().unwrap_or_else(|_| stream.content.clone());
+                return Ok(Some(String::from_utf8_lossy(&content).into_owned()))
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// `exchange_rate` is in units of `currency` per euro. Bills not in euros also state the VAT in euros (EN16931 BT-6, BT-111).
+/// Bills of a single `project` carry its purchase order and cost center (BT-13, BT-19), bills with sections name the project
+/// and its cost center on each line (BT-133). `period` is the service period (BG-14)
+#[allow(clippy::too_many_arguments)]
+pub fn create_ebill_xml(billnr:&str, amounts: &Amounts, bill_date: NaiveDate, due_date: NaiveDate, period: &(NaiveDate, NaiveDate), bill_config: &BillConfig, company_config: &CompanyConfig, project: Option<&str>, payment_terms: &str, bank_config: &BankConfig, currency: &str, exchange_rate: f64) -> XMLResult<String> {
     let mut env = Environment::new();
 
     println!("Creating Zugferd xml using template");
 
-    env.add_template("ebill", include_str!("../templates/ebill.xml"))?;
-
-    let amount_net = (amounts.net * 100.0).ceil() / 100.0;
-    let amount_vat = (amounts.vat * 100.0).ceil() / 100.0;
-    let amount_total = (amounts.total * 100.0).ceil()  / 100.0;
+    // The .xml name turns on auto-escaping, so names and reasons from the config can not break the XML
+    env.add_template("ebill.xml", include_str!("../templates/ebill.xml"))?;
+
+    let amount_lines = amounts.lines.iter().map(|line| line.amount).sum::<f64>() + amounts.items.iter().map(|item| item.amount).sum::<f64>();
+    let document_adjustments = |charge: bool| amounts.adjustments.iter().filter(|adjustment| adjustment.charge == charge).map(|adjustment| adjustment.amount).sum::<f64>();
+    let amount_allowances = amounts.cap_deduction + amounts.contingent_deduction + document_adjustments(false);
+    let amount_charges = amounts.surcharge + document_adjustments(true);
+    let amount_vat_eur = (currency != "EUR").then(|| money(amounts.vat / exchange_rate));
+
+    let project_config = project.and_then(|key| company_config.projects.get(key));
+    let sections = company_config.project_billing == ProjectBilling::Sections;
+
+    let hour_lines = amounts.lines.iter().map(|line| context! {
+        name => {
+            let name = if line.activity.is_empty() { &bill_config.bill_item } else { &line.activity };
+            if sections && !line.project.is_empty() {
+                format!("{}: {}", company_config.project_name(&line.project), name)
+            } else {
+                name.to_string()
+            }
+        },
+        accounting_cost => if sections { company_config.projects.get(&line.project).and_then(|project| project.cost_center.clone()) } else { None },
+        quantity => format!("{:.4}", line.hours),
+        unit => "HUR",
+        price => money(line.hourly_fee),
+        amount => money(line.amount),
+        charges => line.adjustments.iter().map(adjustment_context).collect::<Vec<_>>(),
+    });
+    let item_lines = amounts.items.iter().map(|item| context! {
+        name => item.description,
+        accounting_cost => None::<String>,
+        quantity => format!("{:.4}", 1.0),
+        unit => "C62",
+        price => money(item.amount),
+        amount => money(item.amount),
+        charges => Vec::<minijinja::Value>::new(),
+    });
+    let lines: Vec<_> = hour_lines.chain(item_lines).collect();
+    let mut charges = Vec::new();
+    if amounts.surcharge > 0.0 {
+        charges.push(context! { is_charge => "true", reason => "Wochenendzuschlag", percent => None::<String>, amount => money(amounts.surcharge) });
+    }
+    if amounts.cap_deduction > 0.0 {
+        charges.push(context! { is_charge => "false", reason => "Begrenzung auf den Tageshöchstsatz", percent => None::<String>, amount => money(amounts.cap_deduction) });
+    }
+    if amounts.contingent_deduction > 0.0 {
+        charges.push(context! { is_charge => "false", reason => "Abzug aus dem Stundenkontingent", percent => None::<String>, amount => money(amounts.contingent_deduction) });
+    }
+    charges.extend(amounts.adjustments.iter().map(adjustment_context));
+    // XRechnung's structured Skonto line (BT-20)
+    let skonto = amounts.skonto.map(|skonto| context! { days => skonto.days, percent => format!("{:.2}", skonto.percent) });
 
     let bill_date_formatted = &bill_date.format("%Y-%m-%d").to_string();
     let due_date_formatted = &due_date.format("%Y-%m-%d").to_string();
 
 
-    let template = env.get_template("ebill")?;
+    let template = env.get_template("ebill.xml")?;
     let xml = template.render(context! { 
         issuer_name => bill_config.name, 
         issuer_company => bill_config.company,
@@ -103,16 +184,27 @@ pub fn create_ebill_xml(billnr:&str, amounts: &Amounts, bill_date: NaiveDate, du
         receiver_street => company_config.address.addressline,
         receiver_city => company_config.address.city,
         receiver_postcode => company_config.address.postcode,
+        purchase_order => project_config.and_then(|project| project.purchase_order.clone()),
+        accounting_cost => project_config.and_then(|project| project.cost_center.clone()),
         bill_item => bill_config.bill_item,
         bill_item_description => bill_config.bill_item_description,
-        quantity => amounts.hours_total,
-        hourly_fee => amounts.hourly_fee,
-        amount_net => amount_net,
-        amount_vat => amount_vat,
-        amount_total => amount_total,
+        lines => lines,
+        charges => charges,
+        amount_lines => money(amount_lines),
+        amount_charges => money(amount_charges),
+        amount_allowances => money(amount_allowances),
+        amount_net => money(amounts.net),
+        amount_vat => money(amounts.vat),
+        amount_total => money(amounts.total),
+        currency => currency,
+        skonto => skonto,
+        payment_terms => payment_terms,
+        amount_vat_eur => amount_vat_eur,
         bill_number => billnr,
         bill_date => bill_date_formatted,
         due_date => due_date_formatted,
+        period_start => period.0.format("%Y-%m-%d").to_string(),
+        period_end => period.1.format("%Y-%m-%d").to_string(),
     })?;
 
 
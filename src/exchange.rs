@@ -0,0 +1,71 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::NaiveDate;
+use csv::ReaderBuilder;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ExchangeError {
+    #[error("Could not read the exchange rates {file:?}")]
+    Io { file: PathBuf, source: std::io::Error },
+    #[error("{file:?}: {message}")]
+    Invalid { file: PathBuf, message: String },
+    #[error("{file:?} has no {currency} rate on or before {date}")]
+    MissingRate { file: PathBuf, currency: String, date: NaiveDate },
+}
+
+type ExchangeResult<T> = Result<T, ExchangeError>;
+
+/// Units of a currency per euro, as published by the ECB
+#[derive(Debug, Clone, Copy)]
+pub struct Rate {
+    pub rate: f64,
+    pub date: NaiveDate,
+}
+
+// eurofxref-hist.csv uses ISO dates, the daily eurofxref.csv "31 December 2024"
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%d %B %Y"))
+        .ok()
+}
+
+/// The latest rate of `currency` on or before `date` from an ECB csv (the daily `eurofxref.csv` or
+/// the history `eurofxref-hist.csv`). Euros need no file
+pub fn rate_for(path: &Path, currency: &str, date: &NaiveDate) -> ExchangeResult<Rate> {
+    if currency.eq_ignore_ascii_case("EUR") {
+        return Ok(Rate { rate: 1.0, date: *date })
+    }
+
+    let invalid = |message: String| ExchangeError::Invalid { file: path.to_path_buf(), message };
+    let content = fs::read_to_string(path).map_err(|source| ExchangeError::Io { file: path.to_path_buf(), source })?;
+
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+
+    let headers = rdr.headers().map_err(|e| invalid(e.to_string()))?.clone();
+    if !headers.get(0).is_some_and(|header| header.eq_ignore_ascii_case("Date")) {
+        return Err(invalid(String::from("expected a Date column first")))
+    }
+    let Some(column) = headers.iter().position(|header| header.eq_ignore_ascii_case(currency)) else {
+        return Err(invalid(format!("no column for {}", currency)))
+    };
+
+    let mut best: Option<Rate> = None;
+    for result in rdr.records() {
+        let row = result.map_err(|e| invalid(e.to_string()))?;
+        let date_str = row.get(0).unwrap_or("");
+        let row_date = parse_date(date_str).ok_or_else(|| invalid(format!("invalid date {:?}", date_str)))?;
+        // Days without a rate are marked N/A
+        let Some(rate) = row.get(column).and_then(|value| value.parse::<f64>().ok()) else { continue };
+
+        if row_date <= *date && best.is_none_or(|best| row_date > best.date) {
+            best = Some(Rate { rate, date: row_date });
+        }
+    }
+
+    best.ok_or_else(|| ExchangeError::MissingRate { file: path.to_path_buf(), currency: currency.to_uppercase(), date: *date })
+}
@@ -0,0 +1,33 @@
+use chrono::NaiveDate;
+use thiserror::Error;
+use crate::date_utils;
+
+/// Parameters the web interface and the API both check the same way. Both answer them with 400
+#[derive(Debug, Error)]
+pub enum ParamError {
+    #[error("Invalid {name} {value:?}")]
+    Invalid { name: String, value: String },
+    #[error("Invalid month {0:?}, expected YYYY-MM")]
+    InvalidMonth(String),
+}
+
+pub fn encode(text: &str) -> String {
+    form_urlencoded::byte_serialize(text.as_bytes()).collect()
+}
+
+/// Company and project names end up in file names, so only plain names are accepted
+pub fn check_name(name: &str, value: &str) -> Result<(), ParamError> {
+    if value.is_empty() || value.starts_with('.') || value.contains(['/', '\\', '\'', '"']) {
+        return Err(ParamError::Invalid { name: name.to_string(), value: value.to_string() })
+    }
+    Ok(())
+}
+
+/// The billing date of a `YYYY-MM` month is its last day, like on the command line. Without a month it is the last day of the current month
+pub fn billdate_of_month(month: Option<&str>) -> Result<NaiveDate, Box<dyn std::error::Error>> {
+    let Some(month) = month.filter(|month| !month.is_empty()) else {
+        return date_utils::parse_date_or_default("")
+    };
+    let first = NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").map_err(|_| ParamError::InvalidMonth(month.to_string()))?;
+    Ok(date_utils::month_bounds(&first)?.1)
+}
@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use chrono::NaiveDate;
+use thiserror::Error;
+use crate::config_reader::{self, BankConfig, BillConfig, CompanyAddress, CompanyConfig, Config, CsvConfig, MailConfig, PathsConfig, PaymentTerms};
+use crate::date_utils::Calendar;
+
+static BASE_RATES: &str = include_str!("../sample/base_rates.csv");
+
+#[derive(Debug, Error)]
+#[allow(clippy::enum_variant_names)]
+pub enum InitError {
+    #[error("io Error")]
+    IoError(#[from] io::Error),
+    #[error("YAML serialization error")]
+    YamlError(#[from] serde_yaml::Error),
+    #[error("Invalid number: {0}")]
+    NumberError(String),
+}
+
+type InitResult<T> = Result<T, InitError>;
+
+fn prompt(question: &str, default: &str) -> InitResult<String> {
+    if default.is_empty() {
+        print!("{}: ", question);
+    } else {
+        print!("{} [{}]: ", question, default);
+    }
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim();
+
+    if answer.is_empty() {
+        Ok(default.to_string())
+    } else {
+        Ok(answer.to_string())
+    }
+}
+
+fn confirm(question: &str) -> InitResult<bool> {
+    let answer = prompt(&format!("{} (y/N)", question), "")?;
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn write_file_unless_declined(path: &Path, content: &str) -> InitResult<()> {
+    if path.exists() && !confirm(&format!("{:?} exists. Overwrite?", path))? {
+        println!("Keeping existing {:?}", path);
+        return Ok(());
+    }
+    fs::write(path, content)?;
+    println!("Wrote {:?}", path);
+    Ok(())
+}
+
+fn ask_config() -> InitResult<(Config, String)> {
+    println!("\nAbout you");
+    let name = prompt("Your name", "")?;
+    let company = prompt("Your company", &name)?;
+    let street = prompt("Street", "")?;
+    let postcode = prompt("Postcode", "")?;
+    let city = prompt("City", "")?;
+    let country = prompt("Country code", "DE")?;
+    let email = prompt("Mail address", "")?;
+    let telephone = prompt("Telephone", "")?;
+    let company_web = prompt("Website", "")?;
+    let vat_id = prompt("VAT ID", "")?;
+    let tax_id = prompt("Tax number", "")?;
+
+    println!("\nYour bank account");
+    let bank_name = prompt("Bank", "")?;
+    let iban = prompt("IBAN", "")?;
+    let bic = prompt("BIC", "")?;
+
+    println!("\nYour mail server (for drafts)");
+    let imap_server = prompt("IMAP server", "")?;
+    let imap_port = prompt("IMAP port", "993")?;
+    let imap_port = imap_port.parse().map_err(|_| InitError::NumberError(imap_port))?;
+
+    println!("\nYour first customer");
+    let customer = prompt("Short name (used for the csv file)", "SampleCompany")?;
+    let customer_name = prompt("Full name", &customer)?;
+    let customer_email = prompt("Mail address", "")?;
+    let customer_street = prompt("Street", "")?;
+    let customer_postcode = prompt("Postcode", "")?;
+    let customer_city = prompt("City", "")?;
+    let hourly_fee = prompt("Hourly fee", "100")?;
+    let hourly_fee = hourly_fee.parse().map_err(|_| InitError::NumberError(hourly_fee))?;
+
+    let mut companies = BTreeMap::new();
+    companies.insert(customer.clone(), CompanyConfig {
+        email: customer_email,
+        subject: format!("Rechnung {}", company),
+        greeting_to: String::from("Sehr geehrte Damen und Herren,"),
+        greeting_from: format!("Mit freundlichen Grüßen\n{}\n\n", name),
+        hourly_fee,
+        address: CompanyAddress {
+            name: customer_name,
+            city: customer_city,
+            postcode: customer_postcode,
+            addressline: customer_street,
+        },
+        source: None,
+        currency: String::from("EUR"),
+        rate_card: None,
+        recurring: Vec::new(),
+        contingents: Vec::new(),
+        budgets: Vec::new(),
+        projects: BTreeMap::new(),
+        project_billing: Default::default(),
+        discounts: Vec::new(),
+        surcharges: Vec::new(),
+        skonto: None,
+        payment_terms: None,
+        billing_period: Default::default(),
+        debtor_account: None,
+    });
+
+    let config = Config {
+        bank_config: BankConfig {
+            bank_name,
+            bic,
+            iban,
+            name: name.clone(),
+        },
+        bill_config: BillConfig {
+            header: String::from("Rechnung"),
+            company,
+            name: name.clone(),
+            street,
+            city,
+            country,
+            postcode,
+            email: email.clone(),
+            telephone_concise: telephone.replace(' ', ""),
+            telephone,
+            company_web,
+            bill_item: String::from("Dienstleistungen"),
+            bill_item_description: String::from("Dienstleistungen nach Aufwand"),
+            vat_id,
+            tax_id,
+            color: String::from("#99d0ba"),
+            text: String::from("anbei meine Rechnung für die geleisteten Stunden."),
+            number_format: None,
+            calendar: Calendar::default(),
+            payment_terms: PaymentTerms::default(),
+            service_period: Default::default(),
+        },
+        mailconfig: MailConfig {
+            email: email.clone(),
+            email_from: name,
+            imap_server,
+            imap_port,
+            email_text: String::from("anbei meine Rechnung für die geleisteten Stunden"),
+        },
+        companies,
+        paths: PathsConfig {
+            data: PathBuf::from("data"),
+            bills: PathBuf::from("bills"),
+            db: PathBuf::from("db.sql"),
+            rates: PathBuf::from("rates.csv"),
+            base_rates: PathBuf::from("base_rates.csv"),
+            root: PathBuf::new(),
+        },
+        csv: CsvConfig {
+            date_formats: vec![String::from("german"), String::from("iso"), String::from("us")],
+            delimiter: None,
+        },
+        dunning: Default::default(),
+        datev: None,
+    };
+
+    Ok((config, customer))
+}
+
+pub fn init(config_name: &str, billdate: &NaiveDate, data_dir: &Path) -> InitResult<()> {
+    println!("Setting up rustbill. Press enter to accept the value in brackets.");
+
+    let config_path = Path::new(config_name);
+    let (config, customer) = ask_config()?;
+    write_file_unless_declined(config_path, &serde_yaml::to_string(&config)?)?;
+    write_file_unless_declined(&config_reader::config_root(config_name).join(&config.paths.base_rates), BASE_RATES)?;
+
+    println!("\nMail credentials (stored in .env)");
+    let mail_user = prompt("Mail user", &config.mailconfig.email)?;
+    let mail_password = prompt("Mail password", "")?;
+    let env = format!("RUSTBILL_MAIL_USER={}\nRUSTBILL_MAIL_PASSWORD={}\n", mail_user, mail_password);
+    write_file_unless_declined(Path::new(".env"), &env)?;
+
+    fs::create_dir_all(data_dir)?;
+    println!("Created {:?}", data_dir);
+
+    let csv = format!(
+        "Date;Minutes;Description\n{date};60;Example entry\n",
+        date = billdate.format("%d.%m.%Y"),
+    );
+    write_file_unless_declined(&data_dir.join(format!("{}.csv", customer)), &csv)?;
+
+    println!("\nDone. Run `rustbill config check` to validate the setup.");
+    Ok(())
+}
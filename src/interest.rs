@@ -0,0 +1,211 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::{Days, Months, NaiveDate};
+use csv::ReaderBuilder;
+use thiserror::Error;
+use crate::config_reader::Config;
+use crate::db::Payment;
+
+#[derive(Debug, Error)]
+pub enum InterestError {
+    #[error("Could not read the Basiszinssatz table {file:?}")]
+    Io { file: PathBuf, source: std::io::Error },
+    #[error("{file:?}: {message}")]
+    Invalid { file: PathBuf, message: String },
+    #[error("{file:?} has no Basiszinssatz for {date}")]
+    MissingRate { file: PathBuf, date: NaiveDate },
+}
+
+type InterestResult<T> = Result<T, InterestError>;
+
+// Principals below half a cent are paid
+static TOLERANCE: f64 = 0.005;
+
+/// The Basiszinssatz from `valid_from` until the next entry, in percent
+#[derive(Debug, Clone, Copy)]
+pub struct BaseRate {
+    pub valid_from: NaiveDate,
+    pub rate: f64,
+}
+
+/// Reads the Basiszinssatz table, a csv with the columns `valid_from` (YYYY-MM-DD) and `rate`.
+/// The Bundesbank publishes the rate each January 1 and July 1
+pub fn read_base_rates(path: &Path) -> InterestResult<Vec<BaseRate>> {
+    let invalid = |message: String| InterestError::Invalid { file: path.to_path_buf(), message };
+    let content = fs::read_to_string(path).map_err(|source| InterestError::Io { file: path.to_path_buf(), source })?;
+
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(true)
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+
+    let mut rates = Vec::new();
+    for result in rdr.records() {
+        let row = result.map_err(|e| invalid(e.to_string()))?;
+        let (date_str, rate_str) = (row.get(0).unwrap_or(""), row.get(1).unwrap_or(""));
+        let valid_from = NaiveDate::parse_from_str(date_str, "%Y-%m-%d").map_err(|_| invalid(format!("invalid date {:?}", date_str)))?;
+        let rate = rate_str.parse::<f64>().map_err(|_| invalid(format!("invalid rate {:?}", rate_str)))?;
+        rates.push(BaseRate { valid_from, rate });
+    }
+    rates.sort_by_key(|rate| rate.valid_from);
+    Ok(rates)
+}
+
+/// The default interest in percent per year
+#[derive(Debug, Clone)]
+pub enum InterestRate {
+    Fixed(f64),
+    /// The Basiszinssatz plus `points` percentage points
+    Statutory { file: PathBuf, base_rates: Vec<BaseRate>, points: f64 },
+}
+
+impl InterestRate {
+    /// `dunning.interest_rate` if set, otherwise the statutory rate from `paths.base_rates`
+    pub fn from_config(config: &Config) -> InterestResult<Self> {
+        Ok(match config.dunning.interest_rate {
+            Some(rate) => InterestRate::Fixed(rate),
+            None => InterestRate::Statutory {
+                file: config.paths.base_rates.clone(),
+                base_rates: read_base_rates(&config.paths.base_rates)?,
+                points: config.dunning.interest_points,
+            },
+        })
+    }
+
+    pub fn at(&self, date: &NaiveDate) -> InterestResult<f64> {
+        match self {
+            InterestRate::Fixed(rate) => Ok(*rate),
+            InterestRate::Statutory { file, base_rates, points } => base_rates
+                .iter()
+                .rev()
+                .find(|base_rate| base_rate.valid_from <= *date)
+                .map(|base_rate| base_rate.rate + points)
+                .ok_or_else(|| InterestError::MissingRate { file: file.clone(), date: *date }),
+        }
+    }
+
+    /// The first day after `date` the rate changes on
+    fn next_change(&self, date: &NaiveDate) -> Option<NaiveDate> {
+        match self {
+            InterestRate::Fixed(_) => None,
+            InterestRate::Statutory { base_rates, .. } => base_rates.iter().map(|base_rate| base_rate.valid_from).find(|from| from > date),
+        }
+    }
+
+    /// Warns if `date` is past the half year of the last Basiszinssatz, which is likely outdated then
+    fn check_current(&self, date: &NaiveDate) {
+        if let InterestRate::Statutory { file, base_rates, .. } = self {
+            let outdated = base_rates.last().and_then(|last| last.valid_from.checked_add_months(Months::new(6))).is_some_and(|end| *date >= end);
+            if outdated {
+                println!("Warning: the last Basiszinssatz in {:?} may be outdated for {}", file, date);
+            }
+        }
+    }
+}
+
+/// A stretch with the same principal and rate
+#[derive(Debug, Clone)]
+pub struct InterestPeriod {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub days: i64,
+    pub principal: f64,
+    /// In percent per year
+    pub rate: f64,
+    pub interest: f64,
+}
+
+/// The default interest on a bill from the day after it was due
+#[derive(Debug, Clone)]
+pub struct InterestStatement {
+    pub due: NaiveDate,
+    /// The last day interest is charged for
+    pub until: NaiveDate,
+    pub periods: Vec<InterestPeriod>,
+    pub interest: f64,
+    /// The Verzugspauschale, 0 if none is owed
+    pub flat_fee: f64,
+}
+
+impl InterestStatement {
+    pub fn total(&self) -> f64 {
+        self.interest + self.flat_fee
+    }
+
+    /// A line per period and the flat fee, with the amounts formatted by `format_money`
+    pub fn items(&self, format_money: impl Fn(f64) -> String) -> Vec<(String, f64)> {
+        let mut items: Vec<(String, f64)> = self.periods
+            .iter()
+            .map(|period| (
+                format!(
+                    "Verzugszinsen {} – {}: {} % p. a. auf {} für {} Tage",
+                    period.from.format("%d.%m.%Y"), period.to.format("%d.%m.%Y"), format!("{:.2}", period.rate).replace('.', ","),
+                    format_money(period.principal), period.days,
+                ),
+                period.interest,
+            ))
+            .collect();
+        if self.flat_fee > 0.0 {
+            items.push((String::from("Verzugspauschale nach § 288 Abs. 5 BGB"), self.flat_fee));
+        }
+        items
+    }
+}
+
+/// Interest and the Verzugspauschale invoiced on a bill before
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Invoiced {
+    /// Interest and fee
+    pub amount: f64,
+    /// The last day interest was invoiced for
+    pub until: Option<NaiveDate>,
+}
+
+/// Simple interest on `amount` from the day after `due` (or after the interest `invoiced` before) up to `until`.
+/// Payments reduce the principal from their day on, after they covered what was `invoiced` (§ 367 BGB).
+/// The periods are split where the rate changes. Each period is rounded to cents.
+/// `flat_fee` is owed if anything was still open after the due date
+pub fn statement(amount: f64, due: &NaiveDate, payments: &[Payment], until: &NaiveDate, rate: &InterestRate, flat_fee: f64, invoiced: &Invoiced) -> InterestResult<InterestStatement> {
+    let mut payments: Vec<(NaiveDate, f64)> = payments.iter().filter_map(|payment| Some((payment.date()?, payment.amount))).collect();
+    payments.sort_by_key(|(date, _)| *date);
+    rate.check_current(until);
+
+    let mut periods = Vec::new();
+    let mut principal = amount;
+    let mut costs = invoiced.amount;
+    let mut cursor = invoiced.until.map_or(*due, |invoiced_until| invoiced_until.max(*due)).checked_add_days(Days::new(1));
+    while let Some(from) = cursor.filter(|from| from <= until) {
+        let paid = payments.iter().filter(|(date, _)| *date <= from).map(|(_, paid)| paid).sum::<f64>();
+        let paid_costs = paid.min(costs);
+        costs -= paid_costs;
+        principal -= paid - paid_costs;
+        payments.retain(|(date, _)| *date > from);
+        if principal < TOLERANCE {
+            break
+        }
+
+        let next_payment = payments.first().map(|(date, _)| *date);
+        let next = [rate.next_change(&from), next_payment].into_iter().flatten().min();
+        let to = next.and_then(|next| next.pred_opt()).map_or(*until, |last| last.min(*until));
+        let days = (to - from).num_days() + 1;
+        let period_rate = rate.at(&from)?;
+        periods.push(InterestPeriod {
+            from,
+            to,
+            days,
+            principal,
+            rate: period_rate,
+            interest: (principal * period_rate / 100.0 * days as f64 / 365.0 * 100.0).round() / 100.0,
+        });
+        cursor = to.succ_opt();
+    }
+
+    let overdue = !periods.is_empty();
+    Ok(InterestStatement {
+        due: *due,
+        until: *until,
+        interest: periods.iter().map(|period| period.interest).sum(),
+        periods,
+        flat_fee: if overdue { flat_fee } else { 0.0 },
+    })
+}
@@ -0,0 +1,50 @@
+//! Bills hourly work from timesheets: reads the time entries of each company, renders a PDF with an embedded
+//! e-invoice, keeps the bill numbers in a SQLite database and uploads mail drafts.
+//!
+//! The pipeline is [`Biller`]. Each of its stages can be replaced, e.g. to read the entries from somewhere else:
+//!
+//! ```no_run
+//! use chrono::NaiveDate;
+//! use rustbill::Biller;
+//!
+//! let biller = Biller::from_config_file("config.yaml", &[])?;
+//! let billdate = NaiveDate::from_ymd_opt(2024, 12, 31).unwrap();
+//! for company in biller.companies(&billdate)? {
+//!     for (bill, filename) in biller.bill(&company, &billdate, false)? {
+//!         println!("{}: {} saved as {}", company, bill.billnr, filename);
+//!     }
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+pub mod pdf_gen;
+pub mod date_utils;
+pub mod csv_reader;
+pub mod config_reader;
+pub mod qrcode;
+pub mod sign;
+pub mod ebill;
+pub mod calculate;
+pub mod db;
+pub mod mail;
+pub mod check;
+pub mod init;
+pub mod validate;
+pub mod time_source;
+pub mod track;
+pub mod billing;
+pub mod exchange;
+pub mod budget;
+pub mod payments;
+pub mod dunning;
+pub mod interest;
+pub mod datev;
+pub mod report;
+#[cfg(any(feature = "web", feature = "api"))]
+mod http;
+#[cfg(feature = "web")]
+pub mod web;
+#[cfg(feature = "api")]
+pub mod api;
+
+pub use billing::{Bill, Biller};
+pub use config_reader::Config;
@@ -1,5 +1,3 @@
-use imap;
-use native_tls;
 use native_tls::TlsStream;
 use chrono::{NaiveDate, Locale};
 use std::net::TcpStream;
@@ -7,9 +5,11 @@ use std::env;
 use lettre::message::{header::ContentType, header, Attachment, SinglePart, MultiPart, Message};
 use dotenv::dotenv;
 use crate::config_reader::{CompanyConfig, MailConfig};
+use crate::date_utils;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum MailError{
     #[error("IMAP error. Check credentials or connection.")]
     IMAPError(#[from] imap::error::Error),
@@ -30,7 +30,7 @@ fn get_imap_session(config: &MailConfig) -> MailResult<imap::Session<TlsStream<T
     let mailuser = env::var("RUSTBILL_MAIL_USER")?;
     let mailpass = env::var("RUSTBILL_MAIL_PASSWORD")?;
     let tls = native_tls::TlsConnector::builder().build()?;
-    let client = imap::connect((config.imap_server.to_string(), config.imap_port), config.imap_server.to_string(), &tls)?;
+    let client = imap::connect((config.imap_server.to_string(), config.imap_port), &config.imap_server, &tls)?;
 
     let imap_session = client
         .login(&mailuser, mailpass)
@@ -39,26 +39,41 @@ fn get_imap_session(config: &MailConfig) -> MailResult<imap::Session<TlsStream<T
     Ok(imap_session)
 }
 
-pub fn create_mail_draft(mailconfig_global: &MailConfig, mailconfig_company: &CompanyConfig, billdate: &NaiveDate, pdf_content: Vec<u8>, pdf_name: &str) -> MailResult<()> {
+/// `period` is the billed period, named by its month if it is a calendar month
+pub fn create_mail_draft(mailconfig_global: &MailConfig, mailconfig_company: &CompanyConfig, period: &(NaiveDate, NaiveDate), pdf_content: Vec<u8>, pdf_name: &str) -> MailResult<()> {
+
+    let mail_text = if date_utils::is_month(period) {
+        format!("{text} im {month} {year}.",
+            text=mailconfig_global.email_text,
+            month=period.0.format_localized("%B", Locale::de_DE),
+            year=period.0.format("%Y"),
+        )
+    } else {
+        format!("{text} vom {from} bis zum {to}.",
+            text=mailconfig_global.email_text,
+            from=period.0.format("%d.%m.%Y"),
+            to=period.1.format("%d.%m.%Y"),
+        )
+    };
+
+    create_draft(mailconfig_global, mailconfig_company, &mailconfig_company.subject, &mail_text, pdf_content, pdf_name)
+}
 
-    let mut imap_session = get_imap_session(mailconfig_global)?;
+/// Stores a mail to the company with the greetings around `body` and the PDF attached in the drafts folder
+pub fn create_draft(mailconfig_global: &MailConfig, mailconfig_company: &CompanyConfig, subject: &str, body: &str, pdf_content: Vec<u8>, pdf_name: &str) -> MailResult<()> {
 
-    let mail_text = format!("{text} im {month} {year}.",
-        text=mailconfig_global.email_text,
-        month=billdate.format_localized("%B", Locale::de_DE),
-        year=billdate.format("%Y"),
-    );
+    let mut imap_session = get_imap_session(mailconfig_global)?;
 
     let text = format!(
         "{greeting_to}\n\n{body}\n\n{greeting_from}\n",
         greeting_to = mailconfig_company.greeting_to,
-        body = mail_text,
+        body = body,
         greeting_from = mailconfig_company.greeting_from,
     );
 
     let textmail = SinglePart::builder()
         .header(header::ContentType::TEXT_PLAIN)
-        .body(String::from(text));
+        .body(text);
 
     let content_type = ContentType::parse("application/pdf")?;
     let attachment = Attachment::new(pdf_name.to_string()).body(pdf_content, content_type);
@@ -66,7 +81,7 @@ pub fn create_mail_draft(mailconfig_global: &MailConfig, mailconfig_company: &Co
     let email = Message::builder()
         .from(mailconfig_global.email.parse().unwrap())
         .to(mailconfig_company.email.parse().unwrap())
-        .subject(mailconfig_company.subject.to_string())
+        .subject(subject.to_string())
         .multipart(
             MultiPart::mixed()
                 .singlepart(textmail)
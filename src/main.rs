@@ -1,56 +1,290 @@
-use std::path::Path;
-use clap::Parser;
+use std::path::PathBuf;
+use clap::{Parser, Subcommand, ValueEnum};
 use rand::thread_rng;
 use rand::seq::SliceRandom;
-use chrono::Datelike;
-use config_reader::read_config;
-mod pdf_gen;
-mod date_utils;
-mod csv_reader;
-mod config_reader;
-mod qrcode;
-mod sign;
-mod ebill;
-mod calculate;
-mod db;
-mod mail;
+use chrono::NaiveDate;
+use rustbill::config_reader::{self, read_config};
+use rustbill::{billing, budget, check, date_utils, datev, dunning, init, payments, report, track, Biller};
+#[cfg(feature = "web")]
+use rustbill::web;
+#[cfg(feature = "api")]
+use rustbill::api;
 
 #[derive(Parser, Debug)]
 #[command(name = "cli_parser")]
 #[command(about = "A parser with a default argument (the company) and an optional date flag")]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     #[arg(short, long)]
     company: Option<String>,
-    #[arg(short, long, default_value_t = String::from(""))] // Optional date in YYYY-MM-DD format
+    #[arg(short, long, global = true, default_value_t = String::from(""))] // Optional date in YYYY-MM-DD format
     date: String,
     #[arg(short, long)]
     maildraft: bool,
-    #[arg(long, default_value_t = String::from("config.yaml"))] // to create a mail draft
+    #[arg(long)] // Fail if a timesheet has warnings
+    strict: bool,
+    #[arg(long, requires = "to")] // Bill from this day (YYYY-MM-DD) instead of the billing period of each company
+    from: Option<NaiveDate>,
+    #[arg(long, requires = "from")] // Last day billed, also the bill date unless --date is given
+    to: Option<NaiveDate>,
+    #[arg(long, requires = "service_to")] // First day of the Leistungszeitraum stated on the bills, instead of the first entry
+    service_from: Option<NaiveDate>,
+    #[arg(long, requires = "service_from")] // Last day of the Leistungszeitraum stated on the bills
+    service_to: Option<NaiveDate>,
+    #[arg(long, global = true, default_value_t = String::from("config.yaml"))] // to create a mail draft
     config: String,
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")] // Override a config value, e.g. mailconfig.imap_server=imap.example.com
+    overrides: Vec<String>,
+    #[arg(long, global = true)] // Directory containing the YYYY-MM timesheet directories
+    data_dir: Option<PathBuf>,
+    #[arg(long, global = true)] // Directory the bills are saved to
+    bills_dir: Option<PathBuf>,
+    #[arg(long, global = true)] // Location of the bill database
+    db: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Interactively create config.yaml, .env, the data directory and a sample csv
+    Init,
+    /// Work with the config
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Track time in the timesheets
+    Track {
+        #[command(subcommand)]
+        command: TrackCommand,
+    },
+    /// Show the budgets of all companies (or --company) including the billing period of --date
+    Budget {
+        #[arg(short, long)]
+        company: Option<String>,
+    },
+    /// Import bank statements and assign payments to bills
+    Payments {
+        #[command(subcommand)]
+        command: PaymentsCommand,
+    },
+    /// List the unpaid bills as of --date (default: today) with their due date and dunning level
+    Open,
+    /// Create the dunning letters due on --date (default: today)
+    Dunning {
+        #[arg(short, long)]
+        company: Option<String>,
+        #[arg(short, long)] // Also create mail drafts
+        maildraft: bool,
+    },
+    /// Bill the default interest on a late bill up to --date (default: today) or its payment
+    Interest {
+        billnr: String,
+        #[arg(short, long)] // Also create a mail draft
+        maildraft: bool,
+    },
+    /// Revenue and VAT of a month, quarter or year, for the advance VAT return
+    Report {
+        #[arg(long)]
+        year: i32,
+        #[arg(long, conflicts_with = "quarter")] // 1 to 12
+        month: Option<u32>,
+        #[arg(long)] // 1 to 4
+        quarter: Option<u32>,
+        #[arg(long, value_enum, default_value_t = ReportFormat::Table)]
+        format: ReportFormat,
+        #[arg(short, long)] // For csv and pdf. Default: Umsatzbericht_<period> in the bills directory
+        output: Option<PathBuf>,
+    },
+    /// Export the bills for accounting
+    Export {
+        #[command(subcommand)]
+        command: ExportCommand,
+    },
+    /// Serve the web frontend
+    #[cfg(feature = "web")]
+    Serve {
+        #[arg(long, default_value_t = String::from("127.0.0.1:8080"))]
+        addr: String,
+    },
+    /// Serve the JSON API. Needs RUSTBILL_API_TOKEN
+    #[cfg(feature = "api")]
+    Api {
+        #[arg(long, default_value_t = String::from("127.0.0.1:8081"))]
+        addr: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum TrackCommand {
+    /// Start a timer
+    Start {
+        company: String,
+        description: String,
+    },
+    /// Stop the running timer and log the time
+    Stop,
+    /// Log minutes worked, on the day given with --date (default: today)
+    Log {
+        company: String,
+        minutes: i32,
+        description: String,
+    },
+    /// List the running timer and the entries of the month
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+enum PaymentsCommand {
+    /// Match the credits of a CAMT.053 (xml) or MT940 statement to the unpaid bills
+    Import {
+        file: PathBuf,
+    },
+    /// List the payments that could not be matched to a bill
+    List,
+    /// Assign an unmatched payment to a bill
+    Assign {
+        id: i32,
+        billnr: String,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ReportFormat {
+    Table,
+    Csv,
+    Pdf,
+}
+
+#[derive(Subcommand, Debug)]
+enum ExportCommand {
+    /// Write the bills of a month (or year) as a DATEV Buchungsstapel (EXTF csv)
+    Datev {
+        #[arg(long)]
+        year: i32,
+        #[arg(long)] // Only this month, 1 to 12
+        month: Option<u32>,
+        #[arg(short, long)] // Default: EXTF_Buchungsstapel_YYYY_MM.csv in the bills directory
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Validate the config, check all companies of the month have an entry and compile the templates
+    Check,
+}
+
+// Paths given on the command line are relative to the working directory, not to the config
+fn path_overrides(args: &Args) -> std::io::Result<Vec<String>> {
+    let mut overrides = args.overrides.clone();
+    let paths = [("data", &args.data_dir), ("bills", &args.bills_dir), ("db", &args.db)];
+
+    for (key, path) in paths {
+        if let Some(path) = path {
+            let path = std::path::absolute(path)?;
+            overrides.push(format!("paths.{}={}", key, path.to_string_lossy()));
+        }
+    }
+    Ok(overrides)
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
-    let date = args.date;
-    let config_name = &args.config;
-    let config = read_config(&config_name)?;
-
-    db::create_db_if_needed()?;
-
-    let billdate = date_utils::parse_date_or_default(&date)?;
-    let billdate_formatted = &billdate.format("%d.%m.%Y").to_string();
-    let duedate = date_utils::calculate_due_date(billdate)?;
-    let duedate_formatted = &duedate.format("%d.%m.%Y").to_string();
-
-    let basedir_data = Path::new("data");
-    let subdir_data_str = format!(
-        "{year}-{month:02}",
-        year = billdate.year(),
-        month=billdate.month(),
-    );
-    let data_dir = &basedir_data.join(&subdir_data_str);
-    let binding = data_dir.to_string_lossy().replace("data", "bills");
-    let pdfdir = Path::new(&binding);
+    let billdate = match args.to {
+        Some(to) if args.date.is_empty() => to,
+        _ => date_utils::parse_date_or_default(&args.date)?,
+    };
+    let overrides = path_overrides(&args)?;
+
+    match &args.command {
+        Some(Command::Init) => {
+            let data = args.data_dir.clone().unwrap_or_else(|| config_reader::config_root(&args.config).join("data"));
+            init::init(&args.config, &billdate, &data.join(date_utils::month_dir_name(&billdate)))?
+        },
+        Some(Command::Config { command: ConfigCommand::Check }) => check::check_config(&args.config, &overrides, &billdate)?,
+        Some(Command::Track { command }) => {
+            let config = read_config(&args.config, &overrides)?;
+            match command {
+                TrackCommand::Start { company, description } => track::start(&config, company, description)?,
+                TrackCommand::Stop => track::stop(&config)?,
+                TrackCommand::Log { company, minutes, description } => {
+                    let date = date_utils::parse_date_or_today(&args.date)?;
+                    track::log(&config, company, *minutes, description, &date)?
+                },
+                TrackCommand::List => track::list(&config, &billdate)?,
+            }
+        },
+        Some(Command::Budget { company }) => {
+            let biller = Biller::from_config_file(&args.config, &overrides)?;
+            budget::report(&biller, company.as_slice(), &billdate)?
+        },
+        Some(Command::Payments { command }) => {
+            let config = read_config(&args.config, &overrides)?;
+            match command {
+                PaymentsCommand::Import { file } => payments::import(&config, file)?,
+                PaymentsCommand::List => payments::list(&config)?,
+                PaymentsCommand::Assign { id, billnr } => payments::assign(&config, *id, billnr)?,
+            }
+        },
+        Some(Command::Open) => {
+            let date = date_utils::parse_date_or_today(&args.date)?;
+            dunning::report(&read_config(&args.config, &overrides)?, &date)?
+        },
+        Some(Command::Dunning { company, maildraft }) => {
+            let date = date_utils::parse_date_or_today(&args.date)?;
+            dunning::dun(&read_config(&args.config, &overrides)?, &date, company.as_deref(), *maildraft)?
+        },
+        Some(Command::Interest { billnr, maildraft }) => {
+            let date = date_utils::parse_date_or_today(&args.date)?;
+            dunning::interest_invoice(&read_config(&args.config, &overrides)?, billnr, &date, *maildraft)?
+        },
+        Some(Command::Report { year, month, quarter, format, output }) => {
+            let config = read_config(&args.config, &overrides)?;
+            let period = match (month, quarter) {
+                (Some(month), _) => report::Period::Month(*month),
+                (None, Some(quarter)) => report::Period::Quarter(*quarter),
+                (None, None) => report::Period::Year,
+            };
+            let report = report::build(&config, *year, period)?;
+            match format {
+                ReportFormat::Table => report::print(&report),
+                ReportFormat::Csv => report::write_csv(&config, &report, output.as_deref())?,
+                ReportFormat::Pdf => report::write_pdf(&config, &report, output.as_deref())?,
+            }
+        },
+        Some(Command::Export { command: ExportCommand::Datev { year, month, output } }) => {
+            datev::export(&read_config(&args.config, &overrides)?, *year, *month, output.as_deref())?
+        },
+        #[cfg(feature = "web")]
+        Some(Command::Serve { addr }) => web::serve(read_config(&args.config, &overrides)?, addr)?,
+        #[cfg(feature = "api")]
+        Some(Command::Api { addr }) => api::serve(read_config(&args.config, &overrides)?, addr)?,
+        None => run_billing(args, &overrides, billdate)?,
+    }
+
+    Ok(())
+}
+
+fn run_billing(args: Args, overrides: &[String], billdate: NaiveDate) -> Result<(), Box<dyn std::error::Error>> {
+    let mut biller = Biller::from_config_file(&args.config, overrides)?;
+    if args.maildraft {
+        biller = biller.with_deliver(billing::MailDraft);
+    }
+    if let (Some(from), Some(to)) = (args.from, args.to) {
+        if from > to {
+            return Err(format!("--from {} is after --to {}", from, to).into())
+        }
+        biller = biller.with_period(from, to);
+    }
+    if let (Some(from), Some(to)) = (args.service_from, args.service_to) {
+        if from > to {
+            return Err(format!("--service-from {} is after --service-to {}", from, to).into())
+        }
+        biller = biller.with_service_period(from, to);
+    }
+
+    let data_dir = &biller.config().paths.data_dir(&billdate);
 
     println!("Data dir is {:?}", data_dir);
 
@@ -63,7 +297,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             all_companies.push(s)
         },
         None => {
-            all_companies = csv_reader::find_all_companies(data_dir)?;
+            all_companies = biller.companies(&billdate)?;
         }
     }
     println!("Running for the following companies: {:#?}", all_companies);
@@ -74,75 +308,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     for company_str in all_companies {
         println!("Processing {:}", &company_str);
-        let mut file_str = company_str.clone();
-        if !file_str.ends_with(".csv") {
-            file_str.push_str(".csv");
-        }
-
-        let csv_path = data_dir.join(file_str);
-        let company = String::from(&company_str);
-
-        println!("Trying to read csv from {:?}", &csv_path);
-
-        let csv_data = csv_reader::read_csv(&csv_path)?;
 
-        let minutes_total = csv_reader::extract_minutes_total(&csv_data)?;
-
-        if minutes_total == 0 {
+        if biller.bill(&company_str, &billdate, args.strict)?.is_empty() {
             println!("{:?} has no entries. This is expected for some. Skipping\n", &company_str);
             continue;
         }
 
-        let (billnr, billnr_int) = match db::get_billnr_if_exists(&company, &billdate)? {
-            Some((billnr, billnr_int)) => (billnr, billnr_int),
-            None => {
-                let (billnr, billnr_int) = db::get_new_billnr(&billdate, &subdir_data_str)?;
-                (billnr, billnr_int)
-            }
-        };
-        println!("The bill number is {:?}", billnr);
-
-        let company_config = config_reader::get_company_config(&config_name, &company_str)?;
-
-        let amounts = calculate::calculate_amounts(&minutes_total, &company_config.hourly_fee)?;
-
-        let decimal_amount_str = calculate::to_euro_string(&amounts.total)?;
-
-        let qrcode = qrcode::create_qrcode(&config.bank_config, &decimal_amount_str, &billdate, &billnr, &config.bill_config.color)?;
-
-        let pdf_content = pdf_gen::Content {
-            company: company.to_string(),
-            billnr: billnr.clone(),
-            vat: amounts.vat.clone(),
-            date: billdate_formatted.clone(),
-            due: duedate_formatted.clone(),
-            qrcode,
-            hourly_fee: amounts.hourly_fee,
-            data_dir: data_dir.to_string_lossy().into_owned(),
-            config_name: config_name.clone(),
-        };
-
-        let pdf_data = pdf_gen::generate_pdf(pdf_content)?;
-        println!("Generated pdf");
-
-        // let signed_pdf_data = sign::sign_pdf(pdf_data)?;
-
-        let xml = ebill::create_ebill_xml(&billnr, &amounts, billdate, duedate, &config.bill_config, &company_config, &config.bank_config)?;
-
-        let pdf_with_xml = ebill::add_xml_to_pdf(&pdf_data, xml)?;
-    
-        let saved_pdf_filename = pdf_gen::save_pdf(&pdf_with_xml, pdfdir, billdate, &company_str)?;
-
-        let _ = db::add_to_db(&company, &billdate, &billnr, &amounts.total, &decimal_amount_str, &billnr_int);
-
-        if args.maildraft {
-            let _ = mail::create_mail_draft(&config.mailconfig, &company_config, &billdate,pdf_with_xml, &saved_pdf_filename)?;
-        }
-        
         println!("{:}: Done\n", &company_str);
     }
 
     // println!("\nTABLE AFTER EVERYTHING:\n");
     // let _ = db::print_all_db_entries()?;
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,403 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::NaiveDate;
+use roxmltree::{Document, Node};
+use thiserror::Error;
+use crate::config_reader::Config;
+use crate::db::{DBEntry, DBError, Db, Payment};
+
+#[derive(Debug, Error)]
+pub enum PaymentError {
+    #[error("Could not read {file:?}")]
+    Io { file: PathBuf, source: std::io::Error },
+    #[error("{file:?}: invalid CAMT.053 xml")]
+    Xml { file: PathBuf, source: roxmltree::Error },
+    #[error("{file:?}, line {line}: {message}")]
+    InvalidStatement { file: PathBuf, line: usize, message: String },
+    #[error("No payment with id {0}")]
+    UnknownPayment(i32),
+    #[error("Payment {id} is already assigned to bill {billnr}")]
+    AlreadyAssigned { id: i32, billnr: String },
+    #[error("No bill {0:?}")]
+    UnknownBill(String),
+    #[error(transparent)]
+    DB(#[from] DBError),
+}
+
+type PaymentResult<T> = Result<T, PaymentError>;
+
+// Amounts closer than half a cent are equal
+static TOLERANCE: f64 = 0.005;
+
+/// A credit on a bank statement
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    /// The bank's reference, or one built from the other fields if the statement has none
+    pub id: String,
+    pub date: NaiveDate,
+    pub amount: f64,
+    pub currency: String,
+    pub payer: String,
+    /// The remittance information, e.g. "RE 2024-1201 vom 31.12.2024" as written by the QR code of the bill
+    pub reference: String,
+}
+
+impl Transaction {
+    fn new(id: Option<String>, date: NaiveDate, amount: f64, currency: String, payer: String, reference: String) -> Self {
+        let id = id.unwrap_or_else(|| format!("{}|{:.2}|{}|{}", date, amount, payer, reference));
+        Transaction { id, date, amount, currency, payer, reference }
+    }
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, path: &[&str]) -> Option<Node<'a, 'input>> {
+    path.iter().try_fold(node, |node, name| node.children().find(|child| child.tag_name().name() == *name))
+}
+
+fn child_text<'a>(node: Node<'a, '_>, path: &[&str]) -> Option<&'a str> {
+    child(node, path)?.text().map(str::trim).filter(|text| !text.is_empty())
+}
+
+fn descendants_text(node: Node, name: &str) -> String {
+    node.descendants()
+        .filter(|child| child.tag_name().name() == name)
+        .filter_map(|child| child.text().map(str::trim))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The credits of a CAMT.053 statement. Entries with several transactions (batch bookings) yield one per transaction
+pub fn parse_camt(file: &Path, content: &str) -> PaymentResult<Vec<Transaction>> {
+    let document = Document::parse(content).map_err(|source| PaymentError::Xml { file: file.to_path_buf(), source })?;
+    let invalid = |node: Node, message: &str| PaymentError::InvalidStatement {
+        file: file.to_path_buf(),
+        line: document.text_pos_at(node.range().start).row as usize,
+        message: message.to_string(),
+    };
+
+    let mut transactions = Vec::new();
+    for entry in document.descendants().filter(|node| node.tag_name().name() == "Ntry") {
+        if child_text(entry, &["CdtDbtInd"]) != Some("CRDT") {
+            continue
+        }
+        let date = child_text(entry, &["BookgDt", "Dt"])
+            .or_else(|| child_text(entry, &["BookgDt", "DtTm"]))
+            .or_else(|| child_text(entry, &["ValDt", "Dt"]))
+            .and_then(|date| NaiveDate::parse_from_str(date.get(..10)?, "%Y-%m-%d").ok())
+            .ok_or_else(|| invalid(entry, "entry without a booking date"))?;
+        let amount_node = child(entry, &["Amt"]).ok_or_else(|| invalid(entry, "entry without an amount"))?;
+        let amount = |node: Node| node.text().and_then(|amount| amount.trim().parse::<f64>().ok()).ok_or_else(|| invalid(node, "invalid amount"));
+        let entry_amount = amount(amount_node)?;
+        let entry_currency = amount_node.attribute("Ccy").unwrap_or("EUR");
+        let entry_ref = child_text(entry, &["AcctSvcrRef"]);
+
+        let details: Vec<Node> = entry.descendants().filter(|node| node.tag_name().name() == "TxDtls").collect();
+        if details.is_empty() {
+            transactions.push(Transaction::new(entry_ref.map(str::to_string), date, entry_amount, entry_currency.to_string(), String::new(), String::new()));
+            continue
+        }
+
+        for (index, details_node) in details.iter().enumerate() {
+            let tx_amount_node = child(*details_node, &["AmtDtls", "TxAmt", "Amt"]).or_else(|| child(*details_node, &["Amt"]));
+            let (tx_amount, currency) = match tx_amount_node {
+                Some(node) => (amount(node)?, node.attribute("Ccy").unwrap_or(entry_currency)),
+                None if details.len() == 1 => (entry_amount, entry_currency),
+                None => return Err(invalid(*details_node, "transaction of a batch booking without an amount")),
+            };
+            let payer = child_text(*details_node, &["RltdPties", "Dbtr", "Nm"])
+                .or_else(|| child_text(*details_node, &["RltdPties", "Dbtr", "Pty", "Nm"]))
+                .or_else(|| child_text(*details_node, &["RltdPties", "UltmtDbtr", "Nm"]))
+                .unwrap_or_default();
+            let reference = child(*details_node, &["RmtInf"])
+                .map(|remittance| [descendants_text(remittance, "Ustrd"), descendants_text(remittance, "Ref")].join(" ").trim().to_string())
+                .unwrap_or_default();
+            let id = child_text(*details_node, &["Refs", "AcctSvcrRef"])
+                .map(str::to_string)
+                .or_else(|| entry_ref.map(|entry_ref| if details.len() == 1 { entry_ref.to_string() } else { format!("{}/{}", entry_ref, index + 1) }));
+            transactions.push(Transaction::new(id, date, tx_amount, currency.to_string(), payer.to_string(), reference));
+        }
+    }
+    Ok(transactions)
+}
+
+// The fields of an MT940 file with their line, continuation lines appended
+fn mt940_fields(content: &str) -> Vec<(usize, String, String)> {
+    let mut fields: Vec<(usize, String, String)> = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim_end();
+        let tag = line.strip_prefix(':').and_then(|rest| rest.split_once(':'));
+        match (tag, fields.last_mut()) {
+            (Some((tag, value)), _) if !tag.is_empty() && tag.len() <= 3 => fields.push((index + 1, tag.to_string(), value.to_string())),
+            (_, Some((_, _, value))) if line != "-" => value.push_str(line),
+            _ => {},
+        }
+    }
+    fields
+}
+
+// German banks structure field 86 with `?NN` subfields: 20-29 and 60-63 are the purpose, 32-33 the payer
+fn parse_mt940_info(info: &str) -> (String, String) {
+    if !info.contains('?') {
+        return (String::new(), info.trim().to_string())
+    }
+    let (mut payer, mut reference) = (String::new(), String::new());
+    for part in info.split('?').skip(1) {
+        let (code, text) = (part.get(..2).unwrap_or(part), part.get(2..).unwrap_or_default());
+        match code {
+            "20" | "21" | "22" | "23" | "24" | "25" | "26" | "27" | "28" | "29" | "60" | "61" | "62" | "63" => reference.push_str(text),
+            "32" | "33" => payer.push_str(text),
+            _ => {},
+        }
+    }
+    (payer.trim().to_string(), reference.trim().to_string())
+}
+
+/// The credits of an MT940 statement
+pub fn parse_mt940(file: &Path, content: &str) -> PaymentResult<Vec<Transaction>> {
+    let invalid = |line: usize, message: &str| PaymentError::InvalidStatement { file: file.to_path_buf(), line, message: message.to_string() };
+
+    let mut transactions = Vec::new();
+    let mut currency = String::from("EUR");
+    // A statement line waits for its information field
+    let mut pending: Option<(Option<String>, NaiveDate, f64)> = None;
+
+    for (line, tag, value) in mt940_fields(content) {
+        match tag.as_str() {
+            "60F" | "60M" => currency = value.get(7..10).unwrap_or("EUR").to_string(),
+            "61" => {
+                if let Some((id, date, amount)) = pending.take() {
+                    transactions.push(Transaction::new(id, date, amount, currency.clone(), String::new(), String::new()));
+                }
+                let date = value.get(..6)
+                    .and_then(|date| NaiveDate::parse_from_str(date, "%y%m%d").ok())
+                    .ok_or_else(|| invalid(line, "statement line without a valid date"))?;
+                let mut rest = &value[6..];
+                // The optional booking date
+                if rest.get(..4).is_some_and(|booking_date| booking_date.chars().all(|c| c.is_ascii_digit())) {
+                    rest = &rest[4..];
+                }
+                // C credits, RD reverses a debit, which credits the account as well
+                let credit = rest.starts_with('C') || rest.starts_with("RD");
+                rest = rest.trim_start_matches(['R', 'C', 'D']);
+                // The optional funds code, the last letter of the currency
+                if rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                    rest = &rest[1..];
+                }
+                let amount_str: String = rest.chars().take_while(|c| c.is_ascii_digit() || *c == ',').collect();
+                let amount: f64 = amount_str.replace(',', ".").parse().map_err(|_| invalid(line, "statement line without a valid amount"))?;
+                let bank_ref = rest.split_once("//").map(|(_, bank_ref)| bank_ref.trim().to_string()).filter(|bank_ref| !bank_ref.is_empty());
+                if credit {
+                    pending = Some((bank_ref, date, amount));
+                }
+            },
+            "86" => {
+                if let Some((id, date, amount)) = pending.take() {
+                    let (payer, reference) = parse_mt940_info(&value);
+                    transactions.push(Transaction::new(id, date, amount, currency.clone(), payer, reference));
+                }
+            },
+            _ => {},
+        }
+    }
+    if let Some((id, date, amount)) = pending {
+        transactions.push(Transaction::new(id, date, amount, currency, String::new(), String::new()));
+    }
+    Ok(transactions)
+}
+
+/// The credits of a CAMT.053 (xml) or MT940 statement file
+pub fn read_statement(file: &Path) -> PaymentResult<Vec<Transaction>> {
+    let content = fs::read_to_string(file).map_err(|source| PaymentError::Io { file: file.to_path_buf(), source })?;
+    if content.trim_start().starts_with('<') {
+        parse_camt(file, &content)
+    } else {
+        parse_mt940(file, &content)
+    }
+}
+
+fn normalize(text: &str) -> String {
+    text.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+// The bill number as a whole in the reference, so 2024-1201 is not found in 2024-12010
+fn mentions(reference: &str, billnr: &str) -> bool {
+    let reference: String = reference.chars().filter(|c| !c.is_whitespace()).collect();
+    let billnr: String = billnr.chars().filter(|c| !c.is_whitespace()).collect();
+    if billnr.is_empty() {
+        return false
+    }
+    reference.match_indices(&billnr).any(|(start, _)| {
+        let before = reference[..start].chars().next_back();
+        let after = reference[start + billnr.len()..].chars().next();
+        !before.is_some_and(|c| c.is_ascii_digit()) && !after.is_some_and(|c| c.is_ascii_digit())
+    })
+}
+
+fn payer_matches(payer: &str, company: &str, config: &Config) -> bool {
+    let payer = normalize(payer);
+    let names = [Some(company), config.companies.get(company).map(|company_config| company_config.address.name.as_str())];
+    !payer.is_empty() && names.into_iter().flatten().map(normalize).any(|name| !name.is_empty() && (payer.contains(&name) || name.contains(&payer)))
+}
+
+/// An unpaid bill and what is still open of it
+struct OpenBill {
+    bill: DBEntry,
+    open: f64,
+}
+
+impl OpenBill {
+    /// What settles the bill when paid on `date`: the rest of the Skonto total within the Skonto days, otherwise what is open
+    fn settled_by(&self, date: &NaiveDate) -> f64 {
+        match self.bill.skonto() {
+            Some((total, last_day)) if *date <= last_day => (total - (self.bill.amount - self.open)).max(0.0),
+            _ => self.open,
+        }
+    }
+}
+
+/// The bills a credit pays: those whose number is in the reference, otherwise the only one of the payer open over exactly
+/// the amount (or its Skonto total). Each with the part of the amount it gets
+fn allocate(transaction: &Transaction, open_bills: &[OpenBill], config: &Config) -> Vec<(usize, f64)> {
+    let mentioned: Vec<usize> = (0..open_bills.len()).filter(|&index| mentions(&transaction.reference, &open_bills[index].bill.billnr)).collect();
+    let candidates = match mentioned.as_slice() {
+        [] => {
+            let by_amount: Vec<usize> = (0..open_bills.len())
+                .filter(|&index| {
+                    let open_bill = &open_bills[index];
+                    open_bill.bill.currency == transaction.currency
+                        && [open_bill.open, open_bill.settled_by(&transaction.date)].iter().any(|open| (open - transaction.amount).abs() < TOLERANCE)
+                        && payer_matches(&transaction.payer, &open_bill.bill.company, config)
+                })
+                .collect();
+            if by_amount.len() != 1 {
+                return Vec::new()
+            }
+            by_amount
+        },
+        _ => mentioned,
+    };
+
+    // A payment of several bills settles them in order, anything left over goes to the last one
+    let mut remaining = transaction.amount;
+    let mut shares = Vec::new();
+    for (position, &index) in candidates.iter().enumerate() {
+        let share = if position + 1 == candidates.len() { remaining } else { remaining.min(open_bills[index].settled_by(&transaction.date)) };
+        if share < TOLERANCE {
+            break
+        }
+        remaining -= share;
+        shares.push((index, share));
+    }
+    shares
+}
+
+// Marks the bill paid once the payments cover it, or cover the Skonto total within the Skonto days. Returns what is still open
+fn settle(db: &Db, bill: &DBEntry) -> PaymentResult<f64> {
+    let payments = db.get_payments(&bill.billnr)?;
+    let paid_by = |last_day: Option<NaiveDate>| -> f64 {
+        payments
+            .iter()
+            .filter(|payment| last_day.is_none_or(|last_day| payment.date().is_some_and(|date| date <= last_day)))
+            .map(|payment| payment.amount)
+            .sum()
+    };
+    let skonto_paid = bill.skonto().is_some_and(|(total, last_day)| paid_by(Some(last_day)) > total - TOLERANCE);
+    let open = if skonto_paid { 0.0 } else { bill.amount - paid_by(None) };
+    if open < TOLERANCE {
+        db.set_status(&bill.billnr, "paid")?;
+    }
+    Ok(open.max(0.0))
+}
+
+fn describe(transaction_date: &str, amount: f64, currency: &str, payer: &str) -> String {
+    format!("{}  {:>10.2} {}  {:<30}", transaction_date, amount, currency, payer)
+}
+
+/// Imports the credits of a statement file: matches them to unpaid bills by bill number, or by amount and payer, and marks
+/// fully paid bills as paid. Credits that match no bill are kept for `assign`. Credits imported before are skipped
+pub fn import(config: &Config, file: &Path) -> PaymentResult<()> {
+    let db = Db::open(&config.paths.db)?;
+    let transactions = read_statement(file)?;
+    println!("Found {} credits in {:?}", transactions.len(), file);
+
+    let mut open_bills = Vec::new();
+    for bill in db.get_unpaid_bills()? {
+        let open = bill.amount - db.get_paid_amount(&bill.billnr)?;
+        open_bills.push(OpenBill { bill, open });
+    }
+
+    let (mut matched, mut unmatched, mut skipped) = (0, 0, 0);
+    for transaction in &transactions {
+        let line = describe(&transaction.date.to_string(), transaction.amount, &transaction.currency, &transaction.payer);
+        if db.has_payment(&transaction.id)? {
+            skipped += 1;
+            continue
+        }
+
+        let payment = |billnr: &str, amount: f64| Payment {
+            id: 0,
+            transaction_id: transaction.id.clone(),
+            date: transaction.date.to_string(),
+            amount,
+            currency: transaction.currency.clone(),
+            payer: transaction.payer.clone(),
+            reference: transaction.reference.clone(),
+            billnr: billnr.to_string(),
+        };
+
+        let shares = allocate(transaction, &open_bills, config);
+        if shares.is_empty() {
+            db.add_payment(&payment("", transaction.amount))?;
+            println!("{}  unmatched: {}", line, transaction.reference);
+            unmatched += 1;
+            continue
+        }
+        for (index, amount) in shares {
+            let open_bill = &mut open_bills[index];
+            db.add_payment(&payment(&open_bill.bill.billnr, amount))?;
+            open_bill.open = settle(&db, &open_bill.bill)?;
+            match open_bill.open {
+                open if open < TOLERANCE => println!("{}  {} paid", line, open_bill.bill.billnr),
+                open => println!("{}  {} partially paid, {:.2} {} open", line, open_bill.bill.billnr, open, open_bill.bill.currency),
+            }
+        }
+        matched += 1;
+    }
+
+    println!("\n{} matched, {} unmatched, {} imported before", matched, unmatched, skipped);
+    if unmatched > 0 {
+        println!("See them with `rustbill payments list` and assign them with `rustbill payments assign <id> <billnr>`");
+    }
+    Ok(())
+}
+
+/// Lists the payments not assigned to a bill
+pub fn list(config: &Config) -> PaymentResult<()> {
+    let db = Db::open(&config.paths.db)?;
+    let payments = db.get_unassigned_payments()?;
+    if payments.is_empty() {
+        println!("All payments are assigned");
+        return Ok(())
+    }
+    println!("{:>5}  {:<10}  {:>14}  {:<30}  Reference", "Id", "Date", "Amount", "Payer");
+    for payment in payments {
+        println!("{:>5}  {}  {}", payment.id, describe(&payment.date, payment.amount, &payment.currency, &payment.payer), payment.reference);
+    }
+    Ok(())
+}
+
+/// Assigns an unmatched payment to a bill, which is marked paid once its payments cover it
+pub fn assign(config: &Config, id: i32, billnr: &str) -> PaymentResult<()> {
+    let db = Db::open(&config.paths.db)?;
+    let payment = db.get_payment(id)?.ok_or(PaymentError::UnknownPayment(id))?;
+    if !payment.billnr.is_empty() {
+        return Err(PaymentError::AlreadyAssigned { id, billnr: payment.billnr })
+    }
+    let bill = db.get_bill(billnr)?.ok_or_else(|| PaymentError::UnknownBill(billnr.to_string()))?;
+
+    db.assign_payment(id, billnr)?;
+    match settle(&db, &bill)? {
+        open if open < TOLERANCE => println!("Bill {} is paid", billnr),
+        open => println!("Bill {} is partially paid, {:.2} {} open", billnr, open, bill.currency),
+    }
+    Ok(())
+}
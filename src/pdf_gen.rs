@@ -5,36 +5,143 @@ use std::io::Write;
 use std::fs::File;
 use derive_typst_intoval::{IntoDict, IntoValue};
 use typst_as_lib::TypstTemplate;
-use typst::foundations::{Bytes, Dict, IntoValue};
+use typst::foundations::{Array, Bytes, Dict, IntoValue, Value};
 use typst::foundations::Smart;
 use typst::text::Font;
 use typst_pdf::{self, PdfOptions, PdfStandard, PdfStandards};
 use thiserror::Error;
+use crate::budget::{BudgetStatus, Unit};
+use crate::calculate::{ActivityLine, Amounts, AppliedAdjustment};
+use crate::config_reader::{CompanyConfig, Config, ProjectBilling, ProjectConfig};
+use crate::csv_reader::Record;
+use crate::date_utils;
 
 static TEMPLATE_FILE: &str = include_str!("../templates/invoice.typ");
+static DUNNING_TEMPLATE_FILE: &str = include_str!("../templates/dunning.typ");
+static REPORT_TEMPLATE_FILE: &str = include_str!("../templates/report.typ");
 static FONT: &[u8] = include_bytes!("../templates/Akrobat-Regular.otf");
 static FONTBOLD: &[u8] = include_bytes!("../templates/Akrobat-Bold.otf");
 static FONTLIGHT: &[u8] = include_bytes!("../templates/Akrobat-Light.otf");
 
 #[derive(Debug, Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum PdfError {
     #[error("PDF compile error")]
     PdfCompileError(#[from] typst_as_lib::TypstAsLibError),
     #[error("PDF write error")]
     PdfWriteError(#[from] std::io::Error),
+    #[error("Config conversion error")]
+    ConfigError(#[from] serde_yaml::Error),
 }
 
+/// A timesheet entry as shown in the hour overview
+#[derive(Debug, Clone, IntoValue)]
+pub struct Entry {
+    pub date: String,
+    /// Day within the billing period, starting at 1
+    pub period_day: i64,
+    pub minutes: i64,
+    pub description: String,
+    pub amount: f64,
+    /// The amount formatted in the bill currency
+    pub amount_str: String,
+}
+
+/// A position of the bill: the hours of one activity
+#[derive(Debug, Clone, IntoValue)]
+pub struct Line {
+    pub name: String,
+    pub hours: f64,
+    pub hourly_fee_str: String,
+    /// Before the discounts and surcharges of the line
+    pub amount_str: String,
+    pub adjustments: Vec<Item>,
+}
+
+/// The lines of a project on a bill with sections
+#[derive(Debug, Clone, IntoValue)]
+pub struct Section {
+    pub name: String,
+    /// Purchase order and cost center, empty if neither is configured
+    pub reference: String,
+    pub lines: Vec<Line>,
+    pub subtotal_str: String,
+}
+
+/// A fixed amount, e.g. a retainer or a purchased contingent
+#[derive(Debug, Clone, IntoValue)]
+pub struct Item {
+    pub description: String,
+    pub amount_str: String,
+}
+
+/// A budget of the company after this bill
+#[derive(Debug, Clone, IntoValue)]
+pub struct BudgetLine {
+    pub name: String,
+    pub used: String,
+    pub limit: String,
+    pub remaining: String,
+}
+
+/// Everything the invoice template renders. The template does no calculations of its own
 #[derive(Debug, Clone, IntoValue, IntoDict)]
 pub struct Content {
     pub company: String,
     pub billnr: String,
-    pub vat: f64,
     pub date: String,
     pub due: String,
+    /// E.g. "innerhalb von 10 Werktagen"
+    pub payment_terms: String,
+    /// Leistungszeitraum, e.g. "02.12.2024 – 13.12.2024"
+    pub service_period: String,
     pub qrcode: String,
-    pub hourly_fee: f64,
-    pub data_dir: String,
-    pub config_name: String,
+    pub config: Value,
+    pub entries: Vec<Entry>,
+    /// For bills of a single project, empty otherwise
+    pub project: String,
+    pub purchase_order: String,
+    pub cost_center: String,
+    /// The lines outside of sections
+    pub lines: Vec<Line>,
+    pub sections: Vec<Section>,
+    pub items: Vec<Item>,
+    /// Discounts and surcharges of the whole bill
+    pub adjustments: Vec<Item>,
+    pub budgets: Vec<BudgetLine>,
+    pub days_in_period: i64,
+    pub minutes_total: i64,
+    pub hours_total: f64,
+    pub amount_net: f64,
+    pub vat: f64,
+    pub amount_total: f64,
+    /// ISO code of the bill currency
+    pub currency: String,
+    /// The amounts formatted in the bill currency
+    pub hourly_fee_str: String,
+    pub amount_net_str: String,
+    /// The entries less the day cap, the sum of the hour overview
+    pub amount_hours_str: String,
+    pub vat_str: String,
+    pub amount_total_str: String,
+    /// Empty if there is no weekend work or day cap
+    pub surcharge_str: String,
+    /// In percent
+    pub weekend_surcharge: f64,
+    pub cap_deduction_str: String,
+    /// Hours drawn from prepaid contingents and their value, empty if none
+    pub contingent_hours: f64,
+    pub contingent_str: String,
+    /// Hours left in the contingents, empty if the company has none
+    pub contingent_remaining: String,
+    /// Early payment discount, empty if the company has none
+    pub skonto_percent: String,
+    pub skonto_date: String,
+    pub skonto_total_str: String,
+    /// Only for bills not in euros: the VAT in euros and the ECB rate it was converted with. Empty otherwise
+    pub vat_eur_str: String,
+    pub exchange_rate: String,
+    pub exchange_rate_date: String,
 }
 
 impl From<Content> for Dict {
@@ -43,7 +150,204 @@ impl From<Content> for Dict {
     }
 }
 
-pub fn generate_pdf(data: Content) -> Result<Vec<u8>, PdfError> {
+/// Everything the dunning template renders for a letter about one bill
+#[derive(Debug, Clone, IntoValue, IntoDict)]
+pub struct DunningContent {
+    pub company: String,
+    /// Name of the dunning level, the subject of the letter
+    pub level: String,
+    pub text: String,
+    pub billnr: String,
+    pub bill_date: String,
+    pub bill_due: String,
+    pub date: String,
+    /// Date the open amount has to be paid by
+    pub due: String,
+    pub qrcode: String,
+    pub config: Value,
+    /// The bill, payments received, fees and interest
+    pub items: Vec<Item>,
+    pub total_str: String,
+}
+
+impl From<DunningContent> for Dict {
+    fn from(value: DunningContent) -> Self {
+        value.into_dict()
+    }
+}
+
+/// A line of the revenue report, with the amounts formatted in euros
+#[derive(Debug, Clone, IntoValue)]
+pub struct ReportRow {
+    pub name: String,
+    pub net: String,
+    pub vat: String,
+    pub gross: String,
+    pub paid_net: String,
+    pub paid_vat: String,
+    pub paid: String,
+}
+
+/// Everything the report template renders
+#[derive(Debug, Clone, IntoValue, IntoDict)]
+pub struct ReportContent {
+    pub title: String,
+    /// First and last day
+    pub period: String,
+    pub bills: i64,
+    pub config: Value,
+    pub rates: Vec<ReportRow>,
+    pub months: Vec<ReportRow>,
+    pub companies: Vec<ReportRow>,
+    /// A single row
+    pub total: Vec<ReportRow>,
+}
+
+impl From<ReportContent> for Dict {
+    fn from(value: ReportContent) -> Self {
+        value.into_dict()
+    }
+}
+
+fn yaml_to_value(yaml: &serde_yaml::Value) -> Value {
+    match yaml {
+        serde_yaml::Value::Null => Value::None,
+        serde_yaml::Value::Bool(b) => b.into_value(),
+        serde_yaml::Value::Number(n) => match n.as_i64() {
+            Some(i) => i.into_value(),
+            None => n.as_f64().unwrap_or_default().into_value(),
+        },
+        serde_yaml::Value::String(s) => s.as_str().into_value(),
+        serde_yaml::Value::Sequence(seq) => Value::Array(seq.iter().map(yaml_to_value).collect::<Array>()),
+        serde_yaml::Value::Mapping(map) => Value::Dict(
+            map.iter()
+                .filter_map(|(key, value)| key.as_str().map(|key| (key.into(), yaml_to_value(value))))
+                .collect::<Dict>()
+        ),
+        serde_yaml::Value::Tagged(tagged) => yaml_to_value(&tagged.value),
+    }
+}
+
+/// The merged config as a typst dict, so the template sees the same values as the rest of rustbill
+pub fn config_to_value(config: &Config) -> Result<Value, PdfError> {
+    let yaml = serde_yaml::to_value(config)?;
+    Ok(yaml_to_value(&yaml))
+}
+
+/// The entries with their billed minutes and amounts
+pub fn build_entries(records: &[Record], amounts: &Amounts, period: &(NaiveDate, NaiveDate), format_money: impl Fn(f64) -> String) -> Vec<Entry> {
+    records
+        .iter()
+        .zip(&amounts.entries)
+        .map(|(record, billed)| Entry {
+            date: record.date.format("%d.%m.%Y").to_string(),
+            period_day: (record.date - period.0).num_days() + 1,
+            minutes: billed.minutes.into(),
+            description: record.description.clone(),
+            amount: billed.amount,
+            amount_str: format_money(billed.amount),
+        })
+        .collect()
+}
+
+pub fn build_budgets(budgets: &[BudgetStatus], format_money: impl Fn(f64) -> String) -> Vec<BudgetLine> {
+    let format = |status: &BudgetStatus, value: f64| match status.unit {
+        Unit::Hours => format!("{} Stunden", (value * 100.0).round() / 100.0),
+        Unit::Amount => format_money(value),
+    };
+    budgets
+        .iter()
+        .map(|status| BudgetLine {
+            name: status.name.clone(),
+            used: format(status, status.used()),
+            limit: format(status, status.limit),
+            remaining: format(status, status.remaining()),
+        })
+        .collect()
+}
+
+pub fn build_items(amounts: &Amounts, format_money: impl Fn(f64) -> String) -> Vec<Item> {
+    amounts.items
+        .iter()
+        .map(|item| Item { description: item.description.clone(), amount_str: format_money(item.amount) })
+        .collect()
+}
+
+fn build_adjustment(adjustment: &AppliedAdjustment, format_money: &impl Fn(f64) -> String) -> Item {
+    Item {
+        description: match adjustment.percent {
+            Some(percent) => format!("{} ({} %)", adjustment.reason, percent),
+            None => adjustment.reason.clone(),
+        },
+        amount_str: format_money(adjustment.signed()),
+    }
+}
+
+pub fn build_adjustments(amounts: &Amounts, format_money: impl Fn(f64) -> String) -> Vec<Item> {
+    amounts.adjustments.iter().map(|adjustment| build_adjustment(adjustment, &format_money)).collect()
+}
+
+fn build_line(line: &ActivityLine, bill_item: &str, format_money: &impl Fn(f64) -> String) -> Line {
+    Line {
+        name: if line.activity.is_empty() { bill_item.to_string() } else { line.activity.clone() },
+        hours: line.hours,
+        hourly_fee_str: format_money(line.hourly_fee),
+        amount_str: format_money(line.base),
+        adjustments: line.adjustments.iter().map(|adjustment| build_adjustment(adjustment, format_money)).collect(),
+    }
+}
+
+/// One line per activity, entries without activity are named `bill_item`. With sections only the lines without project
+pub fn build_lines(amounts: &Amounts, company_config: &CompanyConfig, bill_item: &str, format_money: impl Fn(f64) -> String) -> Vec<Line> {
+    amounts.lines
+        .iter()
+        .filter(|line| company_config.project_billing != ProjectBilling::Sections || line.project.is_empty())
+        .map(|line| build_line(line, bill_item, &format_money))
+        .collect()
+}
+
+/// "Bestellnummer 4711, Kostenstelle 100" as far as configured
+pub fn project_reference(project: &ProjectConfig) -> String {
+    let purchase_order = project.purchase_order.as_ref().map(|purchase_order| format!("Bestellnummer {}", purchase_order));
+    let cost_center = project.cost_center.as_ref().map(|cost_center| format!("Kostenstelle {}", cost_center));
+    purchase_order.into_iter().chain(cost_center).collect::<Vec<_>>().join(", ")
+}
+
+/// A section per project with its lines and subtotal, if the company bills its projects in sections
+pub fn build_sections(amounts: &Amounts, company_config: &CompanyConfig, bill_item: &str, format_money: impl Fn(f64) -> String) -> Vec<Section> {
+    if company_config.project_billing != ProjectBilling::Sections {
+        return Vec::new()
+    }
+    let mut projects: Vec<&str> = amounts.lines.iter().map(|line| line.project.as_str()).filter(|project| !project.is_empty()).collect();
+    projects.dedup();
+    projects
+        .into_iter()
+        .map(|project| Section {
+            name: company_config.project_name(project).to_string(),
+            reference: company_config.projects.get(project).map(project_reference).unwrap_or_default(),
+            lines: amounts.lines
+                .iter()
+                .filter(|line| line.project == project)
+                .map(|line| build_line(line, bill_item, &format_money))
+                .collect(),
+            subtotal_str: format_money(amounts.section(project).1),
+        })
+        .collect()
+}
+
+pub fn generate_pdf(data: Content, root: &Path) -> Result<Vec<u8>, PdfError> {
+    compile(TEMPLATE_FILE, data, root)
+}
+
+pub fn generate_dunning_pdf(data: DunningContent, root: &Path) -> Result<Vec<u8>, PdfError> {
+    compile(DUNNING_TEMPLATE_FILE, data, root)
+}
+
+pub fn generate_report_pdf(data: ReportContent, root: &Path) -> Result<Vec<u8>, PdfError> {
+    compile(REPORT_TEMPLATE_FILE, data, root)
+}
+
+fn compile(source: &'static str, data: impl Into<Dict>, root: &Path) -> Result<Vec<u8>, PdfError> {
     let font = Font::new(Bytes::from(FONT), 0)
         .expect("Could not parse akrobat regular font!");
 
@@ -53,7 +357,7 @@ pub fn generate_pdf(data: Content) -> Result<Vec<u8>, PdfError> {
     let fontlight = Font::new(Bytes::from(FONTLIGHT), 0)
         .expect("Could not parse akrobat light font!");
 
-    let template = TypstTemplate::new(vec![font, fontbold, fontlight], TEMPLATE_FILE).with_file_system_resolver(".");
+    let template = TypstTemplate::new(vec![font, fontbold, fontlight], source).with_file_system_resolver(root);
 
     let doc = template
         .compile_with_input(data)
@@ -68,16 +372,47 @@ pub fn generate_pdf(data: Content) -> Result<Vec<u8>, PdfError> {
     Ok(pdf)
 }
 
-pub fn save_pdf(data: &Vec<u8>, pdf_dir: &Path, billdate: NaiveDate, company: &str) -> Result<String, PdfError> {
-    let pdf_filename = format!(
-        "{date}_Rechnung_{company}_{month_pretty}_{year}.pdf",
+/// A single day for work on one day, otherwise the first and last day
+pub fn format_period(period: &(NaiveDate, NaiveDate)) -> String {
+    match period {
+        (start, end) if start == end => start.format("%d.%m.%Y").to_string(),
+        (start, end) => format!("{} – {}", start.format("%d.%m.%Y"), end.format("%d.%m.%Y")),
+    }
+}
+
+/// `project` is the key of the project for bills of a single project. Bills of a calendar month are named after the month,
+/// others after their first and last day
+pub fn pdf_filename(billdate: NaiveDate, company: &str, project: Option<&str>, period: &(NaiveDate, NaiveDate)) -> String {
+    let period_str = if date_utils::is_month(period) {
+        format!("{}_{}", billdate.format_localized("%B", Locale::de_DE), billdate.format("%Y"))
+    } else {
+        format!("{}_bis_{}", period.0.format("%Y-%m-%d"), period.1.format("%Y-%m-%d"))
+    };
+    format!(
+        "{date}_Rechnung_{company}{project}_{period_str}.pdf",
         date = billdate.format("%Y_%m_%d"),
         company = company,
-        month_pretty = billdate.format_localized("%B", Locale::de_DE),
-        year = billdate.format("%Y"),
-    );
+        project = project.map(|project| format!("_{}", project)).unwrap_or_default(),
+    )
+}
 
-    let outpath = pdf_dir.join(&pdf_filename);
+/// `level` counts from 1 for the first dunning level
+pub fn dunning_filename(date: NaiveDate, company: &str, billnr: &str, level: usize) -> String {
+    format!("{}_Mahnung{}_{}_{}.pdf", date.format("%Y_%m_%d"), level, company, billnr)
+}
+
+pub fn interest_filename(date: NaiveDate, company: &str, billnr: &str) -> String {
+    format!("{}_Zinsrechnung_{}_{}.pdf", date.format("%Y_%m_%d"), company, billnr)
+}
+
+pub fn save_pdf(data: &[u8], pdf_dir: &Path, billdate: NaiveDate, company: &str, project: Option<&str>, period: &(NaiveDate, NaiveDate)) -> Result<String, PdfError> {
+    let pdf_filename = pdf_filename(billdate, company, project, period);
+    write_pdf(data, pdf_dir, &pdf_filename)?;
+    Ok(pdf_filename)
+}
+
+pub fn write_pdf(data: &[u8], pdf_dir: &Path, pdf_filename: &str) -> Result<(), PdfError> {
+    let outpath = pdf_dir.join(pdf_filename);
 
     println!("Saving pdf to {:?}", &outpath);
 
@@ -86,5 +421,5 @@ pub fn save_pdf(data: &Vec<u8>, pdf_dir: &Path, billdate: NaiveDate, company: &s
     let mut pdf_file = File::create(&outpath)?;
     let _ = pdf_file.write_all(data);
 
-    Ok(pdf_filename)
+    Ok(())
 }
\ No newline at end of file
@@ -14,7 +14,7 @@ type QrResult<T> = Result<T, QRGenErrors>;
 
 pub fn create_qrcode(bank_config: &BankConfig, amount_total: &str, billdate: &NaiveDate, billnr: &str, color: &str) -> QrResult<String> {
     
-    let amount_formatted = String::from("EUR") + &amount_total;
+    let amount_formatted = String::from("EUR") + amount_total;
 
     let billdate_formatted = billdate.format("%d.%m.%Y").to_string();
 
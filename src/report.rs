@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::{Datelike, Locale, NaiveDate};
+use thiserror::Error;
+use crate::config_reader::Config;
+use crate::db::{DBEntry, DBError, Db};
+use crate::pdf_gen::{self, PdfError, ReportContent, ReportRow};
+use crate::{calculate, date_utils};
+
+#[derive(Debug, Error)]
+pub enum ReportError {
+    #[error("Invalid month {0}")]
+    InvalidMonth(u32),
+    #[error("Invalid quarter {0}")]
+    InvalidQuarter(u32),
+    #[error("Could not write {file:?}")]
+    Io { file: PathBuf, source: std::io::Error },
+    #[error("Could not write the csv")]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Date(#[from] date_utils::DateError),
+    #[error(transparent)]
+    Currency(#[from] calculate::AmountCalcs),
+    #[error(transparent)]
+    DB(#[from] DBError),
+    #[error(transparent)]
+    Pdf(#[from] PdfError),
+}
+
+type ReportResult<T> = Result<T, ReportError>;
+
+/// The months a report covers
+#[derive(Debug, Clone, Copy)]
+pub enum Period {
+    Month(u32),
+    Quarter(u32),
+    Year,
+}
+
+impl Period {
+    fn months(&self) -> ReportResult<(u32, u32)> {
+        match *self {
+            Period::Month(month) if (1..=12).contains(&month) => Ok((month, month)),
+            Period::Month(month) => Err(ReportError::InvalidMonth(month)),
+            Period::Quarter(quarter) if (1..=4).contains(&quarter) => Ok((quarter * 3 - 2, quarter * 3)),
+            Period::Quarter(quarter) => Err(ReportError::InvalidQuarter(quarter)),
+            Period::Year => Ok((1, 12)),
+        }
+    }
+
+    fn name(&self, year: i32) -> String {
+        match self {
+            Period::Month(month) => format!("{}_{:02}", year, month),
+            Period::Quarter(quarter) => format!("{}_Q{}", year, quarter),
+            Period::Year => year.to_string(),
+        }
+    }
+
+    fn title(&self, year: i32) -> String {
+        match self {
+            Period::Month(month) => format!("{:02}/{}", month, year),
+            Period::Quarter(quarter) => format!("Q{} {}", quarter, year),
+            Period::Year => year.to_string(),
+        }
+    }
+}
+
+/// Amounts in euros. Invoiced counts the bills dated in the period (Soll-Versteuerung),
+/// paid the payments received in it (Ist-Versteuerung), split by the VAT rate of their bill
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Totals {
+    pub net: f64,
+    pub vat: f64,
+    pub paid_net: f64,
+    pub paid_vat: f64,
+}
+
+impl Totals {
+    pub fn gross(&self) -> f64 {
+        self.net + self.vat
+    }
+
+    pub fn paid(&self) -> f64 {
+        self.paid_net + self.paid_vat
+    }
+
+    fn add_bill(&mut self, net: f64, vat: f64) {
+        self.net += net;
+        self.vat += vat;
+    }
+
+    fn add_payment(&mut self, net: f64, vat: f64) {
+        self.paid_net += net;
+        self.paid_vat += vat;
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub year: i32,
+    pub period: Period,
+    pub first_day: NaiveDate,
+    pub last_day: NaiveDate,
+    /// Number of bills dated in the period, without cancelled ones
+    pub bills: usize,
+    /// By VAT rate in percent
+    pub rates: BTreeMap<u32, Totals>,
+    /// By company key
+    pub companies: BTreeMap<String, Totals>,
+    /// By month of the period
+    pub months: BTreeMap<u32, Totals>,
+    pub total: Totals,
+}
+
+impl Report {
+    /// All rows of the report with the section they belong to
+    fn rows(&self) -> Vec<(&'static str, String, Totals)> {
+        let rates = self.rates.iter().map(|(rate, totals)| ("rate", format!("{} %", rate), *totals));
+        let months = self.months.iter().map(|(month, totals)| ("month", format!("{}-{:02}", self.year, month), *totals));
+        let companies = self.companies.iter().map(|(company, totals)| ("company", company.clone(), *totals));
+        rates.chain(months).chain(companies).chain([("total", String::from("Total"), self.total)]).collect()
+    }
+}
+
+// The net and VAT in euros of an amount of the bill, in the proportion of its stored net to its total.
+// The bill's own amount gives exactly its stored net, so rounding matches the bill
+fn split(bill: &DBEntry, gross: f64) -> (f64, f64) {
+    let rate = if bill.exchange_rate > 0.0 { bill.exchange_rate } else { 1.0 };
+    let net = if bill.amount == 0.0 { gross } else { gross * bill.net_amount() / bill.amount };
+    (net / rate, (gross - net) / rate)
+}
+
+/// Collects the bills and payments of the period from the database
+pub fn build(config: &Config, year: i32, period: Period) -> ReportResult<Report> {
+    let (first_month, last_month) = period.months()?;
+    let first_day = NaiveDate::from_ymd_opt(year, first_month, 1).ok_or(ReportError::InvalidMonth(first_month))?;
+    let last_day = date_utils::month_bounds(&NaiveDate::from_ymd_opt(year, last_month, 1).ok_or(ReportError::InvalidMonth(last_month))?)?.1;
+
+    let db = Db::open(&config.paths.db)?;
+
+    let mut report = Report {
+        year,
+        period,
+        first_day,
+        last_day,
+        bills: 0,
+        rates: BTreeMap::new(),
+        companies: BTreeMap::new(),
+        months: (first_month..=last_month).map(|month| (month, Totals::default())).collect(),
+        total: Totals::default(),
+    };
+
+    for bill in db.get_bills_in_months(year, first_month, last_month)?.iter().filter(|bill| bill.status != "cancelled") {
+        let (net, vat) = split(bill, bill.amount);
+        report.bills += 1;
+        report.rates.entry(bill.vat_percent()).or_default().add_bill(net, vat);
+        report.companies.entry(bill.company.clone()).or_default().add_bill(net, vat);
+        report.months.entry(bill.month).or_default().add_bill(net, vat);
+        report.total.add_bill(net, vat);
+    }
+
+    let mut bills: BTreeMap<String, Option<DBEntry>> = BTreeMap::new();
+    for payment in db.get_payments_between(&first_day, &last_day)? {
+        if !bills.contains_key(&payment.billnr) {
+            bills.insert(payment.billnr.clone(), db.get_bill(&payment.billnr)?);
+        }
+        let (Some(Some(bill)), Some(date)) = (bills.get(&payment.billnr), payment.date()) else { continue };
+        let (net, vat) = split(bill, payment.amount);
+        report.rates.entry(bill.vat_percent()).or_default().add_payment(net, vat);
+        report.companies.entry(bill.company.clone()).or_default().add_payment(net, vat);
+        report.months.entry(date.month()).or_default().add_payment(net, vat);
+        report.total.add_payment(net, vat);
+    }
+    Ok(report)
+}
+
+fn print_section(title: &str, rows: &[(&'static str, String, Totals)], section: &str) {
+    println!("\n{:<24} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12}", title, "Net", "VAT", "Gross", "Paid net", "Paid VAT", "Paid");
+    for (_, name, totals) in rows.iter().filter(|(row_section, _, _)| *row_section == section) {
+        println!(
+            "{:<24} {:>12.2} {:>12.2} {:>12.2} {:>12.2} {:>12.2} {:>12.2}",
+            name, totals.net, totals.vat, totals.gross(), totals.paid_net, totals.paid_vat, totals.paid(),
+        );
+    }
+}
+
+/// Prints the report as tables, in euros
+pub fn print(report: &Report) {
+    let rows = report.rows();
+    println!("Revenue {} ({} to {}), {} bills, amounts in EUR", report.period.title(report.year), report.first_day, report.last_day, report.bills);
+    print_section("VAT rate", &rows, "rate");
+    print_section("Month", &rows, "month");
+    print_section("Company", &rows, "company");
+    print_section("", &rows, "total");
+}
+
+fn default_file(config: &Config, report: &Report, extension: &str) -> PathBuf {
+    config.paths.bills.join(format!("Umsatzbericht_{}.{}", report.period.name(report.year), extension))
+}
+
+fn write(file: &Path, content: &[u8]) -> ReportResult<()> {
+    if let Some(dir) = file.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|source| ReportError::Io { file: file.to_path_buf(), source })?;
+    }
+    fs::write(file, content).map_err(|source| ReportError::Io { file: file.to_path_buf(), source })?;
+    println!("Saved the report to {:?}", file);
+    Ok(())
+}
+
+/// Writes the report as csv to `output` or `Umsatzbericht_<period>.csv` in the bills directory
+pub fn write_csv(config: &Config, report: &Report, output: Option<&Path>) -> ReportResult<()> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["section", "name", "net", "vat", "gross", "paid_net", "paid_vat", "paid"])?;
+    for (section, name, totals) in report.rows() {
+        let amounts = [totals.net, totals.vat, totals.gross(), totals.paid_net, totals.paid_vat, totals.paid()].map(|amount| format!("{:.2}", amount));
+        writer.write_record([section, &name].into_iter().chain(amounts.iter().map(String::as_str)))?;
+    }
+    let content = writer.into_inner().map_err(|e| ReportError::Csv(e.into_error().into()))?;
+    write(&output.map(Path::to_path_buf).unwrap_or_else(|| default_file(config, report, "csv")), &content)
+}
+
+/// Renders the report with `templates/report.typ` to `output` or `Umsatzbericht_<period>.pdf` in the bills directory
+pub fn write_pdf(config: &Config, report: &Report, output: Option<&Path>) -> ReportResult<()> {
+    let euro = calculate::find_currency("EUR")?;
+    let locale = calculate::money_locale(euro, config.bill_config.number_format.as_deref())?;
+    let money = |amount: f64| calculate::format_money(amount, euro, locale);
+    let rows = report.rows();
+    let section = |section: &str| -> Vec<ReportRow> {
+        rows.iter()
+            .filter(|(row_section, _, _)| *row_section == section)
+            .map(|(_, name, totals)| ReportRow {
+                name: name.clone(),
+                net: money(totals.net),
+                vat: money(totals.vat),
+                gross: money(totals.gross()),
+                paid_net: money(totals.paid_net),
+                paid_vat: money(totals.paid_vat),
+                paid: money(totals.paid()),
+            })
+            .collect()
+    };
+    let months = |month: u32| NaiveDate::from_ymd_opt(report.year, month, 1).map(|date| date.format_localized("%B", Locale::de_DE).to_string()).unwrap_or_default();
+
+    let content = ReportContent {
+        title: format!("Umsatzbericht {}", report.period.title(report.year)),
+        period: format!("{} – {}", report.first_day.format("%d.%m.%Y"), report.last_day.format("%d.%m.%Y")),
+        bills: report.bills as i64,
+        config: pdf_gen::config_to_value(config)?,
+        rates: section("rate"),
+        months: section("month").into_iter().zip(report.months.keys()).map(|(row, month)| ReportRow { name: months(*month), ..row }).collect(),
+        companies: section("company"),
+        total: section("total"),
+    };
+    let pdf = pdf_gen::generate_report_pdf(content, &config.paths.root)?;
+    write(&output.map(Path::to_path_buf).unwrap_or_else(|| default_file(config, report, "pdf")), &pdf)
+}
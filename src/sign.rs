@@ -71,7 +71,7 @@ pub fn sign_pdf(pdf_data: Vec<u8>) -> Result<Vec<u8>, SignError> {
 }
 
 #[allow(dead_code)]
-fn add_form_field_to_pdf(input_bytes: &Vec<u8>) -> Result<Vec<u8>, FormError> {
+fn add_form_field_to_pdf(input_bytes: &[u8]) -> Result<Vec<u8>, FormError> {
     // Load the PDF document
     let mut doc = Document::load_mem(input_bytes)?;
 
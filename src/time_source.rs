@@ -0,0 +1,390 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+use rusqlite::{params, Connection};
+use serde_json::Value;
+use thiserror::Error;
+use crate::config_reader::{Config, CsvConfig, JsonFormat, SourceConfig};
+use crate::csv_reader::{self, CsvError, Record};
+use crate::date_utils::{self, DateError};
+
+#[derive(Debug, Error)]
+pub enum SourceError {
+    #[error(transparent)]
+    Csv(#[from] CsvError),
+    #[error(transparent)]
+    Date(#[from] DateError),
+    #[error("Could not read {file:?}")]
+    Io { file: PathBuf, source: std::io::Error },
+    #[error("{file:?}: invalid JSON")]
+    Json { file: PathBuf, source: serde_json::Error },
+    #[error("{file:?}: timesheet query failed")]
+    Sqlite { file: PathBuf, source: rusqlite::Error },
+    #[error("{file:?}, entry {entry}: {message}")]
+    InvalidEntry { file: PathBuf, entry: u64, message: String },
+}
+
+type SourceResult<T> = Result<T, SourceError>;
+
+/// A store of time entries, e.g. a timesheet or a calendar
+pub trait TimeSource {
+    /// All entries of `company` within `period`. Sources that hold a single month may return entries outside of it,
+    /// these are flagged by the validation.
+    fn entries(&self, company: &str, period: &(NaiveDate, NaiveDate)) -> SourceResult<Vec<Record>>;
+}
+
+pub struct CsvSource<'a> {
+    pub data_dir: PathBuf,
+    pub csv_config: &'a CsvConfig,
+}
+
+impl TimeSource for CsvSource<'_> {
+    fn entries(&self, company: &str, _period: &(NaiveDate, NaiveDate)) -> SourceResult<Vec<Record>> {
+        let mut file_str = company.to_string();
+        if !file_str.ends_with(".csv") {
+            file_str.push_str(".csv");
+        }
+        let csv_path = self.data_dir.join(file_str);
+        println!("Trying to read csv from {:?}", &csv_path);
+
+        // No time logged. Companies with recurring items are still billed
+        if !csv_path.exists() {
+            println!("No csv, so no time logged");
+            return Ok(Vec::new())
+        }
+
+        Ok(csv_reader::read_csv(&csv_path, self.csv_config)?)
+    }
+}
+
+/// Event durations from an iCalendar file. The event summary becomes the description, all-day events are skipped
+pub struct IcsSource {
+    pub path: PathBuf,
+    /// Only events with this category if set
+    pub category: Option<String>,
+}
+
+/// Local time of `DTSTART`/`DTEND` values such as `20241203T090000Z`, `20241203T090000` or `20241203`
+fn parse_ics_datetime(value: &str) -> Option<NaiveDateTime> {
+    if let Some(utc) = value.strip_suffix('Z') {
+        let naive = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+        return Some(DateTime::<Local>::from(naive.and_utc()).naive_local())
+    }
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y%m%d").map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default()))
+        .ok()
+}
+
+/// ISO 8601 durations as used by iCalendar and Clockify, e.g. `PT1H30M` or `P1DT2H`
+fn parse_iso_duration(value: &str) -> Option<i64> {
+    let value = value.strip_prefix('P')?;
+    let mut minutes = 0.0;
+    let mut number = String::new();
+    let mut in_time = false;
+
+    for c in value.chars() {
+        match c {
+            'T' => in_time = true,
+            '0'..='9' | '.' => number.push(c),
+            unit => {
+                let n: f64 = number.parse().ok()?;
+                number.clear();
+                minutes += match (unit, in_time) {
+                    ('W', false) => n * 7.0 * 24.0 * 60.0,
+                    ('D', false) => n * 24.0 * 60.0,
+                    ('H', true) => n * 60.0,
+                    ('M', true) => n,
+                    ('S', true) => n / 60.0,
+                    _ => return None,
+                };
+            },
+        }
+    }
+    Some(minutes.round() as i64)
+}
+
+// Long lines are folded by starting the continuation with a space or tab
+fn unfold_ics_lines(content: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in content.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+impl TimeSource for IcsSource {
+    fn entries(&self, _company: &str, period: &(NaiveDate, NaiveDate)) -> SourceResult<Vec<Record>> {
+        println!("Reading calendar from {:?}", &self.path);
+        let content = fs::read_to_string(&self.path).map_err(|source| SourceError::Io { file: self.path.clone(), source })?;
+
+        let mut records: Vec<Record> = Vec::new();
+        let (mut start, mut end, mut duration, mut summary) = (None, None, None, String::new());
+        let (mut all_day, mut categories) = (false, Vec::new());
+        let (mut entry, mut skipped) = (0, 0);
+
+        for line in unfold_ics_lines(&content) {
+            let Some((name, value)) = line.split_once(':') else {continue};
+            // Parameters such as TZID are ignored, times are taken as local time. VALUE=DATE marks all-day events
+            let mut parameters = name.split(';');
+            let name = parameters.next().unwrap_or(name);
+
+            match name {
+                "BEGIN" if value == "VEVENT" => {
+                    (start, end, duration, summary) = (None, None, None, String::new());
+                    (all_day, categories) = (false, Vec::new());
+                    entry += 1;
+                },
+                "DTSTART" => {
+                    all_day = parameters.any(|parameter| parameter.eq_ignore_ascii_case("VALUE=DATE")) || !value.contains('T');
+                    start = parse_ics_datetime(value);
+                },
+                "DTEND" => end = parse_ics_datetime(value),
+                "DURATION" => duration = parse_iso_duration(value),
+                "SUMMARY" => summary = value.replace("\\,", ",").replace("\\;", ";"),
+                "CATEGORIES" => categories.extend(value.split(',').map(|category| category.trim().to_lowercase())),
+                "END" if value == "VEVENT" => {
+                    let invalid = |message: &str| SourceError::InvalidEntry { file: self.path.clone(), entry, message: message.to_string() };
+                    let start = start.ok_or_else(|| invalid("event without a valid DTSTART"))?;
+                    let date = start.date();
+                    let other_category = self.category.as_ref().is_some_and(|category| !categories.contains(&category.to_lowercase()));
+                    if date < period.0 || date > period.1 || other_category {
+                        continue
+                    }
+                    // All-day events are blockers or holidays rather than work
+                    if all_day {
+                        skipped += 1;
+                        continue
+                    }
+                    let minutes = match (end, duration) {
+                        (Some(end), _) => (end - start).num_minutes(),
+                        (None, Some(duration)) => duration,
+                        (None, None) => return Err(invalid("event without DTEND or DURATION")),
+                    };
+                    if minutes < 0 {
+                        return Err(invalid("event ends before it starts"))
+                    }
+                    records.push(Record { date, minutes: minutes as i32, description: summary.clone(), activity: String::new(), project: String::new(), line: entry });
+                },
+                _ => {},
+            }
+        }
+        if skipped > 0 {
+            println!("Skipped {} all-day events", skipped);
+        }
+        println!("Found {} calendar entries", records.len());
+        Ok(records)
+    }
+}
+
+/// Time entries exported from Toggl Track, Clockify or in a simple generic format
+pub struct JsonSource {
+    pub path: PathBuf,
+    pub format: JsonFormat,
+    /// Only entries of this client if set
+    pub client: Option<String>,
+}
+
+fn detect_json_format(entry: &Value) -> JsonFormat {
+    if entry.get("timeInterval").is_some() {
+        JsonFormat::Clockify
+    } else if entry.get("dur").is_some() || entry.get("start").is_some() {
+        JsonFormat::Toggl
+    } else {
+        JsonFormat::Generic
+    }
+}
+
+fn json_datetime(value: Option<&Value>) -> Option<NaiveDateTime> {
+    let datetime = DateTime::parse_from_rfc3339(value?.as_str()?).ok()?;
+    Some(datetime.with_timezone(&Local).naive_local())
+}
+
+fn parse_json_entry(entry: &Value, format: &JsonFormat) -> Result<(NaiveDate, i64), String> {
+    let description = entry.get("description").and_then(Value::as_str).unwrap_or("");
+    match format {
+        JsonFormat::Toggl => {
+            let start = json_datetime(entry.get("start")).ok_or("missing or invalid start")?;
+            // Detailed reports give `dur` in milliseconds, the time entry API `duration` in seconds.
+            // Running entries have a negative duration
+            let minutes = if let Some(ms) = entry.get("dur").and_then(Value::as_i64) {
+                if ms < 0 {
+                    return Err(format!("negative dur {} for {:?}, is the timer still running?", ms, description))
+                }
+                (ms as f64 / 60_000.0).round() as i64
+            } else if let Some(seconds) = entry.get("duration").and_then(Value::as_i64) {
+                if seconds < 0 {
+                    return Err(format!("negative duration {} for {:?}, is the timer still running?", seconds, description))
+                }
+                (seconds as f64 / 60.0).round() as i64
+            } else {
+                let end = json_datetime(entry.get("stop").or(entry.get("end"))).ok_or("missing duration")?;
+                (end - start).num_minutes()
+            };
+            Ok((start.date(), minutes))
+        },
+        JsonFormat::Clockify => {
+            let interval = entry.get("timeInterval").ok_or("missing timeInterval")?;
+            let start = json_datetime(interval.get("start")).ok_or("missing or invalid start")?;
+            let minutes = match interval.get("duration").and_then(Value::as_str).and_then(parse_iso_duration) {
+                Some(minutes) => minutes,
+                None => {
+                    let end = json_datetime(interval.get("end")).ok_or("missing duration")?;
+                    (end - start).num_minutes()
+                },
+            };
+            Ok((start.date(), minutes))
+        },
+        JsonFormat::Generic | JsonFormat::Auto => {
+            let date_str = entry.get("date").and_then(Value::as_str).ok_or("missing date")?;
+            let date = NaiveDate::parse_from_str(date_str, "%Y-%m-%d").map_err(|_| format!("invalid date {:?}", date_str))?;
+            let minutes = entry.get("minutes").and_then(Value::as_f64).ok_or(format!("missing minutes for {:?}", description))?;
+            Ok((date, minutes.round() as i64))
+        },
+    }
+}
+
+impl TimeSource for JsonSource {
+    fn entries(&self, _company: &str, period: &(NaiveDate, NaiveDate)) -> SourceResult<Vec<Record>> {
+        println!("Reading time entries from {:?}", &self.path);
+        let content = fs::read_to_string(&self.path).map_err(|source| SourceError::Io { file: self.path.clone(), source })?;
+        let json: Value = serde_json::from_str(&content).map_err(|source| SourceError::Json { file: self.path.clone(), source })?;
+
+        // Some exports wrap the entries, e.g. `{"data": [...]}`
+        let entries = match &json {
+            Value::Array(entries) => entries.as_slice(),
+            Value::Object(map) => map.get("data").or(map.get("entries")).and_then(Value::as_array).map(Vec::as_slice).unwrap_or(&[]),
+            _ => &[],
+        };
+
+        let mut records: Vec<Record> = Vec::new();
+        for (idx, entry) in entries.iter().enumerate() {
+            let line = idx as u64 + 1;
+            let format = match self.format {
+                JsonFormat::Auto => detect_json_format(entry),
+                format => format,
+            };
+            // Toggl names the client `client` or `client_name`, Clockify `clientName`
+            let client = entry.get("client").or(entry.get("client_name")).or(entry.get("clientName")).and_then(Value::as_str).unwrap_or("");
+            if self.client.as_ref().is_some_and(|wanted| !wanted.eq_ignore_ascii_case(client)) {
+                continue
+            }
+            let (date, minutes) = parse_json_entry(entry, &format)
+                .map_err(|message| SourceError::InvalidEntry { file: self.path.clone(), entry: line, message })?;
+
+            if date >= period.0 && date <= period.1 {
+                let description = entry.get("description").and_then(Value::as_str).unwrap_or("").to_string();
+                let activity = entry.get("activity").and_then(Value::as_str).unwrap_or("").to_string();
+                // Toggl and the generic format name the project `project`, Clockify `projectName`
+                let project = entry.get("project").or(entry.get("projectName")).and_then(Value::as_str).unwrap_or("").to_string();
+                records.push(Record { date, minutes: minutes as i32, description, activity, project, line });
+            }
+        }
+        println!("Found {} time entries", records.len());
+        Ok(records)
+    }
+}
+
+/// A local SQLite table with the columns `company`, `date` (YYYY-MM-DD), `minutes` and `description`
+pub struct SqliteSource {
+    pub path: PathBuf,
+    pub table: String,
+}
+
+impl TimeSource for SqliteSource {
+    fn entries(&self, company: &str, period: &(NaiveDate, NaiveDate)) -> SourceResult<Vec<Record>> {
+        println!("Reading timesheet table {:?} from {:?}", &self.table, &self.path);
+        let sqlite_error = |source| SourceError::Sqlite { file: self.path.clone(), source };
+
+        let conn = Connection::open(&self.path).map_err(sqlite_error)?;
+        let query_str = format!(
+            "SELECT rowid, date, minutes, description FROM \"{table}\" WHERE company = ?1 AND date BETWEEN ?2 AND ?3 ORDER BY date",
+            table = self.table.replace('"', ""),
+        );
+        let mut stmt = conn.prepare(&query_str).map_err(sqlite_error)?;
+
+        let rows = stmt.query_map(params![company, period.0.to_string(), period.1.to_string()], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, i32>(2)?, row.get::<_, Option<String>>(3)?))
+        }).map_err(sqlite_error)?;
+
+        let mut records: Vec<Record> = Vec::new();
+        for row in rows {
+            let (rowid, date_str, minutes, description) = row.map_err(sqlite_error)?;
+            let date = NaiveDate::parse_from_str(&date_str, "%Y-%m-%d").map_err(|_| SourceError::InvalidEntry {
+                file: self.path.clone(),
+                entry: rowid as u64,
+                message: format!("invalid date {:?}", date_str),
+            })?;
+            records.push(Record { date, minutes, description: description.unwrap_or_default(), activity: String::new(), project: String::new(), line: rowid as u64 });
+        }
+        println!("Found {} timesheet entries", records.len());
+        Ok(records)
+    }
+}
+
+fn with_month(path: &Path, billdate: &NaiveDate) -> PathBuf {
+    PathBuf::from(path.to_string_lossy().replace("{month}", &date_utils::month_dir_name(billdate)))
+}
+
+/// The time source configured for `company`, defaulting to the csv in the data directory
+pub fn source_for<'a>(config: &'a Config, company: &str, billdate: &NaiveDate) -> Box<dyn TimeSource + 'a> {
+    let source = config.companies.get(company).and_then(|company_config| company_config.source.as_ref());
+
+    match source {
+        None | Some(SourceConfig::Csv) => Box::new(CsvSource { data_dir: config.paths.data_dir(billdate), csv_config: &config.csv }),
+        Some(SourceConfig::Ics { path, category }) => Box::new(IcsSource { path: with_month(path, billdate), category: category.clone() }),
+        Some(SourceConfig::Json { path, format, client }) => Box::new(JsonSource { path: with_month(path, billdate), format: *format, client: client.clone() }),
+        Some(SourceConfig::Sqlite { path, table }) => Box::new(SqliteSource { path: with_month(path, billdate), table: table.clone() }),
+    }
+}
+
+// Csv files and sources with `{month}` in the path hold a single month
+fn is_monthly(config: &Config, company: &str) -> bool {
+    match config.companies.get(company).and_then(|company_config| company_config.source.as_ref()) {
+        None | Some(SourceConfig::Csv) => true,
+        Some(SourceConfig::Ics { path, .. } | SourceConfig::Json { path, .. } | SourceConfig::Sqlite { path, .. }) => path.to_string_lossy().contains("{month}"),
+    }
+}
+
+/// The entries of `company` within `period`, read from the source of each month it touches. Entries of another period
+/// are left out, entries outside the month of their source are kept for the validation to flag
+pub fn entries_in(config: &Config, company: &str, period: &(NaiveDate, NaiveDate)) -> SourceResult<Vec<Record>> {
+    if !is_monthly(config, company) {
+        return source_for(config, company, &period.1).entries(company, period)
+    }
+
+    let mut records = Vec::new();
+    for first in date_utils::months_in(period)? {
+        let month = date_utils::month_bounds(&first)?;
+        let in_month = (month.0.max(period.0), month.1.min(period.1));
+        let entries = source_for(config, company, &first).entries(company, &in_month)?;
+        records.extend(entries.into_iter().filter(|record| {
+            let other_period = record.date >= month.0 && record.date <= month.1 && (record.date < period.0 || record.date > period.1);
+            !other_period
+        }));
+    }
+    Ok(records)
+}
+
+/// Companies with a csv in the data directory of a month of `period` plus all companies with another configured source
+pub fn find_all_companies(config: &Config, period: &(NaiveDate, NaiveDate)) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut companies: BTreeSet<String> = BTreeSet::new();
+
+    for first in date_utils::months_in(period)? {
+        let data_dir = config.paths.data_dir(&first);
+        if data_dir.exists() {
+            companies.extend(csv_reader::find_all_companies(&data_dir)?);
+        }
+    }
+
+    for (company, company_config) in &config.companies {
+        if matches!(company_config.source, Some(SourceConfig::Ics { .. } | SourceConfig::Json { .. } | SourceConfig::Sqlite { .. })) {
+            companies.insert(company.clone());
+        }
+    }
+
+    Ok(companies.into_iter().collect())
+}
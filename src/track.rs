@@ -0,0 +1,191 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use crate::config_reader::{Config, ExtractError, SourceConfig};
+use crate::{csv_reader, date_utils, time_source};
+
+static TIMER_FILE: &str = ".timer.yaml";
+static TIMER_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+#[derive(Debug, Error)]
+pub enum TrackError {
+    #[error("A timer for {company:?} is already running since {start}. Stop it first")]
+    TimerRunning { company: String, start: String },
+    #[error("No timer is running")]
+    NoTimer,
+    #[error("Invalid timer file {0:?}")]
+    InvalidTimer(PathBuf),
+    #[error("{file:?} has no Minutes or Hours column to log to")]
+    UnsupportedCsv { file: PathBuf },
+    #[error("Invalid date format {0:?}, the first of csv.date_formats is used to log entries")]
+    InvalidDateFormat(String),
+    #[error("Minutes need to be positive, got {0}")]
+    InvalidMinutes(i32),
+    #[error(transparent)]
+    Config(#[from] ExtractError),
+    #[error("io Error")]
+    Io(#[from] std::io::Error),
+    #[error("Timer file error")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("csv write error")]
+    Csv(#[from] csv::Error),
+}
+
+type TrackResult<T> = Result<T, TrackError>;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Timer {
+    pub company: String,
+    pub description: String,
+    pub start: String,
+}
+
+fn timer_path(config: &Config) -> PathBuf {
+    config.paths.data.join(TIMER_FILE)
+}
+
+fn read_timer(path: &Path) -> TrackResult<Option<(Timer, NaiveDateTime)>> {
+    if !path.exists() {
+        return Ok(None)
+    }
+    let timer: Timer = serde_yaml::from_str(&fs::read_to_string(path)?)?;
+    let start = NaiveDateTime::parse_from_str(&timer.start, TIMER_FORMAT).map_err(|_| TrackError::InvalidTimer(path.to_path_buf()))?;
+    Ok(Some((timer, start)))
+}
+
+fn warn_if_not_csv(config: &Config, company: &str) {
+    match config.companies.get(company) {
+        None => println!("Warning: {:?} has no config entry yet", company),
+        Some(company_config) if !matches!(company_config.source, None | Some(SourceConfig::Csv)) => {
+            println!("Warning: {:?} is billed from another source, the csv entry will not be billed", company)
+        },
+        _ => {},
+    }
+}
+
+/// Appends an entry to `<data>/YYYY-MM/<company>.csv`, in the columns and delimiter the file already uses and the first
+/// configured date format
+fn append_entry(config: &Config, company: &str, date: &NaiveDate, minutes: i32, description: &str) -> TrackResult<PathBuf> {
+    let data_dir = config.paths.data_dir(date);
+    fs::create_dir_all(&data_dir)?;
+    let path = data_dir.join(format!("{}.csv", company));
+
+    let mut needs_newline = false;
+    let (delimiter, headers) = if path.exists() {
+        let content = fs::read_to_string(&path)?;
+        needs_newline = !content.is_empty() && !content.ends_with('\n');
+        let header = content.trim_start_matches('\u{feff}').lines().next().unwrap_or("").to_string();
+        let delimiter = config.csv.delimiter_byte()?.unwrap_or_else(|| csv_reader::detect_delimiter(&header));
+        let headers: Vec<String> = header.split(delimiter as char).map(|h| h.trim().trim_matches('"').to_string()).collect();
+        (delimiter, headers)
+    } else {
+        let delimiter = config.csv.delimiter_byte()?.unwrap_or(b';');
+        let mut file = fs::File::create(&path)?;
+        let headers = vec![String::from("Date"), String::from("Minutes"), String::from("Description")];
+        writeln!(file, "{}", headers.join(&(delimiter as char).to_string()))?;
+        (delimiter, headers)
+    };
+
+    let has = |name: &str| headers.iter().any(|h| h.eq_ignore_ascii_case(name));
+    if !has("Minutes") && !has("Hours") {
+        return Err(TrackError::UnsupportedCsv { file: path })
+    }
+
+    let date_format = csv_reader::write_date_format(&config.csv);
+    if StrftimeItems::new(&date_format).any(|item| item == Item::Error) {
+        return Err(TrackError::InvalidDateFormat(date_format))
+    }
+    let row: Vec<String> = headers
+        .iter()
+        .map(|header| match header.to_lowercase().as_str() {
+            "date" => date.format(&date_format).to_string(),
+            "minutes" => minutes.to_string(),
+            "hours" => format!("{:.2}", minutes as f64 / 60.0),
+            "description" => description.to_string(),
+            _ => String::new(),
+        })
+        .collect();
+
+    let mut file = OpenOptions::new().append(true).open(&path)?;
+    if needs_newline {
+        writeln!(file)?;
+    }
+    let mut wtr = csv::WriterBuilder::new().delimiter(delimiter).from_writer(file);
+    wtr.write_record(&row)?;
+    wtr.flush()?;
+
+    Ok(path)
+}
+
+/// The running timer, if any
+pub fn running(config: &Config) -> TrackResult<Option<Timer>> {
+    Ok(read_timer(&timer_path(config))?.map(|(timer, _)| timer))
+}
+
+pub fn start(config: &Config, company: &str, description: &str) -> TrackResult<()> {
+    let path = timer_path(config);
+    if let Some((timer, _)) = read_timer(&path)? {
+        return Err(TrackError::TimerRunning { company: timer.company, start: timer.start })
+    }
+    warn_if_not_csv(config, company);
+
+    let timer = Timer {
+        company: company.to_string(),
+        description: description.to_string(),
+        start: Local::now().naive_local().format(TIMER_FORMAT).to_string(),
+    };
+    fs::create_dir_all(&config.paths.data)?;
+    fs::write(&path, serde_yaml::to_string(&timer)?)?;
+
+    println!("Started timer for {:?} at {}", company, timer.start);
+    Ok(())
+}
+
+pub fn stop(config: &Config) -> TrackResult<()> {
+    let path = timer_path(config);
+    let Some((timer, start)) = read_timer(&path)? else {return Err(TrackError::NoTimer)};
+
+    let now = Local::now().naive_local();
+    let minutes = ((now - start).num_seconds() as f64 / 60.0).round().max(1.0) as i32;
+
+    let csv_path = append_entry(config, &timer.company, &start.date(), minutes, &timer.description)?;
+    fs::remove_file(&path)?;
+
+    println!("Logged {} minutes for {:?} to {:?}", minutes, timer.company, csv_path);
+    Ok(())
+}
+
+pub fn log(config: &Config, company: &str, minutes: i32, description: &str, date: &NaiveDate) -> TrackResult<()> {
+    if minutes <= 0 {
+        return Err(TrackError::InvalidMinutes(minutes))
+    }
+    warn_if_not_csv(config, company);
+
+    let csv_path = append_entry(config, company, date, minutes, description)?;
+    println!("Logged {} minutes for {:?} on {} to {:?}", minutes, company, date, csv_path);
+    Ok(())
+}
+
+pub fn list(config: &Config, date: &NaiveDate) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some((timer, start)) = read_timer(&timer_path(config))? {
+        let running = (Local::now().naive_local() - start).num_minutes();
+        println!("Running: {} \"{}\" since {} ({} minutes)\n", timer.company, timer.description, timer.start, running);
+    }
+
+    let period = date_utils::month_bounds(date)?;
+    for company in time_source::find_all_companies(config, &period)? {
+        let entries = time_source::entries_in(config, &company, &period)?;
+        let minutes_total: i32 = entries.iter().map(|entry| entry.minutes).sum();
+
+        println!("{} ({} minutes, {:.2} hours)", company, minutes_total, minutes_total as f64 / 60.0);
+        for entry in entries {
+            println!("  {}  {:>5}  {}", entry.date.format("%d.%m.%Y"), entry.minutes, entry.description);
+        }
+        println!();
+    }
+    Ok(())
+}
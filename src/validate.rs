@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use chrono::{Datelike, NaiveDate, Weekday};
+use thiserror::Error;
+use crate::csv_reader::Record;
+use crate::date_utils::{self, Calendar};
+
+// A single entry above this is most likely a typo (e.g. hours entered as minutes)
+const MAX_MINUTES_PER_ENTRY: i32 = 12 * 60;
+const MINUTES_PER_DAY: i32 = 24 * 60;
+
+#[derive(Debug, Error)]
+pub enum ValidationError {
+    #[error("{company}: {count} error(s) in the timesheet. Not billing")]
+    Errors { company: String, count: usize },
+    #[error("{company}: {count} problem(s) in the timesheet. Not billing in strict mode")]
+    Strict { company: String, count: usize },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug)]
+pub struct Issue {
+    pub severity: Severity,
+    pub line: u64,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "Warning",
+            Severity::Error => "Error",
+        };
+        write!(f, "{} (line {}): {}", severity, self.line, self.message)
+    }
+}
+
+fn issue(severity: Severity, record: &Record, message: String) -> Issue {
+    Issue { severity, line: record.line, message }
+}
+
+fn check_period(record: &Record, period: &(NaiveDate, NaiveDate)) -> Option<Issue> {
+    let (start, end) = period;
+    if record.date < *start || record.date > *end {
+        return Some(issue(Severity::Error, record, format!("{} is outside the billing period {} to {}", record.date, start, end)))
+    }
+    None
+}
+
+fn check_workday(record: &Record, calendar: Calendar) -> Option<Issue> {
+    if matches!(record.date.weekday(), Weekday::Sat | Weekday::Sun) {
+        return Some(issue(Severity::Warning, record, format!("{} is on a weekend", record.date)))
+    }
+    if date_utils::is_holiday(record.date, calendar) {
+        return Some(issue(Severity::Warning, record, format!("{} is a public holiday", record.date)))
+    }
+    None
+}
+
+fn check_minutes(record: &Record) -> Option<Issue> {
+    if record.minutes < 0 {
+        return Some(issue(Severity::Error, record, format!("negative duration of {} minutes", record.minutes)))
+    }
+    if record.minutes == 0 {
+        return Some(issue(Severity::Warning, record, String::from("entry without any minutes")))
+    }
+    if record.minutes > MINUTES_PER_DAY {
+        return Some(issue(Severity::Error, record, format!("{} minutes are more than a day", record.minutes)))
+    }
+    if record.minutes > MAX_MINUTES_PER_ENTRY {
+        return Some(issue(Severity::Warning, record, format!("unusually long entry of {} minutes", record.minutes)))
+    }
+    None
+}
+
+/// Checks all entries lie within the billing period and flags weekend work, duplicates and implausible durations
+/// Holidays are those of `calendar`
+pub fn validate_records(records: &[Record], period: &(NaiveDate, NaiveDate), calendar: Calendar) -> Vec<Issue> {
+    let mut issues: Vec<Issue> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut minutes_per_day: BTreeMap<NaiveDate, (i32, u64)> = BTreeMap::new();
+
+    for record in records {
+        issues.extend(check_period(record, period));
+        issues.extend(check_workday(record, calendar));
+        issues.extend(check_minutes(record));
+
+        if !seen.insert((record.date, record.minutes, record.description.trim().to_lowercase())) {
+            issues.push(issue(Severity::Warning, record, format!("duplicate of an earlier entry on {}", record.date)));
+        }
+
+        let day = minutes_per_day.entry(record.date).or_insert((0, record.line));
+        day.0 += record.minutes;
+    }
+
+    for (date, (minutes, line)) in minutes_per_day {
+        if minutes > MINUTES_PER_DAY {
+            issues.push(Issue { severity: Severity::Error, line, message: format!("{} minutes logged on {}", minutes, date) });
+        }
+    }
+
+    issues
+}
+
+/// Prints the issues. Errors always fail, warnings only in strict mode
+pub fn report_issues(company: &str, issues: &[Issue], strict: bool) -> Result<(), ValidationError> {
+    for issue in issues {
+        println!("{}: {}", company, issue);
+    }
+    let errors = issues.iter().filter(|issue| issue.severity == Severity::Error).count();
+    if errors > 0 {
+        return Err(ValidationError::Errors { company: company.to_string(), count: errors })
+    }
+    if strict && !issues.is_empty() {
+        return Err(ValidationError::Strict { company: company.to_string(), count: issues.len() })
+    }
+    Ok(())
+}
@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use chrono::NaiveDate;
+use thiserror::Error;
+use tiny_http::{Header, Method, Request, Response, Server};
+use crate::config_reader::{self, CompanyConfig, Config};
+use crate::billing::Biller;
+use crate::db::Db;
+use crate::http::{self, encode};
+use crate::{calculate, csv_reader, date_utils, pdf_gen, track, validate};
+
+#[derive(Debug, Error)]
+pub enum WebError {
+    #[error("Could not listen on {addr}: {message}")]
+    Bind { addr: String, message: String },
+    #[error("Missing parameter {0:?}")]
+    MissingParameter(String),
+    #[error("Invalid {name} {value:?}")]
+    InvalidParameter { name: String, value: String },
+    #[error("Unknown company {0:?}")]
+    UnknownCompany(String),
+    #[error("{0:?} has no entries in this month")]
+    NoEntries(String),
+    #[error("Not found")]
+    NotFound,
+    #[error("Refused a form sent from another site ({0})")]
+    ForeignOrigin(String),
+}
+
+type Params = HashMap<String, String>;
+type WebResult = Result<Response<std::io::Cursor<Vec<u8>>>, Box<dyn Error>>;
+
+static STYLE: &str = "
+body { font-family: sans-serif; margin: 2em auto; max-width: 60em; color: #222; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 1.5em; }
+th, td { text-align: left; padding: 0.3em 0.6em; border-bottom: 1px solid #ddd; }
+td.num, th.num { text-align: right; }
+form.inline { display: inline; }
+.error { color: #b00; }
+.warning { color: #a60; }
+nav a { margin-right: 1em; }
+";
+
+struct App {
+    biller: Biller,
+    db: Db,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn parse_params(query: &str) -> Params {
+    form_urlencoded::parse(query.as_bytes()).into_owned().collect()
+}
+
+fn param<'a>(params: &'a Params, name: &str) -> Result<&'a str, WebError> {
+    params
+        .get(name)
+        .map(|value| value.trim())
+        .filter(|value| !value.is_empty())
+        .ok_or_else(|| WebError::MissingParameter(name.to_string()))
+}
+
+fn company_param<'a>(params: &'a Params, name: &str) -> Result<&'a str, Box<dyn Error>> {
+    let company = param(params, name)?;
+    http::check_name(name, company)?;
+    Ok(company)
+}
+
+/// The project key of a bill, like company names part of file names. None if not given
+fn project_param(params: &Params) -> Result<Option<&str>, Box<dyn Error>> {
+    match params.get("project").filter(|project| !project.trim().is_empty()) {
+        Some(_) => company_param(params, "project").map(Some),
+        None => Ok(None),
+    }
+}
+
+fn billdate_param(params: &Params) -> Result<NaiveDate, Box<dyn Error>> {
+    http::billdate_of_month(params.get("month").map(String::as_str))
+}
+
+fn month_str(billdate: &NaiveDate) -> String {
+    billdate.format("%Y-%m").to_string()
+}
+
+fn html(title: &str, body: &str) -> WebResult {
+    let page = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title} - rustbill</title><style>{STYLE}</style></head>\
+        <body><nav><a href=\"/\">Overview</a><a href=\"/bills\">Bills</a></nav><h1>{title}</h1>{body}</body></html>",
+        title = escape(title),
+    );
+    let header = Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap();
+    Ok(Response::from_data(page).with_header(header))
+}
+
+fn redirect(location: &str) -> WebResult {
+    let header = Header::from_bytes("Location", location).unwrap();
+    Ok(Response::from_data(Vec::new()).with_status_code(303).with_header(header))
+}
+
+fn pdf(data: Vec<u8>, filename: &str) -> WebResult {
+    let content_type = Header::from_bytes("Content-Type", "application/pdf").unwrap();
+    let disposition = Header::from_bytes("Content-Disposition", format!("inline; filename=\"{}\"", filename)).unwrap();
+    Ok(Response::from_data(data).with_header(content_type).with_header(disposition))
+}
+
+fn error_page(status: u16, error: &dyn Error) -> Response<std::io::Cursor<Vec<u8>>> {
+    let body = format!("<p class=\"error\">{}</p><p><a href=\"javascript:history.back()\">Back</a></p>", escape(&error.to_string()));
+    let header = Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap();
+    Response::from_data(format!("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><style>{STYLE}</style></head><body><h1>Error</h1>{body}</body></html>"))
+        .with_status_code(status)
+        .with_header(header)
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request.headers().iter().find(|header| header.field.equiv(name)).map(|header| header.value.as_str())
+}
+
+/// Forms must come from the pages served here, so other sites can not make the browser generate bills or log time.
+/// The host of the Origin (or without it the Referer) header has to be the Host the request was sent to
+fn check_origin(request: &Request) -> Result<(), WebError> {
+    let source = header(request, "Origin").or_else(|| header(request, "Referer")).unwrap_or("none");
+    let source_host = source.split_once("://").map(|(_, rest)| rest.split('/').next().unwrap_or_default());
+    match (source_host, header(request, "Host")) {
+        (Some(source_host), Some(host)) if source_host.eq_ignore_ascii_case(host) => Ok(()),
+        _ => Err(WebError::ForeignOrigin(source.to_string())),
+    }
+}
+
+impl App {
+    fn known_company(&self, company: &str, billdate: &NaiveDate) -> Result<(), Box<dyn Error>> {
+        let companies = self.biller.companies(billdate)?;
+        if !companies.iter().any(|known| known == company) && !self.biller.config().companies.contains_key(company) {
+            return Err(Box::new(WebError::UnknownCompany(company.to_string())))
+        }
+        Ok(())
+    }
+
+    fn overview(&self, params: &Params) -> WebResult {
+        let billdate = billdate_param(params)?;
+        let month = month_str(&billdate);
+        let period = date_utils::month_bounds(&billdate)?;
+        let mut body = String::new();
+
+        body.push_str(&format!(
+            "<form method=\"get\" action=\"/\"><label>Month <input type=\"month\" name=\"month\" value=\"{month}\"></label> <button>Show</button></form>"
+        ));
+
+        body.push_str("<h2>Timer</h2>");
+        match track::running(self.biller.config())? {
+            Some(timer) => body.push_str(&format!(
+                "<p>Running for <b>{}</b> since {}: {}</p><form method=\"post\" action=\"/timer/stop\"><button>Stop and log</button></form>",
+                escape(&timer.company), escape(&timer.start), escape(&timer.description),
+            )),
+            None => body.push_str(
+                "<form method=\"post\" action=\"/timer/start\">\
+                <input name=\"company\" placeholder=\"Company\" list=\"companies\" required> \
+                <input name=\"description\" placeholder=\"Description\" size=\"40\"> \
+                <button>Start</button></form>",
+            ),
+        }
+
+        body.push_str(&format!(
+            "<h2>Log time</h2><form method=\"post\" action=\"/entries\">\
+            <input name=\"company\" placeholder=\"Company\" list=\"companies\" required> \
+            <input type=\"date\" name=\"date\" value=\"{}\" required> \
+            <input type=\"number\" name=\"minutes\" placeholder=\"Minutes\" min=\"1\" required> \
+            <input name=\"description\" placeholder=\"Description\" size=\"40\"> \
+            <button>Log</button></form>",
+            date_utils::parse_date_or_today("")?.min(period.1).max(period.0),
+        ));
+
+        let companies = self.biller.companies(&billdate)?;
+        body.push_str("<datalist id=\"companies\">");
+        for company in self.biller.config().companies.keys() {
+            body.push_str(&format!("<option value=\"{}\">", escape(company)));
+        }
+        body.push_str("</datalist>");
+
+        body.push_str(&format!("<h2>Companies in {}</h2>", month));
+        if companies.is_empty() {
+            body.push_str("<p>No time logged in this month yet.</p>");
+        } else {
+            body.push_str("<table><tr><th>Company</th><th class=\"num\">Entries</th><th class=\"num\">Hours</th><th class=\"num\">Net</th><th>Bill</th><th></th></tr>");
+            for company in &companies {
+                body.push_str(&self.company_row(company, &billdate));
+            }
+            body.push_str("</table>");
+            body.push_str(&format!(
+                "<form method=\"post\" action=\"/generate\"><input type=\"hidden\" name=\"month\" value=\"{month}\"><button>Generate all bills</button></form>"
+            ));
+        }
+
+        html("rustbill", &body)
+    }
+
+    fn format_net(&self, company: &str, company_config: &CompanyConfig, entries: &[csv_reader::Record], billdate: &NaiveDate) -> Result<String, Box<dyn Error>> {
+        let period = self.biller.period(company, billdate)?;
+        let contingent_used = self.db.get_usage(company, None, &period.0)?.contingent_minutes;
+        let scope = calculate::Scope { period, project: None, contingent_used };
+        let amounts = calculate::calculate_amounts(entries, company_config, &scope)?;
+        let currency = calculate::find_currency(&company_config.currency)?;
+        let locale = calculate::money_locale(currency, self.biller.config().bill_config.number_format.as_deref())?;
+        Ok(calculate::format_money(amounts.net, currency, locale))
+    }
+
+    fn company_row(&self, company: &str, billdate: &NaiveDate) -> String {
+        let month = month_str(billdate);
+        let link = format!("company={}&amp;month={}", encode(company), month);
+
+        let entries = match self.biller.entries(company, billdate) {
+            Ok(entries) => entries,
+            Err(e) => return format!("<tr><td>{}</td><td colspan=\"5\" class=\"error\">{}</td></tr>", escape(company), escape(&e.to_string())),
+        };
+        let minutes_total: i32 = entries.iter().map(|entry| entry.minutes).sum();
+        let net = match config_reader::get_company_config(self.biller.config(), company) {
+            Ok(company_config) => match self.format_net(company, company_config, &entries, billdate) {
+                Ok(net) => escape(&net),
+                Err(e) => format!("<span class=\"error\">{}</span>", escape(&e.to_string())),
+            },
+            Err(_) => String::from("<span class=\"error\">no config</span>"),
+        };
+        let billnr = match self.db.get_bills_in_month(company, billdate) {
+            Ok(bills) => bills.iter().map(|bill| escape(&bill.billnr)).collect::<Vec<_>>().join(", "),
+            Err(_) => String::new(),
+        };
+
+        format!(
+            "<tr><td><a href=\"/company?{link}\">{company}</a></td><td class=\"num\">{count}</td><td class=\"num\">{hours:.2}</td><td class=\"num\">{net}</td><td>{billnr}</td>\
+            <td><a href=\"/preview?{link}\" target=\"_blank\">Preview</a> \
+            <form class=\"inline\" method=\"post\" action=\"/generate\"><input type=\"hidden\" name=\"company\" value=\"{company}\"><input type=\"hidden\" name=\"month\" value=\"{month}\"><button>Generate</button></form></td></tr>",
+            company = escape(company),
+            count = entries.len(),
+            hours = minutes_total as f64 / 60.0,
+        )
+    }
+
+    fn company(&self, params: &Params) -> WebResult {
+        let company = company_param(params, "company")?;
+        let billdate = billdate_param(params)?;
+        let period = self.biller.period(company, &billdate)?;
+        self.known_company(company, &billdate)?;
+
+        let entries = self.biller.entries(company, &billdate)?;
+        let minutes_total = csv_reader::extract_minutes_total(&entries)?;
+        let mut body = format!(
+            "<p>{} to {}: {} minutes ({:.2} hours)</p>",
+            period.0.format("%d.%m.%Y"), period.1.format("%d.%m.%Y"), minutes_total, minutes_total as f64 / 60.0,
+        );
+
+        let issues = validate::validate_records(&entries, &period, self.biller.config().bill_config.calendar);
+        if !issues.is_empty() {
+            body.push_str("<ul>");
+            for issue in &issues {
+                let class = match issue.severity {
+                    validate::Severity::Warning => "warning",
+                    validate::Severity::Error => "error",
+                };
+                body.push_str(&format!("<li class=\"{}\">{}</li>", class, escape(&issue.to_string())));
+            }
+            body.push_str("</ul>");
+        }
+
+        body.push_str("<table><tr><th>Date</th><th class=\"num\">Minutes</th><th>Description</th></tr>");
+        for entry in &entries {
+            body.push_str(&format!(
+                "<tr><td>{}</td><td class=\"num\">{}</td><td>{}</td></tr>",
+                entry.date.format("%d.%m.%Y"), entry.minutes, escape(&entry.description),
+            ));
+        }
+        body.push_str("</table>");
+        body.push_str(&format!(
+            "<p><a href=\"/preview?company={}&amp;month={}\" target=\"_blank\">Preview the bill</a></p>",
+            encode(company), month_str(&billdate),
+        ));
+
+        html(company, &body)
+    }
+
+    fn log_entry(&self, params: &Params) -> WebResult {
+        let company = company_param(params, "company")?;
+        let date_str = param(params, "date")?;
+        let date = NaiveDate::parse_from_str(date_str, "%Y-%m-%d")
+            .map_err(|_| WebError::InvalidParameter { name: String::from("date"), value: date_str.to_string() })?;
+        let minutes_str = param(params, "minutes")?;
+        let minutes: i32 = minutes_str
+            .parse()
+            .map_err(|_| WebError::InvalidParameter { name: String::from("minutes"), value: minutes_str.to_string() })?;
+        let description = params.get("description").map(|description| description.trim()).unwrap_or("");
+
+        track::log(self.biller.config(), company, minutes, description, &date)?;
+        redirect(&format!("/?month={}", month_str(&date)))
+    }
+
+    fn start_timer(&self, params: &Params) -> WebResult {
+        let company = company_param(params, "company")?;
+        let description = params.get("description").map(|description| description.trim()).unwrap_or("");
+        track::start(self.biller.config(), company, description)?;
+        redirect("/")
+    }
+
+    fn stop_timer(&self) -> WebResult {
+        track::stop(self.biller.config())?;
+        redirect("/")
+    }
+
+    fn preview(&self, params: &Params) -> WebResult {
+        let company = company_param(params, "company")?;
+        let billdate = billdate_param(params)?;
+        let project = project_param(params)?;
+        self.known_company(company, &billdate)?;
+
+        // The first bill, unless the one of a project billed separately is asked for
+        let bill = self.biller.render(company, &billdate, false)?
+            .into_iter()
+            .find(|bill| project.is_none() || bill.project.as_deref() == project)
+            .ok_or_else(|| WebError::NoEntries(company.to_string()))?;
+        pdf(bill.pdf, &pdf_gen::pdf_filename(billdate, company, bill.project.as_deref(), &bill.period))
+    }
+
+    fn generate(&self, params: &Params) -> WebResult {
+        let billdate = billdate_param(params)?;
+        let companies = match params.get("company").filter(|company| !company.is_empty()) {
+            Some(_) => {
+                let company = company_param(params, "company")?;
+                self.known_company(company, &billdate)?;
+                vec![company.to_string()]
+            },
+            None => self.biller.companies(&billdate)?,
+        };
+
+        let mut body = String::from("<ul>");
+        for company in companies {
+            let result = self.biller.render(&company, &billdate, false)
+                .and_then(|bills| bills.into_iter().map(|bill| self.biller.save(&bill, &billdate).map(|filename| (bill.billnr, filename))).collect::<Result<Vec<_>, _>>());
+            let lines = match result {
+                Ok(saved) if saved.is_empty() => vec![format!("{}: no entries, skipped", escape(&company))],
+                Ok(saved) => saved.iter().map(|(billnr, filename)| format!("{}: bill {} saved as {}", escape(&company), escape(billnr), escape(filename))).collect(),
+                Err(e) => vec![format!("<span class=\"error\">{}: {}</span>", escape(&company), escape(&e.to_string()))],
+            };
+            for line in lines {
+                body.push_str(&format!("<li>{}</li>", line));
+            }
+        }
+        body.push_str("</ul><p><a href=\"/bills\">Show all bills</a></p>");
+
+        html(&format!("Bills for {}", month_str(&billdate)), &body)
+    }
+
+    fn bills(&self) -> WebResult {
+        let mut body = String::from("<table><tr><th>Date</th><th>Bill</th><th>Company</th><th class=\"num\">Amount</th><th></th></tr>");
+        for bill in self.db.get_all_bills()? {
+            let (Some(billdate), Some(period)) = (bill.billdate(), bill.period()) else { continue };
+            let project = (!bill.project.is_empty()).then_some(bill.project.as_str());
+            let pdf_path = self.biller.config().paths.bills_dir(&billdate).join(pdf_gen::pdf_filename(billdate, &bill.company, project, &period));
+            let link = if pdf_path.exists() {
+                format!("<a href=\"/bills/pdf?billnr={}\" target=\"_blank\">PDF</a>", encode(&bill.billnr))
+            } else {
+                String::from("file missing")
+            };
+            body.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td class=\"num\">{}</td><td>{}</td></tr>",
+                billdate.format("%d.%m.%Y"), escape(&bill.billnr), escape(&bill.company), escape(&bill.amount_str), link,
+            ));
+        }
+        body.push_str("</table>");
+        html("Bills", &body)
+    }
+
+    fn bill_pdf(&self, params: &Params) -> WebResult {
+        let bill = self.db.get_bill(param(params, "billnr")?)?.ok_or(WebError::NotFound)?;
+        let (Some(billdate), Some(period)) = (bill.billdate(), bill.period()) else {
+            return Err(Box::new(WebError::NotFound))
+        };
+        let project = (!bill.project.is_empty()).then_some(bill.project.as_str());
+        let filename = pdf_gen::pdf_filename(billdate, &bill.company, project, &period);
+        let data = fs::read(self.biller.config().paths.bills_dir(&billdate).join(&filename)).map_err(|_| WebError::NotFound)?;
+        pdf(data, &filename)
+    }
+
+    fn handle(&self, request: &mut Request) -> WebResult {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let mut params = parse_params(query);
+
+        if *request.method() == Method::Post {
+            check_origin(request)?;
+            let mut form = String::new();
+            request.as_reader().read_to_string(&mut form)?;
+            params.extend(parse_params(&form));
+        }
+
+        match (request.method(), path) {
+            (Method::Get, "/") => self.overview(&params),
+            (Method::Get, "/company") => self.company(&params),
+            (Method::Get, "/preview") => self.preview(&params),
+            (Method::Get, "/bills") => self.bills(),
+            (Method::Get, "/bills/pdf") => self.bill_pdf(&params),
+            (Method::Post, "/entries") => self.log_entry(&params),
+            (Method::Post, "/timer/start") => self.start_timer(&params),
+            (Method::Post, "/timer/stop") => self.stop_timer(),
+            (Method::Post, "/generate") => self.generate(&params),
+            _ => Err(Box::new(WebError::NotFound)),
+        }
+    }
+}
+
+/// Serves the web frontend on `addr` until the process is stopped. Requests are handled one at a time
+pub fn serve(config: Config, addr: &str) -> Result<(), Box<dyn Error>> {
+    let db = Db::open(&config.paths.db)?;
+    let biller = Biller::new(config)?;
+
+    let server = Server::http(addr).map_err(|e| WebError::Bind { addr: addr.to_string(), message: e.to_string() })?;
+    println!("Serving on http://{}", addr);
+
+    let app = App { biller, db };
+    for mut request in server.incoming_requests() {
+        let response = app.handle(&mut request).unwrap_or_else(|e| {
+            eprintln!("{} {}: {}", request.method(), request.url(), e);
+            let status = match e.downcast_ref::<WebError>() {
+                Some(WebError::NotFound) => 404,
+                Some(WebError::ForeignOrigin(_)) => 403,
+                Some(_) => 400,
+                None if e.is::<track::TrackError>() || e.is::<http::ParamError>() => 400,
+                None => 500,
+            };
+            error_page(status, e.as_ref())
+        });
+        if let Err(e) = request.respond(response) {
+            eprintln!("Could not send the response: {}", e);
+        }
+    }
+    Ok(())
+}
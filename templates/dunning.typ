@@ -0,0 +1,93 @@
+#import sys: inputs
+#import "templates/template.typ": letter-simple, configread, footerdef
+
+
+#let recipient = inputs.at("company")
+#let level = inputs.at("level")
+#let invoice_nr = inputs.at("billnr")
+#let bill_date = inputs.at("bill_date")
+#let bill_due = inputs.at("bill_due")
+#let date = inputs.at("date")
+#let due_date = inputs.at("due")
+#let qrcode = inputs.at("qrcode")
+#let items = inputs.at("items")
+#let total = inputs.at("total_str")
+
+#set text(lang: "de")
+
+#let config = configread(
+  inputs.at("config"), recipient
+)
+#let address = config.sender_street + ", " + config.sender_postcode + " " + config.sender_city
+
+#show: letter-simple.with(
+  sender: (
+    name: config.sender_name,
+    company: config.sender_company,
+    address: address,
+    extra: [
+      #link("tel:" + config.sender_phone_concise)[#config.sender_phone]\
+      #link("mailto:" + config.sender_email)[#config.sender_email]\
+      #link("https://" + config.sender_web)[#config.sender_web]\
+    ],
+  ),
+  logo: image("templates/logo.svg", width: 25mm),
+
+  footer: footerdef(config),
+
+  folding-marks: false,
+  hole-mark: false,
+
+  recipient: [
+    #config.recipient_name\
+    #config.recipient_street\
+    #config.recipient_postcode #config.recipient_city\
+  ],
+
+  reference-signs: (
+    ([Rechnungsnummer], [#invoice_nr]),
+    ([Rechnungsdatum], [#bill_date]),
+    ([Steuernummer], [#config.tax_id]),
+    ([Datum], [#date]),
+  ),
+  subject: level,
+)
+
+Sehr geehrte Damen und Herren,
+
+#inputs.at("text")
+
+#set table(
+  stroke: none,
+  fill: (x, y) =>
+    if y == 0 { config.color }
+)
+
+#table(
+  columns: (1fr, auto),
+  align: (left, right),
+  table.header([*Position*], [*Betrag*]),
+  ..items.map(item => (item.description, item.amount_str)).flatten(),
+  table.hline(stroke: 0.5pt + config.color),
+  [*Offener Betrag*], [*#total*],
+)
+
+Die Rechnung war am #bill_due fällig. Bitte überweisen Sie den offenen Betrag von *#total* bis zum *#due_date* auf das angeführte Konto.
+Sollten Sie die Zahlung inzwischen veranlasst haben, betrachten Sie dieses Schreiben bitte als gegenstandslos.
+#v(0.3cm)
+
+
+Mit freundlichen Grüßen
+
+#v(0.5cm)
+
+#config.sender_name
+
+#v(0.5cm)
+
+#if qrcode != "" [
+  #image.decode(qrcode, width: 25mm, format: "svg")
+
+  Um den offenen Betrag zu begleichen, können Sie\
+  diesen Code mit ihrer Banking-App scannen.
+]
@@ -1,5 +1,5 @@
 #import sys: inputs
-#import "templates/template.typ": letter-simple, sum_minutes, sum_amounts, format_currency, configread, footerdef, minutes_to_hours, overview_short, overview_detailed, overview_plot
+#import "templates/template.typ": letter-simple, configread, footerdef, overview_short, overview_detailed, overview_plot
 
 
 #let recipient = inputs.at("company")
@@ -7,18 +7,48 @@
 #let vat = float(inputs.at("vat"))
 #let billdate = inputs.at("date")
 #let due_date = inputs.at("due")
+#let payment_terms = inputs.at("payment_terms")
+#let service_period = inputs.at("service_period")
 #let qrcode = inputs.at("qrcode")
-#let hourly_fee = inputs.at("hourly_fee")
-#let data_dir = inputs.at("data_dir")
-#let config_name = inputs.at("config_name")
-
-#let data_with_header = csv(data_dir + "/" + recipient + ".csv", delimiter: ";")
-#let data = data_with_header.slice(1)
+#let entries = inputs.at("entries")
+#let minutes_total = inputs.at("minutes_total")
+#let hours_total = inputs.at("hours_total")
+#let currency = inputs.at("currency")
+#let amounts = (
+  hourly_fee: inputs.at("hourly_fee_str"),
+  net: inputs.at("amount_net_str"),
+  hours: inputs.at("amount_hours_str"),
+  vat: inputs.at("vat_str"),
+  total: inputs.at("amount_total_str"),
+  surcharge: inputs.at("surcharge_str"),
+  weekend_surcharge: inputs.at("weekend_surcharge"),
+  cap_deduction: inputs.at("cap_deduction_str"),
+  contingent: inputs.at("contingent_str"),
+  contingent_hours: inputs.at("contingent_hours"),
+)
+#let lines = inputs.at("lines")
+#let sections = inputs.at("sections")
+#let project = inputs.at("project")
+#let purchase_order = inputs.at("purchase_order")
+#let cost_center = inputs.at("cost_center")
+#let items = inputs.at("items")
+#let adjustments = inputs.at("adjustments")
+#let skonto = (
+  percent: inputs.at("skonto_percent"),
+  date: inputs.at("skonto_date"),
+  total: inputs.at("skonto_total_str"),
+)
+#let contingent_remaining = inputs.at("contingent_remaining")
+#let budgets = inputs.at("budgets")
+#let vat_eur = inputs.at("vat_eur_str")
+#let exchange_rate = inputs.at("exchange_rate")
+#let exchange_rate_date = inputs.at("exchange_rate_date")
+#let days_in_period = inputs.at("days_in_period")
 
 #set text(lang: "de")
 
 #let config = configread(
-  yaml("/" + config_name), recipient
+  inputs.at("config"), recipient
 )
 #let address = config.sender_street + ", " + config.sender_postcode + " " + config.sender_city
 
@@ -49,8 +79,12 @@
 
   reference-signs: (
     ([Rechnungsnummer], [#invoice_nr]),
+    ..if project != "" { (([Projekt], [#project]),) },
+    ..if purchase_order != "" { (([Bestellnummer], [#purchase_order]),) },
+    ..if cost_center != "" { (([Kostenstelle], [#cost_center]),) },
     ([Steuernummer], [#config.tax_id]),
     ([Datum], [#billdate]),
+    ([Leistungszeitraum], [#service_period]),
   ),
   subject: config.header,
 )
@@ -59,11 +93,6 @@ Sehr geehrte Damen und Herren,
 
 #config.billtext
 
-#let minutes_total = sum_minutes(data, 1)
-#let amount_total = sum_amounts(data, 1, hourly_fee)
-#let hours_total = minutes_to_hours(minutes_total)
-#let amount_with_vat = vat + amount_total
-
 #set table(
   align: center,
   stroke: none,
@@ -72,9 +101,25 @@ Sehr geehrte Damen und Herren,
 )
 
 
-#overview_short(hours_total, amount_total, vat, amount_with_vat, config)
+#overview_short(lines, items, amounts, config, sections: sections, adjustments: adjustments)
+
+#if contingent_remaining != "" [
+  Verbleibendes Stundenkontingent: #contingent_remaining Stunden
+]
+
+#for budget in budgets [
+  Budget #budget.name: #budget.used von #budget.limit verbraucht, #budget.remaining verbleibend\
+]
 
-Bitte überweisen Sie den Gesamtbetrag von *#format_currency(amount_with_vat)* innerhalb von 10 Werktagen -- also bis zum *#due_date* -- auf das angeführte Konto.
+#if vat_eur != "" [
+  #set text(size: 9pt)
+  Umsatzsteuer in Euro: #vat_eur (1 EUR = #exchange_rate #currency, EZB-Referenzkurs vom #exchange_rate_date)
+]
+
+Bitte überweisen Sie den Gesamtbetrag von *#amounts.total* #payment_terms -- also bis zum *#due_date* -- auf das angeführte Konto.
+#if skonto.percent != "" [
+  Bei Zahlung bis zum *#skonto.date* gewähren wir #skonto.percent % Skonto, Sie überweisen dann *#skonto.total*.
+]
 #v(0.3cm)
 
 
@@ -86,30 +131,35 @@ Mit freundlichen Grüßen
 
 #v(0.5cm)
 
-#image.decode(qrcode, width: 25mm, format: "svg")
+#if qrcode != "" [
+  #image.decode(qrcode, width: 25mm, format: "svg")
 
-Um die Rechnung zu begleichen, können Sie\
-diesen Code mit ihrer Banking-App scannen.
+  Um die Rechnung zu begleichen, können Sie\
+  diesen Code mit ihrer Banking-App scannen.
+]
 
-#pagebreak()
-= Stundenübersicht
+// Bills with only fixed items have no hours to show
+#if entries.len() > 0 [
+  #pagebreak()
+  = Stundenübersicht
 
-#v(1cm)
+  #v(1cm)
 
 
-#set table(
-  stroke: none,
-  fill: (x, y) =>
-    if y == 0 { config.color },
-  inset: (right: 1.5em)
-)
-#set par(justify: false)
+  #set table(
+    stroke: none,
+    fill: (x, y) =>
+      if y == 0 { config.color },
+    inset: (right: 1.5em)
+  )
+  #set par(justify: false)
 
-#overview_detailed(data, minutes_total, amount_total, 1, hourly_fee, config.color)
+  #overview_detailed(entries, minutes_total, amounts.hours, 1, config.color, cap_deduction: amounts.cap_deduction)
 
-#show figure.caption: none
+  #show figure.caption: none
 
-#v(1cm)
-= Grafische Stundenübersicht
-#v(1cm)
-#overview_plot(data, config.color)
+  #v(1cm)
+  = Grafische Stundenübersicht
+  #v(1cm)
+  #overview_plot(entries, days_in_period, config.color)
+]
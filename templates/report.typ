@@ -0,0 +1,40 @@
+#import sys: inputs
+
+#let config = inputs.at("config").bill_config
+#let color = rgb(config.color)
+
+#set page(paper: "a4", margin: (x: 2cm, y: 2.5cm), footer: context [
+  #set text(size: 9pt)
+  #config.company #h(1fr) #counter(page).display("1 / 1", both: true)
+])
+#set text(font: "Akrobat", lang: "de", hyphenate: false)
+
+#set table(
+  stroke: none,
+  fill: (x, y) =>
+    if y == 0 { color },
+  align: (x, y) => if x == 0 { left } else { right },
+)
+
+#let overview(title, rows) = table(
+  columns: (1fr, auto, auto, auto, auto, auto, auto),
+  table.header([*#title*], [*Netto*], [*USt*], [*Brutto*], [*Netto bezahlt*], [*USt bezahlt*], [*Bezahlt*]),
+  ..rows.map(row => (row.name, row.net, row.vat, row.gross, row.paid_net, row.paid_vat, row.paid)).flatten(),
+)
+
+= #inputs.at("title")
+
+#config.company, Steuernummer #config.tax_id\
+Zeitraum #inputs.at("period"), #inputs.at("bills") Rechnungen, alle Beträge in Euro
+
+Die Spalten Netto, USt und Brutto enthalten die im Zeitraum gestellten Rechnungen (Soll-Versteuerung),
+die Spalten „bezahlt“ die im Zeitraum eingegangenen Zahlungen (Ist-Versteuerung).
+
+== Nach Steuersatz
+#overview("Steuersatz", inputs.at("rates") + inputs.at("total"))
+
+== Nach Monat
+#overview("Monat", inputs.at("months"))
+
+== Nach Kunde
+#overview("Kunde", inputs.at("companies"))
@@ -248,42 +248,6 @@
   }
 }
 
-#let format_currency(number) = {
-    let precision = 2
-    assert(precision > 0)
-    let s = str(calc.round(number, digits: precision))
-    let after_dot = s.find(regex("\..*"))
-    if after_dot == none {
-      s = s + "."
-      after_dot = "."
-    }
-    for i in range(precision - after_dot.len() + 1){
-      s = s + "0"
-    }
-    (s + " €").replace(".", ",")
-  }
-
-#let sum_minutes(array, idx) = {
-  for entry in array {
-    let minutes = entry.at(idx)
-    (int(minutes),)
-  }.sum()
-}
-
-#let sum_amounts(array, idx, hourly_fee) = {
-  for entry in array {
-    let minutes = entry.at(idx)
-    let amount = float(minutes) / 60 * hourly_fee
-    (amount,)
-  }.sum()
-}
-
-#let minutes_to_hours(minutes) = {
-  let minutes_float = float(minutes)
-  let hours_float = minutes_float/60
-  hours_float
-}
-
 #let letter-simple(
   format: "DIN-5008-B",
   
@@ -454,27 +418,59 @@
   set align(left)
 }
 
-#let overview_short(hours_total, amount_total, vat, amount_with_vat, config) = table(
-  align: (left, left, right),
-  columns: (auto, auto, auto),
-  inset: 5pt,
-  table.hline(stroke: config.color + 0.5pt),
-  table.vline(stroke: config.color + 0.5pt),
-  table.header(
-    [*Pos.*], [*Bezeichnung*], [*Betrag*]
-  ),
-  table.vline(stroke: config.color + 0.5pt),
-  [1],
-  [Supportdienstleistungen / #hours_total Stunden zu #config.hourly_fee € (Netto)],
-  [#format_currency(amount_total)],
-  [2],
-  [Umsatzsteuer (19 %)],
-  [#format_currency(vat)],
-  table.hline(stroke: config.color + 0.5pt),
-  table.cell(colspan: 2)[*Gesamtbetrag*],
-  [*#format_currency(amount_with_vat)*],
-  table.hline(stroke: config.color + 0.5pt),
-)
+#let overview_short(lines, items, amounts, config, sections: (), adjustments: ()) = {
+  // Rows of two cells get a position. Discounts of a line, section headers and subtotals fill the position column themselves
+  let line_rows(line) = (
+    ([#line.name / #line.hours Stunden zu #line.hourly_fee_str (Netto)], [#line.amount_str]),
+    ..line.adjustments.map(adjustment => ([], [#adjustment.description], [#adjustment.amount_str])),
+  )
+  let rows = lines.map(line_rows).sum(default: ())
+  for section in sections {
+    rows.push((table.cell(colspan: 3)[*#section.name*#if section.reference != "" [ (#section.reference)]],))
+    rows += section.lines.map(line_rows).sum(default: ())
+    rows.push(([], [Zwischensumme #section.name], [#section.subtotal_str]))
+  }
+  rows += items.map(item => ([#item.description], [#item.amount_str]))
+  if amounts.surcharge != "" {
+    rows.push(([Wochenendzuschlag (#amounts.weekend_surcharge %)], [#amounts.surcharge]))
+  }
+  if amounts.cap_deduction != "" {
+    rows.push(([Begrenzung auf den Tageshöchstsatz], [#amounts.cap_deduction]))
+  }
+  if amounts.contingent != "" {
+    rows.push(([Abzug aus dem Stundenkontingent (#amounts.contingent_hours Stunden)], [#amounts.contingent]))
+  }
+  rows += adjustments.map(adjustment => ([#adjustment.description], [#adjustment.amount_str]))
+  rows.push(([Umsatzsteuer (19 %)], [#amounts.vat]))
+
+  table(
+    align: (left, left, right),
+    columns: (auto, auto, auto),
+    inset: 5pt,
+    table.hline(stroke: config.color + 0.5pt),
+    table.vline(stroke: config.color + 0.5pt),
+    table.header(
+      [*Pos.*], [*Bezeichnung*], [*Betrag*]
+    ),
+    table.vline(stroke: config.color + 0.5pt),
+    ..{
+      let pos = 0
+      let cells = ()
+      for row in rows {
+        if row.len() == 2 {
+          pos += 1
+          cells.push([#pos])
+        }
+        cells += row
+      }
+      cells
+    },
+    table.hline(stroke: config.color + 0.5pt),
+    table.cell(colspan: 2)[*Gesamtbetrag*],
+    [*#amounts.total*],
+    table.hline(stroke: config.color + 0.5pt),
+  )
+}
 
 #let format_unsplit_str(input) = {
   // input
@@ -486,7 +482,7 @@
 }
 
 
-#let overview_detailed(data, minutes_total, amount_total, pos, hourly_fee, custom_color) = table(
+#let overview_detailed(entries, minutes_total, amount_total, pos, custom_color, cap_deduction: "") = table(
   align: (left, left, left, right, right),
   columns: (auto,auto,auto, auto, auto),
   table.hline(stroke: custom_color + 0.5pt),
@@ -495,29 +491,20 @@
     [*Pos.*], [*Datum*],[*Bezeichnung*], [*Minuten*], [*Betrag*]
   ),
   // let pos = 0,
-  ..for (date, minutes, description) in data {
-    // let date = datetime(day: int(day), month: int(month), year: int(year)).display("[day].[month].[year]")
-    let amount = (float(minutes)/60*hourly_fee)
-    let amount_formatted = format_currency((amount))
-    (str(int(pos)), date, description, minutes, amount_formatted)
+  ..for entry in entries {
+    (str(int(pos)), entry.date, entry.description, str(entry.minutes), entry.amount_str)
     pos = pos + 1
   }, 
+  ..if cap_deduction != "" {
+    (table.cell(colspan: 4)[Begrenzung auf den Tageshöchstsatz], cap_deduction)
+  },
   table.vline(stroke: custom_color + 0.5pt),
   table.hline(stroke: custom_color + 0.5pt),
   table.cell(colspan: 3)[*Summe*],
-  [*#minutes_total*],[*#format_currency(amount_total)*],
+  [*#minutes_total*],[*#amount_total*],
   table.hline(stroke: custom_color + 0.5pt),
 )
 
-#let get_days(data) = {
-  data.map(entry => {
-    let date = entry.at(0)
-    let minutes = entry.at(1)
-    let day = str(int(date.split(".").at(0)))
-    day
-  })
-}
-
 #let calculate_step(max_minutes) = {
   let step = max_minutes / 4
   if step > 60 {
@@ -529,62 +516,20 @@
   }
 }
 
-#let is_leap_year(year) = {
-  if calc.rem(year, 4) == 0 and calc.rem(year, 100) != 0 or calc.rem(year,400) == 0 {
-    true
-  } else {
-    false
-  }
-}
-
-#let get_nr_days(data) = {
-  let month = int(data.at(0).at(0).split(".").at(1))
-  let year = int(data.at(0).at(0).split(".").at(2))
-  let feb = if is_leap_year(year) {29} else {28}
-  let days_in_month = (31, feb, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31)
-  days_in_month.at(month - 1)
-}
+#let sum_minutes_by_day(entries, nr_days) = {
+  let day_sums = range(1, nr_days + 1).map(day => (str(day), 0)).to-dict()
 
-#let get_month_dict(data) = {
-  let nr_days = get_nr_days(data)
-  let month_days = range(1, nr_days + 1)
-  let month_array = month_days.map(day => 
-  {
-    (str(day), 0)
-  })
-  let month_dict = month_array.to-dict()
-  month_dict
-}
-
-#let sum_minutes_by_day(data) = {
-  let day_sums = get_month_dict(data)
-  let minutes = data.map(entry => {
-    let minutes = entry.at(1)
-    int(minutes)
-  })
-  let days = get_days(data)
-  let to_sum = days.zip(minutes)
-
-  let test = day_sums.at("4")
-
-  
-  for (day, minutes) in to_sum {
-    let minutes_so_far = day_sums.at(day)
-    day_sums.insert(day, minutes_so_far + minutes)
+  for entry in entries {
+    let day = str(entry.period_day)
+    day_sums.insert(day, day_sums.at(day, default: 0) + entry.minutes)
   }
   day_sums.pairs().map(item => (item.at(1), int(item.at(0))))
 }
 
-#let overview_plot(data, color) = {
-  let nr_days = get_nr_days(data)
-  let days = get_days(data)
-  let minutes = data.map(entry => {
-    let minutes = entry.at(1)
-    int(minutes)
-  })
-  let to_sum = days.zip(minutes)
-  let to_plot = sum_minutes_by_day(data)
-  
+#let overview_plot(entries, nr_days, color) = {
+  let minutes = entries.map(entry => entry.minutes)
+  let to_plot = sum_minutes_by_day(entries, nr_days)
+
   let step = calculate_step(calc.max(..minutes))
   let ymax = calc.max(..to_plot.map(item => item.at(0)))+1
  
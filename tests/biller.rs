@@ -0,0 +1,73 @@
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+use chrono::NaiveDate;
+use rustbill::billing::{BillResult, Collect, Store};
+use rustbill::config_reader::Config;
+use rustbill::csv_reader::Record;
+use rustbill::{Bill, Biller};
+
+mod common;
+
+/// Hands out fixed entries for one company
+struct Entries(Vec<Record>);
+
+impl Collect for Entries {
+    fn companies(&self, _config: &Config, _period: &(NaiveDate, NaiveDate)) -> BillResult<Vec<String>> {
+        Ok(vec![String::from("SampleCompany")])
+    }
+
+    fn entries(&self, _config: &Config, _company: &str, period: &(NaiveDate, NaiveDate)) -> BillResult<Vec<Record>> {
+        Ok(self.0.iter().filter(|record| record.date >= period.0 && record.date <= period.1).cloned().collect())
+    }
+}
+
+/// Keeps the saved bills in memory instead of the database and the bills directory
+#[derive(Clone, Default)]
+struct Memory {
+    saved: Rc<RefCell<Vec<(String, f64)>>>,
+}
+
+impl Store for Memory {
+    fn billnr(&self, _config: &Config, _company: &str, _project: Option<&str>, billdate: &NaiveDate, _period: &(NaiveDate, NaiveDate), taken: &[i32]) -> BillResult<(String, i32)> {
+        let billnr_int = (self.saved.borrow().len() + taken.len() + 1) as i32;
+        Ok((format!("{}{:02}", billdate.format("%Y-%m"), billnr_int), billnr_int))
+    }
+
+    fn save(&self, _config: &Config, bill: &Bill, _billdate: &NaiveDate) -> BillResult<String> {
+        self.saved.borrow_mut().push((bill.billnr.clone(), bill.amounts.total));
+        Ok(format!("{}.pdf", bill.billnr))
+    }
+}
+
+fn record(date: NaiveDate, minutes: i32) -> Record {
+    Record { date, minutes, description: String::from("Support"), activity: String::new(), project: String::new(), line: 0 }
+}
+
+#[test]
+fn bills_a_month_through_the_stages() {
+    let dir = common::project_dir("biller");
+    let date = |day| NaiveDate::from_ymd_opt(2024, 11, day).unwrap();
+    let store = Memory::default();
+    let biller = Biller::from_config_file(&dir.join("config.yaml").to_string_lossy(), &[])
+        .unwrap()
+        .with_collect(Entries(vec![record(date(4), 90), record(date(5), 60), record(date(6), 0)]))
+        .with_store(store.clone());
+
+    let billdate = date(30);
+    assert_eq!(biller.companies(&billdate).unwrap(), ["SampleCompany"]);
+    let bills = biller.bill("SampleCompany", &billdate, false).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(bills.len(), 1);
+    let (bill, filename) = &bills[0];
+    assert_eq!(bill.billnr, "2024-1101");
+    assert_eq!(filename, "2024-1101.pdf");
+    assert_eq!(bill.period, (date(1), billdate));
+    assert_eq!(bill.amounts.net, 250.0);
+    assert_eq!(bill.amounts.vat, 47.5);
+    assert_eq!(bill.amounts.total, 297.5);
+    assert!(bill.pdf.starts_with(b"%PDF"));
+    assert!(bill.pdf.windows(b"factur-x.xml".len()).any(|window| window == b"factur-x.xml"));
+    assert_eq!(*store.saved.borrow(), [(String::from("2024-1101"), 297.5)]);
+}
@@ -0,0 +1,23 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()));
+        } else {
+            fs::copy(entry.path(), to.join(entry.file_name())).unwrap();
+        }
+    }
+}
+
+// The sample config next to a copy of the templates, which are resolved against the config directory
+pub fn project_dir(name: &str) -> PathBuf {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let dir = std::env::temp_dir().join(format!("rustbill-{}-{}", name, std::process::id()));
+    copy_dir(&manifest.join("templates"), &dir.join("templates"));
+    fs::copy(manifest.join("sample/config.yaml"), dir.join("config.yaml")).unwrap();
+    dir
+}
@@ -0,0 +1,34 @@
+use std::fs;
+use chrono::NaiveDate;
+use rustbill::config_reader;
+use rustbill::db::Db;
+use rustbill::dunning;
+
+mod common;
+
+#[test]
+fn interest_of_a_zinsrechnung_is_not_dunned_again() {
+    let dir = common::project_dir("dunning");
+    let config = config_reader::read_config(&dir.join("config.yaml").to_string_lossy(), &[String::from("dunning.interest_rate=10")]).unwrap();
+    let date = |year, month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+
+    let db = Db::open(&config.paths.db).unwrap();
+    let period = (date(2024, 11, 1), date(2024, 11, 30));
+    db.add_to_db("SampleCompany", "", &period.1, &period, &date(2024, 12, 14), "2024-1101", &1000.0, "1.000,00 €", &1, "EUR", &1.0, None).unwrap();
+
+    // The Zahlungserinnerung charges no interest, the Zinsrechnung 30 days and the 1. Mahnung only the 30 days after it
+    dunning::dun(&config, &date(2024, 12, 21), None, false).unwrap();
+    dunning::interest_invoice(&config, "2024-1101", &date(2025, 1, 13), false).unwrap();
+    dunning::dun(&config, &date(2025, 2, 12), None, false).unwrap();
+
+    let bill = db.get_bill("2024-1101").unwrap().unwrap();
+    let dunnings = db.get_dunnings("2024-1101").unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(bill.interest, 8.22);
+    assert_eq!(bill.interest_until, "2025-01-13");
+    assert_eq!(bill.flat_fee, 40.0);
+    assert_eq!(dunnings.len(), 2);
+    assert_eq!(dunnings[0].interest, 0.0);
+    assert_eq!(dunnings[1].interest, 8.22);
+}
@@ -0,0 +1,65 @@
+use std::path::Path;
+use chrono::NaiveDate;
+use rustbill::calculate::{ActivityLine, AppliedAdjustment, Amounts};
+use rustbill::config_reader::{self, Config, ProjectConfig};
+use rustbill::ebill;
+
+fn sample_config() -> Config {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("sample/config.yaml");
+    config_reader::read_config(&path.to_string_lossy(), &[]).unwrap()
+}
+
+fn adjustment(reason: &str) -> AppliedAdjustment {
+    AppliedAdjustment { reason: reason.to_string(), charge: false, percent: Some(10.0), base: 100.0, amount: 10.0 }
+}
+
+fn amounts() -> Amounts {
+    let line = ActivityLine {
+        project: String::new(),
+        activity: String::from("Design <UI> & UX"),
+        minutes: 60,
+        hours: 1.0,
+        hourly_fee: 100.0,
+        base: 100.0,
+        adjustments: Vec::new(),
+        amount: 100.0,
+    };
+    Amounts {
+        net: 90.0,
+        vat: 17.1,
+        total: 107.1,
+        hourly_fee: 100.0,
+        hours_total: 1.0,
+        entries: Vec::new(),
+        lines: vec![line],
+        surcharge: 0.0,
+        cap_deduction: 0.0,
+        items: Vec::new(),
+        contingent_minutes: 0,
+        contingent_deduction: 0.0,
+        contingent_remaining: 0,
+        adjustments: vec![adjustment("Rabatt Müller & Söhne <Stammkunde>")],
+        skonto: None,
+    }
+}
+
+#[test]
+fn escapes_free_text_in_the_xml() {
+    let mut config = sample_config();
+    let project = ProjectConfig { name: None, purchase_order: Some(String::from("PO 4711 & <B>")), cost_center: Some(String::from("R&D")) };
+    config.companies.get_mut("SampleCompany").unwrap().projects.insert(String::from("Web"), project);
+    let company = &config.companies["SampleCompany"];
+    let date = |day| NaiveDate::from_ymd_opt(2024, 11, day).unwrap();
+
+    let xml = ebill::create_ebill_xml(
+        "2024-1101", &amounts(), date(30), date(30), &(date(1), date(30)), &config.bill_config, company, Some("Web"),
+        "innerhalb von 14 Tagen", &config.bank_config, "EUR", 1.0,
+    ).unwrap();
+
+    let document = roxmltree::Document::parse(&xml).unwrap();
+    let text = |name: &str| document.descendants().filter(|node| node.has_tag_name(name)).filter_map(|node| node.text()).collect::<Vec<_>>();
+    assert!(text("AllowanceChargeReason").contains(&"Rabatt Müller & Söhne <Stammkunde>"));
+    assert!(text("Name").contains(&"Design <UI> & UX"));
+    assert!(text("ID").contains(&"PO 4711 & <B>"));
+    assert_eq!(text("AccountingCost"), ["R&D"]);
+}
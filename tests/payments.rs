@@ -0,0 +1,17 @@
+use std::path::Path;
+use chrono::NaiveDate;
+use rustbill::payments;
+
+#[test]
+fn reads_credits_and_reversed_debits_from_mt940() {
+    let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/statement.sta");
+    let transactions = payments::read_statement(&file).unwrap();
+
+    let credits: Vec<_> = transactions.iter().map(|transaction| (transaction.id.as_str(), transaction.date, transaction.amount)).collect();
+    assert_eq!(credits, [
+        ("B1", NaiveDate::from_ymd_opt(2025, 1, 8).unwrap(), 108.25),
+        ("B3", NaiveDate::from_ymd_opt(2025, 1, 10).unwrap(), 35.7),
+    ]);
+    assert_eq!(transactions[0].reference, "RE 2024-1215 vom 10.12.2024");
+    assert_eq!(transactions[1].payer, "Sample Company");
+}